@@ -1,19 +1,26 @@
 use std::collections::HashMap;
 use std::sync::Arc;
+use std::time::Duration;
 use async_trait::async_trait;
+use http::HeaderMap;
+use bytes::Bytes;
+use pingora_http::{RequestHeader, ResponseHeader};
 
 use pingora_proxy::ProxyHttp;
 use pingora::{
     upstreams::peer::{HttpPeer},
     Error,
-    Custom,
     Result
 };
 use pingora_load_balancing::LoadBalancer;
 use pingora_load_balancing::prelude::RoundRobin;
 use pingora_proxy::Session;
-use crate::service::proxy::{ChainProxyConfig, SpecialMethodConfig};
-use crate::app::proxy_base::ProxyBase;
+use crate::service::proxy::{ChainProxyConfig, ProxyOptions, SpecialMethodConfig};
+use crate::error::ProxyError;
+use crate::app::proxy_base::{ProxyBase, ProxyCtx};
+use crate::app::selection::{LatencyEwma, StickySelection, WarmupRamp};
+use crate::app::sampling::ErrorSampler;
+use crate::app::success_ratio::SuccessRatioWindow;
 
 pub struct CommonProxyApp {
     chain_name: String,
@@ -29,6 +36,24 @@ pub struct CommonProxyApp {
 
     // special method configs
     special_method_configs: Vec<SpecialMethodConfig>,
+
+    // request path options
+    proxy_options: ProxyOptions,
+
+    // last selected node, reused within the sticky window
+    sticky_selection: StickySelection,
+
+    // traffic ramp of nodes becoming eligible again
+    warmup_ramp: WarmupRamp,
+
+    // upstream error responses logged with their body
+    error_sampler: ErrorSampler,
+
+    // recent proxy outcomes of every node
+    success_ratio_window: SuccessRatioWindow,
+
+    // recent latency of every node, for the adaptive priority
+    latency_ewma: LatencyEwma,
 }
 
 impl CommonProxyApp {
@@ -38,6 +63,7 @@ impl CommonProxyApp {
         host_configs: Vec<ChainProxyConfig>,
         special_method_configs: Vec<SpecialMethodConfig>,
         clusters: HashMap<String, Arc<LoadBalancer<RoundRobin>>>,
+        proxy_options: ProxyOptions,
     ) -> Self {
         CommonProxyApp {
             chain_name,
@@ -45,6 +71,12 @@ impl CommonProxyApp {
             clusters,
             host_configs,
             special_method_configs,
+            sticky_selection: StickySelection::new(proxy_options.sticky_window),
+            warmup_ramp: WarmupRamp::new(proxy_options.warmup),
+            error_sampler: ErrorSampler::new(proxy_options.error_sample_rate),
+            success_ratio_window: SuccessRatioWindow::new(proxy_options.success_ratio_window),
+            latency_ewma: LatencyEwma::new(),
+            proxy_options,
         }
     }
 }
@@ -72,7 +104,7 @@ impl ProxyBase for CommonProxyApp {
 
         if clusters_by_priority.is_empty() {
             log::error!("No eligible cluster found");
-            return Error::e_explain(Custom("No eligible cluster found"), "proxy error");
+            return ProxyError::NoEligibleCluster.explain("proxy error");
         }
 
         Ok(clusters_by_priority)
@@ -85,17 +117,121 @@ impl ProxyBase for CommonProxyApp {
     fn get_special_method_configs(&self) -> &Vec<SpecialMethodConfig> {
         &self.special_method_configs
     }
+
+    fn get_host_configs(&self) -> &Vec<ChainProxyConfig> {
+        &self.host_configs
+    }
+
+    fn get_proxy_options(&self) -> &ProxyOptions {
+        &self.proxy_options
+    }
+
+    fn get_sticky_selection(&self) -> &StickySelection {
+        &self.sticky_selection
+    }
+
+    fn get_warmup_ramp(&self) -> &WarmupRamp {
+        &self.warmup_ramp
+    }
+
+    fn get_error_sampler(&self) -> &ErrorSampler {
+        &self.error_sampler
+    }
+
+    fn get_success_ratio_window(&self) -> &SuccessRatioWindow {
+        &self.success_ratio_window
+    }
+
+    fn get_latency_ewma(&self) -> &LatencyEwma {
+        &self.latency_ewma
+    }
 }
 
 #[async_trait]
 impl ProxyHttp for CommonProxyApp {
-    type CTX = ();
-    fn new_ctx(&self) {}
+    type CTX = ProxyCtx;
+    fn new_ctx(&self) -> Self::CTX {
+        ProxyCtx::default()
+    }
+
+    async fn request_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<bool> {
+        ProxyBase::request_filter(self, session, ctx).await
+    }
 
-    async fn upstream_peer(&self, session: &mut Session, ctx: &mut ()) -> Result<Box<HttpPeer>> {
+    async fn upstream_peer(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<Box<HttpPeer>> {
         ProxyBase::upstream_peer(self, session, ctx).await
     }
 
+    async fn upstream_request_filter(
+        &self,
+        session: &mut Session,
+        upstream_request: &mut RequestHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        ProxyBase::upstream_request_filter(self, session, upstream_request, ctx).await
+    }
+
+    async fn request_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        ProxyBase::request_body_filter(self, session, body, end_of_stream, ctx).await
+    }
+
+    async fn response_filter(
+        &self,
+        session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        ProxyBase::response_filter(self, session, upstream_response, ctx).await
+    }
+
+    fn upstream_response_filter(
+        &self,
+        session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) {
+        ProxyBase::upstream_response_filter(self, session, upstream_response, ctx)
+    }
+
+    fn upstream_response_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) {
+        ProxyBase::upstream_response_body_filter(self, session, body, end_of_stream, ctx)
+    }
+
+    fn response_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<Option<Duration>> {
+        ProxyBase::response_body_filter(self, session, body, end_of_stream, ctx)
+    }
+
+    fn upstream_response_trailer_filter(
+        &self,
+        session: &mut Session,
+        upstream_trailers: &mut HeaderMap,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        ProxyBase::upstream_response_trailer_filter(self, session, upstream_trailers, ctx)
+    }
+
+    async fn fail_to_proxy(&self, session: &mut Session, e: &Error, ctx: &mut Self::CTX) -> u16 {
+        ProxyBase::fail_to_proxy(self, session, e, ctx).await
+    }
+
     async fn logging(&self, session: &mut Session, e: Option<&Error>, ctx: &mut Self::CTX) {
         ProxyBase::logging(self, session, e, ctx).await
     }
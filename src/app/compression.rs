@@ -0,0 +1,132 @@
+use std::io::Write;
+
+use bytes::Bytes;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use http::header::{
+    ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, TRANSFER_ENCODING, VARY,
+};
+use http::HeaderValue;
+use pingora_http::ResponseHeader;
+
+// a balance of ratio and cpu for the json responses of the nodes
+const GZIP_LEVEL: u32 = 6;
+
+// streamed responses are forwarded as they arrive, holding them back to compress would stall the stream
+const UNCOMPRESSED_CONTENT_TYPES: [&str; 2] = ["application/grpc", "text/event-stream"];
+
+/// Whether the client accepts a gzip response, `gzip` or `*` listed without a zero quality
+pub(crate) fn accepts_gzip(accept_encoding: Option<&HeaderValue>) -> bool {
+    let accept_encoding = match accept_encoding.and_then(|value| value.to_str().ok()) {
+        Some(accept_encoding) => accept_encoding,
+        None => return false,
+    };
+    accept_encoding.split(',').any(|coding| {
+        let mut params = coding.split(';').map(str::trim);
+        let name = params.next().unwrap_or_default();
+        let zero_quality = params
+            .filter_map(|param| param.strip_prefix("q=").or_else(|| param.strip_prefix("Q=")))
+            .any(|quality| quality.parse::<f32>().is_ok_and(|quality| quality == 0.0));
+        (name.eq_ignore_ascii_case("gzip") || name == "*") && !zero_quality
+    })
+}
+
+/// Whether the upstream response is sent gzip compressed to a client accepting it,
+/// only responses with a body the upstream sent uncompressed
+pub(crate) fn compresses_response(resp: &ResponseHeader, method: &str) -> bool {
+    let status = resp.status.as_u16();
+    if method.eq_ignore_ascii_case("HEAD") || resp.status.is_informational() || status == 204 || status == 304 {
+        return false;
+    }
+    let uncompressed = resp
+        .headers
+        .get(CONTENT_ENCODING)
+        .is_none_or(|encoding| encoding.as_bytes().eq_ignore_ascii_case(b"identity"));
+    let streamed = resp
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| {
+            UNCOMPRESSED_CONTENT_TYPES.iter().any(|prefix| content_type.to_ascii_lowercase().starts_with(prefix))
+        });
+    uncompressed && !streamed
+}
+
+/// Gzip encoder of a response body, compressing the chunks as they are forwarded
+#[derive(Debug)]
+pub(crate) struct GzipStream {
+    encoder: GzEncoder<Vec<u8>>,
+}
+
+impl GzipStream {
+    /// Start compressing the response, its length is no longer known ahead so it is sent chunked
+    pub(crate) fn start(resp: &mut ResponseHeader) -> pingora::Result<Self> {
+        resp.remove_header(&CONTENT_LENGTH);
+        // ranges of the uncompressed body no longer apply
+        resp.remove_header(&ACCEPT_RANGES);
+        resp.insert_header(CONTENT_ENCODING, "gzip")?;
+        resp.insert_header(TRANSFER_ENCODING, "chunked")?;
+        resp.append_header(VARY, ACCEPT_ENCODING.as_str())?;
+        Ok(GzipStream { encoder: GzEncoder::new(Vec::new(), Compression::new(GZIP_LEVEL)) })
+    }
+
+    /// The compressed output of the chunk, the gzip trailer is written at the end of the stream
+    pub(crate) fn compress(&mut self, chunk: &[u8], end_of_stream: bool) -> Bytes {
+        // writing to a vec never fails
+        self.encoder.write_all(chunk).unwrap();
+        if end_of_stream {
+            self.encoder.try_finish().unwrap();
+        }
+        Bytes::from(std::mem::take(self.encoder.get_mut()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[test]
+    fn test_accepts_gzip() {
+        assert!(accepts_gzip(Some(&HeaderValue::from_static("gzip"))));
+        assert!(accepts_gzip(Some(&HeaderValue::from_static("br, GZIP;q=0.5"))));
+        assert!(accepts_gzip(Some(&HeaderValue::from_static("*"))));
+        assert!(!accepts_gzip(Some(&HeaderValue::from_static("br, gzip;q=0"))));
+        assert!(!accepts_gzip(Some(&HeaderValue::from_static("identity"))));
+        assert!(!accepts_gzip(None));
+    }
+
+    #[test]
+    fn test_compress_uncompressed_response() {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("content-type", "application/json").unwrap();
+        resp.insert_header("content-length", "1000").unwrap();
+        assert!(compresses_response(&resp, "POST"));
+        assert!(!compresses_response(&resp, "HEAD"));
+
+        let mut stream = GzipStream::start(&mut resp).unwrap();
+        assert_eq!(resp.headers.get("content-encoding").unwrap(), "gzip");
+        assert_eq!(resp.headers.get("vary").unwrap(), "accept-encoding");
+        assert!(resp.headers.get("content-length").is_none());
+
+        // the chunks decompress back to the upstream body
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":"0x1"}"#.repeat(25);
+        let (first, second) = body.split_at(400);
+        let mut compressed = stream.compress(first, false).to_vec();
+        compressed.extend_from_slice(&stream.compress(second, true));
+        assert!(compressed.len() < body.len());
+        let mut decompressed = Vec::new();
+        GzDecoder::new(compressed.as_slice()).read_to_end(&mut decompressed).unwrap();
+        assert_eq!(decompressed, body);
+
+        // already compressed and streamed responses are forwarded as is
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("content-encoding", "br").unwrap();
+        assert!(!compresses_response(&resp, "POST"));
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("content-type", "application/grpc+proto").unwrap();
+        assert!(!compresses_response(&resp, "POST"));
+        assert!(!compresses_response(&ResponseHeader::build(304, None).unwrap(), "GET"));
+    }
+}
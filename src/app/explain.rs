@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use rand::thread_rng;
+use serde::{Deserialize, Serialize};
+
+use crate::app::node_proxy_app::eligible_clusters;
+use crate::app::proxy_base::{route_by_path, special_method_config};
+use crate::app::selection::{pick_candidate, Candidates};
+use crate::config::{redact_url, ChainState, SelectionMode};
+use crate::service::proxy::{ChainProxyConfig, ProxyOptions, SpecialMethodConfig};
+
+/// A sample request to explain the routing of, the jsonrpc method as sent in `X-Proxy-Jsonrpc-Method`
+#[derive(Debug, Deserialize)]
+pub struct ExplainRequest {
+    #[serde(default)]
+    method: Option<String>,
+    #[serde(default = "default_path")]
+    path: String,
+}
+
+fn default_path() -> String {
+    "/".to_string()
+}
+
+impl Default for ExplainRequest {
+    fn default() -> Self {
+        ExplainRequest { method: None, path: default_path() }
+    }
+}
+
+/// Why a node is a candidate of the request or not
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum NodeStatus {
+    // among the nodes the selected one is drawn from
+    Candidate,
+    // eligible, but a higher priority tier has candidates
+    LowerPriority,
+    // not routed to by the path routes of the request path
+    PathFiltered,
+    // behind the highest block by more than its block gap
+    GapFiltered,
+    // no block number reported yet
+    NoBlockNumber,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NodeExplanation {
+    node: String,
+    priority: i32,
+    block_number: Option<u64>,
+    status: NodeStatus,
+}
+
+/// The node a request would be proxied to and why
+#[derive(Debug, Serialize)]
+pub struct RouteExplanation {
+    chain: String,
+    method: Option<String>,
+    path: String,
+    special_method: bool,
+    max_block_number: Option<u64>,
+    nodes: Vec<NodeExplanation>,
+    selected: Option<String>,
+    reason: Option<&'static str>,
+    // why no node would be selected
+    error: Option<String>,
+}
+
+/// Route explainer
+///
+/// Holds the routing inputs of a chain proxy to tell which node a request would be proxied to without proxying it.
+/// The sticky window, warm-up ramp, adaptive latency weights, health scores and region affinity are not applied, a
+/// random pick is drawn again.
+#[derive(Clone)]
+pub struct RouteExplainer {
+    chain_name: String,
+    host_configs: Vec<ChainProxyConfig>,
+    special_method_configs: Vec<SpecialMethodConfig>,
+    chain_state: Arc<Mutex<ChainState>>,
+    proxy_options: ProxyOptions,
+}
+
+impl RouteExplainer {
+    pub fn new(
+        chain_name: &str,
+        host_configs: Vec<ChainProxyConfig>,
+        special_method_configs: Vec<SpecialMethodConfig>,
+        chain_state: Arc<Mutex<ChainState>>,
+        proxy_options: ProxyOptions,
+    ) -> Self {
+        RouteExplainer {
+            chain_name: chain_name.to_string(),
+            host_configs,
+            special_method_configs,
+            chain_state,
+            proxy_options,
+        }
+    }
+
+    pub fn chain_name(&self) -> &str {
+        &self.chain_name
+    }
+
+    /// Explain the routing of the request with the current block numbers, the same steps as the upstream selection
+    pub fn explain(&self, request: &ExplainRequest) -> RouteExplanation {
+        let method = request.method.as_deref();
+        let special_method = method.and_then(|method| special_method_config(&self.special_method_configs, method));
+        let block_numbers = self.chain_state.lock().unwrap().get_block_numbers().clone();
+
+        let mut explanation = RouteExplanation {
+            chain: self.chain_name.clone(),
+            method: request.method.clone(),
+            path: request.path.clone(),
+            special_method: special_method.is_some(),
+            max_block_number: block_numbers.values().max().copied().filter(|max| *max > 0),
+            nodes: Vec::new(),
+            selected: None,
+            reason: None,
+            error: None,
+        };
+
+        let clusters_by_priority =
+            eligible_clusters(&self.host_configs, &self.special_method_configs, &self.chain_state, method);
+        let eligible = clusters_by_priority.as_ref().ok().map(uris_of).unwrap_or_default();
+        let clusters_by_priority = match (clusters_by_priority, special_method.is_some()) {
+            (Ok(clusters_by_priority), true) => Some(clusters_by_priority),
+            (Ok(clusters_by_priority), false) => {
+                route_by_path(&self.proxy_options.path_routes, &request.path, clusters_by_priority)
+            }
+            (Err(e), _) => {
+                explanation.error = Some(e.to_string());
+                None
+            }
+        };
+        let routed = clusters_by_priority.as_ref().map(uris_of).unwrap_or_default();
+        if explanation.error.is_none() && clusters_by_priority.is_none() {
+            explanation.error = Some(format!("no eligible node for path {}", request.path));
+        }
+
+        // the candidates as in the upstream selection, every eligible node when weighted or adaptive
+        let selection = Candidates::of(
+            &clusters_by_priority.unwrap_or_default(),
+            special_method.is_some(),
+            self.proxy_options.adaptive_priority,
+            self.proxy_options.selection,
+        );
+        let candidates = &selection.candidates;
+
+        let nodes = match special_method {
+            Some(config) => &config.nodes,
+            None => &self.host_configs,
+        };
+        explanation.nodes = nodes
+            .iter()
+            .map(|node| {
+                let block_number = block_numbers.get(&node.proxy_uri).copied();
+                let status = if candidates.iter().any(|c| c.proxy_uri == node.proxy_uri) {
+                    NodeStatus::Candidate
+                } else if routed.contains(&node.proxy_uri) {
+                    NodeStatus::LowerPriority
+                } else if eligible.contains(&node.proxy_uri) {
+                    NodeStatus::PathFiltered
+                } else if block_number.is_none() {
+                    NodeStatus::NoBlockNumber
+                } else {
+                    NodeStatus::GapFiltered
+                };
+                NodeExplanation { node: redact_url(&node.proxy_uri), priority: node.priority, block_number, status }
+            })
+            .collect();
+
+        // the adaptive latency weights and health scores are not applied, those candidates are drawn at random
+        let first_healthy_selection = self.proxy_options.selection == SelectionMode::FirstHealthy;
+        let selected = pick_candidate(candidates, first_healthy_selection, None, &mut thread_rng());
+        if let Some((selected, picked_by)) = selected {
+            let max_priority = self.host_configs.iter().map(|c| c.priority).max().unwrap_or(0);
+            let reason = selection.reason(selected, max_priority, special_method.is_some(), picked_by);
+            explanation.selected = Some(redact_url(&selected.proxy_uri));
+            explanation.reason = Some(reason.as_str());
+        }
+
+        explanation
+    }
+}
+
+fn uris_of(clusters_by_priority: &HashMap<i32, Vec<&ChainProxyConfig>>) -> Vec<String> {
+    clusters_by_priority.values().flatten().map(|c| c.proxy_uri.clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PathRoute;
+    use crate::test_util;
+
+    fn host_config(uri: &str, priority: i32) -> ChainProxyConfig {
+        ChainProxyConfig {
+            priority,
+            block_gap: 10,
+            ..test_util::host_config(uri)
+        }
+    }
+
+    fn explainer(proxy_options: ProxyOptions) -> RouteExplainer {
+        let chain_state = Arc::new(Mutex::new(ChainState::new("ethereum")));
+        {
+            let mut state = chain_state.lock().unwrap();
+            state.update_block_number("http://primary", 100);
+            state.update_block_number("http://lagging", 50);
+            state.update_block_number("http://fallback", 100);
+        }
+        let host_configs = vec![
+            host_config("http://primary", 2),
+            host_config("http://lagging", 2),
+            host_config("http://fallback", 1),
+            host_config("http://new", 1),
+        ];
+        let special_method_configs = vec![SpecialMethodConfig {
+            method_name: "debug_traceTransaction".to_string(),
+            nodes: vec![host_config("http://archive", 1)],
+        }];
+        RouteExplainer::new("ethereum", host_configs, special_method_configs, chain_state, proxy_options)
+    }
+
+    fn status_of(explanation: &RouteExplanation, node: &str) -> NodeStatus {
+        explanation.nodes.iter().find(|n| n.node == node).unwrap().status
+    }
+
+    #[test]
+    fn test_explain_special_method() {
+        let explainer = explainer(ProxyOptions::default());
+        let request = ExplainRequest { method: Some("debug_traceTransaction".to_string()), path: default_path() };
+
+        let explanation = explainer.explain(&request);
+        assert!(explanation.special_method);
+        assert_eq!(explanation.selected.as_deref(), Some("http://archive"));
+        assert_eq!(explanation.reason, Some("special"));
+        assert_eq!(explanation.nodes.len(), 1);
+        assert!(explanation.error.is_none());
+    }
+
+    #[test]
+    fn test_explain_block_gap() {
+        let explainer = explainer(ProxyOptions::default());
+        let request = ExplainRequest { method: Some("eth_call".to_string()), path: default_path() };
+
+        let explanation = explainer.explain(&request);
+        assert!(!explanation.special_method);
+        assert_eq!(explanation.max_block_number, Some(100));
+        assert_eq!(explanation.selected.as_deref(), Some("http://primary"));
+        assert_eq!(explanation.reason, Some("block-gap"));
+        assert_eq!(status_of(&explanation, "http://primary"), NodeStatus::Candidate);
+        assert_eq!(status_of(&explanation, "http://lagging"), NodeStatus::GapFiltered);
+        assert_eq!(status_of(&explanation, "http://fallback"), NodeStatus::LowerPriority);
+        assert_eq!(status_of(&explanation, "http://new"), NodeStatus::NoBlockNumber);
+    }
+
+    #[test]
+    fn test_explain_first_healthy() {
+        let explainer = explainer(ProxyOptions { selection: SelectionMode::FirstHealthy, ..Default::default() });
+        let request = ExplainRequest { method: Some("eth_call".to_string()), path: default_path() };
+
+        // the reason is the selection which picked the node
+        let explanation = explainer.explain(&request);
+        assert_eq!(explanation.selected.as_deref(), Some("http://primary"));
+        assert_eq!(explanation.reason, Some("first-healthy"));
+    }
+
+    #[test]
+    fn test_explain_path_route() {
+        let proxy_options = ProxyOptions {
+            path_routes: vec![PathRoute::new("/rosetta", vec!["http://fallback".to_string()])],
+            ..Default::default()
+        };
+        let explainer = explainer(proxy_options);
+
+        // the routed path falls back to its node
+        let request = ExplainRequest { method: None, path: "/rosetta/block".to_string() };
+        let explanation = explainer.explain(&request);
+        assert_eq!(explanation.selected.as_deref(), Some("http://fallback"));
+        assert_eq!(explanation.reason, Some("fallback"));
+        assert_eq!(status_of(&explanation, "http://primary"), NodeStatus::PathFiltered);
+
+        // no node of the chain reports a block number yet
+        let explainer = RouteExplainer::new(
+            "ethereum",
+            vec![host_config("http://primary", 1)],
+            Vec::new(),
+            Arc::new(Mutex::new(ChainState::new("ethereum"))),
+            ProxyOptions::default(),
+        );
+        let explanation = explainer.explain(&ExplainRequest::default());
+        assert!(explanation.selected.is_none());
+        assert!(explanation.error.is_some());
+    }
+}
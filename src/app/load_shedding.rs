@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Requests in flight across every proxy service of the process
+pub static LOAD_SHEDDER: LoadShedder = LoadShedder::new();
+
+/// Load shedder
+///
+/// Counts the requests in flight and rejects new ones past the max,
+/// so under extreme load some requests are shed instead of degrading all of them.
+#[derive(Debug)]
+pub struct LoadShedder {
+    // 0 disables shedding
+    max_in_flight: AtomicUsize,
+    in_flight: AtomicUsize,
+}
+
+impl LoadShedder {
+    pub const fn new() -> Self {
+        LoadShedder {
+            max_in_flight: AtomicUsize::new(0),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    /// Set the max number of requests in flight, 0 disables shedding
+    pub fn set_max_in_flight(&self, max_in_flight: usize) {
+        self.max_in_flight.store(max_in_flight, Ordering::Relaxed);
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Count a new request in flight until the permit is dropped, None if the request is shed
+    pub fn try_acquire(&'static self) -> Option<InFlightPermit> {
+        let in_flight = self.in_flight.fetch_add(1, Ordering::AcqRel);
+        let max_in_flight = self.max_in_flight.load(Ordering::Relaxed);
+        if max_in_flight > 0 && in_flight >= max_in_flight {
+            self.in_flight.fetch_sub(1, Ordering::AcqRel);
+            return None;
+        }
+        Some(InFlightPermit { shedder: self })
+    }
+}
+
+/// A request counted in flight, released when dropped with the request context
+#[derive(Debug)]
+pub struct InFlightPermit {
+    shedder: &'static LoadShedder,
+}
+
+impl Drop for InFlightPermit {
+    fn drop(&mut self) {
+        self.shedder.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Client limiter
+///
+/// Counts the requests in flight of every client of a chain and rejects new ones past the per-client max,
+/// so a single client can't take all the capacity of the chain.
+#[derive(Debug)]
+pub struct ClientLimiter {
+    max_per_client: usize,
+    // requests in flight by client ip, a client leaves the map with its last request
+    in_flight: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl ClientLimiter {
+    pub fn new(max_per_client: usize) -> Self {
+        ClientLimiter {
+            max_per_client,
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn in_flight(&self, client: &str) -> usize {
+        self.in_flight.lock().unwrap().get(client).copied().unwrap_or(0)
+    }
+
+    /// Count a new request of the client in flight until the permit is dropped, None if the client is at its max
+    pub fn try_acquire(&self, client: &str) -> Option<ClientPermit> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        let count = in_flight.entry(client.to_string()).or_insert(0);
+        if *count >= self.max_per_client {
+            return None;
+        }
+        *count += 1;
+        Some(ClientPermit {
+            client: client.to_string(),
+            in_flight: Arc::clone(&self.in_flight),
+        })
+    }
+}
+
+/// A request of a client counted in flight, released when dropped with the request context
+#[derive(Debug)]
+pub struct ClientPermit {
+    client: String,
+    in_flight: Arc<Mutex<HashMap<String, usize>>>,
+}
+
+impl Drop for ClientPermit {
+    fn drop(&mut self) {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if let Some(count) = in_flight.get_mut(&self.client) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&self.client);
+            }
+        }
+    }
+}
+
+/// Concurrency limiter
+///
+/// Bounds the requests of a chain proxied at once. Past the max a request waits in a fifo queue up to the queue
+/// timeout for a slot to free instead of being rejected right away, so a burst is smoothed out rather than
+/// answered with errors the clients retry at once.
+#[derive(Debug)]
+pub struct ConcurrencyLimiter {
+    max_concurrent: usize,
+    queue_timeout: Duration,
+    // 0 doesn't bound the queue
+    max_queued: usize,
+    semaphore: Arc<Semaphore>,
+    queued: Arc<AtomicUsize>,
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(max_concurrent: usize, queue_timeout: Duration, max_queued: usize) -> Self {
+        ConcurrencyLimiter {
+            max_concurrent,
+            queue_timeout,
+            max_queued,
+            semaphore: Arc::new(Semaphore::new(max_concurrent)),
+            queued: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.max_concurrent - self.semaphore.available_permits()
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Take a slot until the permit is dropped, waiting up to the queue timeout for one to free,
+    /// None if the queue is full or no slot freed in time
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        if let Ok(permit) = Arc::clone(&self.semaphore).try_acquire_owned() {
+            return Some(permit);
+        }
+        if self.queue_timeout.is_zero() {
+            return None;
+        }
+        let queued = self.queued.fetch_add(1, Ordering::AcqRel);
+        // left when the wait ends, or the request is dropped while waiting
+        let _queued = QueuedRequest { queued: Arc::clone(&self.queued) };
+        if self.max_queued > 0 && queued >= self.max_queued {
+            return None;
+        }
+        tokio::time::timeout(self.queue_timeout, Arc::clone(&self.semaphore).acquire_owned())
+            .await
+            .ok()
+            .and_then(|permit| permit.ok())
+    }
+}
+
+// a request waiting in the queue of a concurrency limiter
+struct QueuedRequest {
+    queued: Arc<AtomicUsize>,
+}
+
+impl Drop for QueuedRequest {
+    fn drop(&mut self) {
+        self.queued.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_shedder() {
+        static SHEDDER: LoadShedder = LoadShedder::new();
+        SHEDDER.set_max_in_flight(2);
+
+        let first = SHEDDER.try_acquire();
+        let second = SHEDDER.try_acquire();
+        assert!(first.is_some() && second.is_some());
+        assert_eq!(SHEDDER.in_flight(), 2);
+
+        // requests beyond the max are shed and not counted
+        assert!(SHEDDER.try_acquire().is_none());
+        assert_eq!(SHEDDER.in_flight(), 2);
+
+        // a finished request makes room for a new one
+        drop(first);
+        assert_eq!(SHEDDER.in_flight(), 1);
+        assert!(SHEDDER.try_acquire().is_some());
+        assert_eq!(SHEDDER.in_flight(), 1);
+    }
+
+    #[test]
+    fn test_client_limiter() {
+        let limiter = ClientLimiter::new(2);
+
+        let first = limiter.try_acquire("10.0.0.1");
+        let second = limiter.try_acquire("10.0.0.1");
+        assert!(first.is_some() && second.is_some());
+
+        // the excess requests of a client are limited
+        assert!(limiter.try_acquire("10.0.0.1").is_none());
+        assert_eq!(limiter.in_flight("10.0.0.1"), 2);
+
+        // while another client's proceed
+        let other = limiter.try_acquire("10.0.0.2");
+        assert!(other.is_some());
+        assert_eq!(limiter.in_flight("10.0.0.2"), 1);
+
+        // a finished request makes room for the client
+        drop(first);
+        assert!(limiter.try_acquire("10.0.0.1").is_some());
+
+        // a client without requests in flight is forgotten
+        drop(other);
+        assert!(!limiter.in_flight.lock().unwrap().contains_key("10.0.0.2"));
+    }
+
+    #[test]
+    fn test_load_shedder_disabled() {
+        static SHEDDER: LoadShedder = LoadShedder::new();
+
+        let permits: Vec<InFlightPermit> = (0..100).filter_map(|_| SHEDDER.try_acquire()).collect();
+        assert_eq!(permits.len(), 100);
+        assert_eq!(SHEDDER.in_flight(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limiter_queue() {
+        let limiter = Arc::new(ConcurrencyLimiter::new(1, Duration::from_millis(500), 0));
+        let first = limiter.acquire().await;
+        assert!(first.is_some());
+        assert_eq!(limiter.in_flight(), 1);
+
+        // the queued request gets the slot once it frees
+        let queued = tokio::spawn({
+            let limiter = Arc::clone(&limiter);
+            async move { limiter.acquire().await.is_some() }
+        });
+        while limiter.queued() == 0 {
+            tokio::task::yield_now().await;
+        }
+        drop(first);
+        assert!(queued.await.unwrap());
+        assert_eq!(limiter.queued(), 0);
+        assert_eq!(limiter.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limiter_timeout() {
+        let limiter = ConcurrencyLimiter::new(1, Duration::from_millis(20), 0);
+        let _first = limiter.acquire().await.unwrap();
+
+        // no slot frees in time
+        assert!(limiter.acquire().await.is_none());
+        assert_eq!(limiter.queued(), 0);
+
+        // without a queue timeout the request is rejected right away
+        let limiter = ConcurrencyLimiter::new(1, Duration::ZERO, 0);
+        let _first = limiter.acquire().await.unwrap();
+        assert!(limiter.acquire().await.is_none());
+
+        // a full queue rejects right away
+        let limiter = Arc::new(ConcurrencyLimiter::new(1, Duration::from_secs(5), 1));
+        let _first = limiter.acquire().await.unwrap();
+        let waiting = tokio::spawn({
+            let limiter = Arc::clone(&limiter);
+            async move { limiter.acquire().await.is_some() }
+        });
+        while limiter.queued() == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert!(limiter.acquire().await.is_none());
+        assert_eq!(limiter.queued(), 1);
+        waiting.abort();
+    }
+}
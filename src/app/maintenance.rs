@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use bytes::Bytes;
+use pingora_http::ResponseHeader;
+
+use crate::config::Maintenance;
+
+// jsonrpc error code of the default maintenance response, a server error
+const MAINTENANCE_JSONRPC_CODE: i64 = -32000;
+const MAINTENANCE_MESSAGE: &str = "chain is under maintenance";
+
+lazy_static! {
+    // maintenance of the chains by name, replaced when the config is reloaded
+    static ref MAINTENANCE: RwLock<HashMap<String, Maintenance>> = RwLock::new(HashMap::new());
+}
+
+/// Set the maintenance of a chain, None removes it
+pub fn set_maintenance(chain_name: &str, maintenance: Option<&Maintenance>) {
+    let mut chains = MAINTENANCE.write().unwrap();
+    match maintenance {
+        Some(maintenance) => {
+            if chains.get(chain_name) != Some(maintenance) {
+                log::info!(
+                    "chain {chain_name} maintenance {}",
+                    if maintenance.enabled() { "enabled" } else { "disabled" }
+                );
+            }
+            chains.insert(chain_name.to_string(), maintenance.clone());
+        }
+        None => {
+            if chains.remove(chain_name).is_some_and(|maintenance| maintenance.enabled()) {
+                log::info!("chain {chain_name} maintenance disabled");
+            }
+        }
+    }
+}
+
+/// The maintenance of the chain if it is enabled, None if the requests are proxied
+pub fn active_maintenance(chain_name: &str) -> Option<Maintenance> {
+    MAINTENANCE
+        .read()
+        .unwrap()
+        .get(chain_name)
+        .filter(|maintenance| maintenance.enabled())
+        .cloned()
+}
+
+/// The response of a chain under maintenance, the configured body is sent as json if it is valid json,
+/// without a body a jsonrpc chain answers a jsonrpc error
+pub fn maintenance_response(maintenance: &Maintenance, jsonrpc: bool) -> (ResponseHeader, Bytes) {
+    let (content_type, body) = match maintenance.body() {
+        Some(body) if serde_json::from_str::<serde_json::Value>(body).is_ok() => {
+            ("application/json", Bytes::from(body.to_string()))
+        }
+        Some(body) => ("text/plain", Bytes::from(body.to_string())),
+        None if jsonrpc => {
+            let body = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": {
+                    "code": MAINTENANCE_JSONRPC_CODE,
+                    "message": MAINTENANCE_MESSAGE,
+                },
+            });
+            ("application/json", Bytes::from(body.to_string()))
+        }
+        None => ("text/plain", Bytes::from(MAINTENANCE_MESSAGE)),
+    };
+
+    let mut resp = ResponseHeader::build(maintenance.status(), Some(2)).unwrap();
+    resp.insert_header("content-type", content_type).unwrap();
+    resp.insert_header("content-length", body.len().to_string()).unwrap();
+    (resp, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_maintenance() {
+        let chain_name = "maintenance_test";
+        assert!(active_maintenance(chain_name).is_none());
+
+        // maintenance returns the configured response
+        let body = r#"{"jsonrpc":"2.0","id":null,"error":{"code":-32000,"message":"upgrading"}}"#;
+        set_maintenance(chain_name, Some(&Maintenance::new(true, None, Some(body))));
+        let maintenance = active_maintenance(chain_name).unwrap();
+        let (resp, resp_body) = maintenance_response(&maintenance, true);
+        assert_eq!(resp.status.as_u16(), 503);
+        assert_eq!(resp.headers.get("content-type").unwrap(), "application/json");
+        assert_eq!(resp_body, Bytes::from(body));
+
+        set_maintenance(chain_name, Some(&Maintenance::new(true, Some(502), Some("back soon"))));
+        let (resp, resp_body) = maintenance_response(&active_maintenance(chain_name).unwrap(), true);
+        assert_eq!(resp.status.as_u16(), 502);
+        assert_eq!(resp.headers.get("content-type").unwrap(), "text/plain");
+        assert_eq!(resp_body, Bytes::from("back soon"));
+
+        // the default body of a jsonrpc chain is a jsonrpc error
+        let (_, resp_body) = maintenance_response(&Maintenance::new(true, None, None), true);
+        let error: serde_json::Value = serde_json::from_slice(&resp_body).unwrap();
+        assert_eq!(error["error"]["code"], MAINTENANCE_JSONRPC_CODE);
+
+        // disabling restores proxying
+        set_maintenance(chain_name, Some(&Maintenance::new(false, None, Some(body))));
+        assert!(active_maintenance(chain_name).is_none());
+
+        set_maintenance(chain_name, Some(&Maintenance::new(true, None, None)));
+        assert!(active_maintenance(chain_name).is_some());
+        set_maintenance(chain_name, None);
+        assert!(active_maintenance(chain_name).is_none());
+    }
+}
@@ -1,5 +1,12 @@
 pub mod proxy_base;
 mod config;
+pub(crate) mod selection;
+pub(crate) mod sampling;
+pub(crate) mod success_ratio;
+pub(crate) mod load_shedding;
+pub(crate) mod maintenance;
+pub(crate) mod explain;
+pub(crate) mod compression;
 pub(crate) mod node_proxy_app;
 pub(crate) mod common_proxy_app;
-
+pub(crate) mod tcp_proxy_app;
@@ -1,21 +1,30 @@
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use log::{debug, info};
 use async_trait::async_trait;
+use http::HeaderMap;
+use bytes::Bytes;
+use pingora_http::{RequestHeader, ResponseHeader};
 
 use pingora_proxy::ProxyHttp;
 use pingora::{
     upstreams::peer::{HttpPeer},
     Error,
-    Custom,
     Result
 };
 use pingora_load_balancing::LoadBalancer;
 use pingora_load_balancing::prelude::RoundRobin;
 use pingora_proxy::Session;
-use crate::config::ChainState;
-use crate::service::proxy::{ChainProxyConfig, SpecialMethodConfig};
-use crate::app::proxy_base::ProxyBase;
+use crate::config::{ChainState, Maintenance};
+use crate::service::proxy::{ChainProxyConfig, ProxyOptions, SpecialMethodConfig};
+use crate::error::ProxyError;
+use crate::app::proxy_base::{clusters_by_special_method, special_method_header, ProxyBase, ProxyCtx};
+use crate::app::selection::{LatencyEwma, StickySelection, WarmupRamp};
+use crate::app::sampling::ErrorSampler;
+use crate::app::load_shedding::{ClientLimiter, ConcurrencyLimiter};
+use crate::app::maintenance::active_maintenance;
+use crate::app::success_ratio::SuccessRatioWindow;
 
 pub struct NodeProxyApp {
     chain_name: String,
@@ -34,6 +43,30 @@ pub struct NodeProxyApp {
 
     // shared chain state
     chain_state: Arc<Mutex<ChainState>>,
+
+    // request path options
+    proxy_options: ProxyOptions,
+
+    // last selected node, reused within the sticky window
+    sticky_selection: StickySelection,
+
+    // traffic ramp of nodes becoming eligible again
+    warmup_ramp: WarmupRamp,
+
+    // upstream error responses logged with their body
+    error_sampler: ErrorSampler,
+
+    // recent proxy outcomes of every node
+    success_ratio_window: SuccessRatioWindow,
+
+    // recent latency of every node, for the adaptive priority
+    latency_ewma: LatencyEwma,
+
+    // requests in flight of every client, None without a per-client limit
+    client_limiter: Option<ClientLimiter>,
+
+    // requests proxied at once and queued for a slot, None without a concurrency limit
+    concurrency_limiter: Option<ConcurrencyLimiter>,
 }
 
 impl NodeProxyApp {
@@ -44,6 +77,7 @@ impl NodeProxyApp {
         special_method_configs: Vec<SpecialMethodConfig>,
         clusters: HashMap<String, Arc<LoadBalancer<RoundRobin>>>,
         chain_state: Arc<Mutex<ChainState>>,
+        proxy_options: ProxyOptions,
     ) -> Self {
         NodeProxyApp {
             chain_name,
@@ -52,6 +86,20 @@ impl NodeProxyApp {
             host_configs,
             special_method_configs,
             chain_state: Arc::clone(&chain_state),
+            sticky_selection: StickySelection::new(proxy_options.sticky_window),
+            warmup_ramp: WarmupRamp::new(proxy_options.warmup),
+            error_sampler: ErrorSampler::new(proxy_options.error_sample_rate),
+            success_ratio_window: SuccessRatioWindow::new(proxy_options.success_ratio_window),
+            latency_ewma: LatencyEwma::new(),
+            client_limiter: match proxy_options.max_client_in_flight {
+                0 => None,
+                max => Some(ClientLimiter::new(max)),
+            },
+            concurrency_limiter: match proxy_options.max_concurrent {
+                0 => None,
+                max => Some(ConcurrencyLimiter::new(max, proxy_options.queue_timeout, proxy_options.max_queued)),
+            },
+            proxy_options,
         }
     }
 }
@@ -67,81 +115,325 @@ impl ProxyBase for NodeProxyApp {
     }
 
     async fn get_eligible_clusters(&self, session: &mut Session) -> Result<HashMap<i32, Vec<&ChainProxyConfig>>> {
-        if let Some(result) = self.get_clusters_by_special_method(session).await {
-            return result;
-        }
+        eligible_clusters(
+            &self.host_configs,
+            &self.special_method_configs,
+            &self.chain_state,
+            special_method_header(session),
+        )
+    }
 
-        // if not a special method, find the eligible clusters by block number
-        let block_numbers = {
-            let state = self.chain_state.lock().unwrap();
-            state.get_block_numbers().clone()
-        };
+    fn get_protocol(&self) -> &str {
+        &self.protocol
+    }
 
-        let max_block_number = block_numbers.values().max().unwrap_or(&0);
-        if max_block_number == &0 {
-            log::error!("No block number found");
-            return Error::e_explain(Custom("No block number found, maybe health check is unavailable or system is starting"), "proxy error");
-        }
+    fn get_special_method_configs(&self) -> &Vec<SpecialMethodConfig> {
+        &self.special_method_configs
+    }
+
+    fn get_host_configs(&self) -> &Vec<ChainProxyConfig> {
+        &self.host_configs
+    }
+
+    fn get_proxy_options(&self) -> &ProxyOptions {
+        &self.proxy_options
+    }
+
+    fn get_sticky_selection(&self) -> &StickySelection {
+        &self.sticky_selection
+    }
 
-        let block_range = self.host_configs[0].block_gap;
+    fn get_warmup_ramp(&self) -> &WarmupRamp {
+        &self.warmup_ramp
+    }
+
+    fn get_error_sampler(&self) -> &ErrorSampler {
+        &self.error_sampler
+    }
+
+    fn get_success_ratio_window(&self) -> &SuccessRatioWindow {
+        &self.success_ratio_window
+    }
+
+    fn get_latency_ewma(&self) -> &LatencyEwma {
+        &self.latency_ewma
+    }
+
+    fn get_chain_state(&self) -> Option<&Arc<Mutex<ChainState>>> {
+        Some(&self.chain_state)
+    }
+
+    fn get_client_limiter(&self) -> Option<&ClientLimiter> {
+        self.client_limiter.as_ref()
+    }
+
+    fn get_concurrency_limiter(&self) -> Option<&ConcurrencyLimiter> {
+        self.concurrency_limiter.as_ref()
+    }
+
+    fn get_maintenance(&self) -> Option<Maintenance> {
+        active_maintenance(&self.chain_name)
+    }
+}
+
+/// The eligible clusters of a request by priority, the nodes of its special method,
+/// or else the hosts within the block gap, without a live session so routing can be explained
+pub(crate) fn eligible_clusters<'a>(
+    host_configs: &'a [ChainProxyConfig],
+    special_method_configs: &'a [SpecialMethodConfig],
+    chain_state: &Mutex<ChainState>,
+    special_method: Option<&str>,
+) -> Result<HashMap<i32, Vec<&'a ChainProxyConfig>>> {
+    if let Some(clusters_by_priority) =
+        special_method.and_then(|method| clusters_by_special_method(special_method_configs, method))
+    {
+        return Ok(clusters_by_priority);
+    }
+
+    // if not a special method, find the eligible clusters by block number
+    let block_numbers = {
+        let state = chain_state.lock().unwrap();
+        state.get_block_numbers().clone()
+    };
+
+    eligible_clusters_by_block_number(host_configs, &block_numbers)
+}
+
+/// Group the hosts whose block number is within the block gap of the max block number by priority
+pub(crate) fn eligible_clusters_by_block_number<'a>(
+    host_configs: &'a [ChainProxyConfig],
+    block_numbers: &HashMap<String, u64>,
+) -> Result<HashMap<i32, Vec<&'a ChainProxyConfig>>> {
+    let max_block_number = block_numbers.values().max().unwrap_or(&0);
+    if max_block_number == &0 {
+        log::error!("No block number found");
+        return ProxyError::NoBlockNumber.explain("proxy error");
+    }
 
-        debug!(
-            "Max block number: {}, current block range: {}",
-            max_block_number, block_range
+    // don't let a single node dictate the eligibility of the whole chain
+    let min_height_reporters = host_configs.first().map_or(0, |config| config.min_height_reporters);
+    let height_reporters = host_configs
+        .iter()
+        .filter(|config| block_numbers.get(&config.proxy_uri).is_some_and(|n| *n > 0))
+        .count();
+    if height_reporters < min_height_reporters {
+        log::error!(
+            "Only {} nodes report a block number, at least {} required",
+            height_reporters,
+            min_height_reporters
         );
+        return ProxyError::InsufficientHeightReporters.http_status(503);
+    }
+
+    debug!("Max block number: {}", max_block_number);
 
-        let mut clusters_by_priority: HashMap<i32, Vec<&ChainProxyConfig>> = HashMap::new();
-        for config in self.host_configs.iter() {
-            let current_block_number = block_numbers.get(&config.proxy_uri);
-            if current_block_number.is_none() {
-                debug!(
-                    "Host: {} is not eligible, block number not found",
-                    config.proxy_uri
-                );
-                continue;
-            }
-
-            let current_block_number = current_block_number.unwrap();
-
-            if max_block_number - current_block_number > block_range {
-                info!(
-                    "Host: {} is not eligible, block number: {}",
-                    config.proxy_uri,
-                    current_block_number
-                );
-                continue;
-            }
-
-            clusters_by_priority.entry(config.priority).or_insert_with(Vec::new).push(config);
+    let mut clusters_by_priority: HashMap<i32, Vec<&ChainProxyConfig>> = HashMap::new();
+    for config in host_configs.iter() {
+        let current_block_number = block_numbers.get(&config.proxy_uri);
+        if current_block_number.is_none() {
+            debug!(
+                "Host: {} is not eligible, block number not found",
+                config.proxy_uri
+            );
+            continue;
         }
 
-        if clusters_by_priority.is_empty() {
-            log::error!("No eligible cluster found");
-            return Error::e_explain(Custom("No eligible cluster found"), "proxy error");
+        let current_block_number = current_block_number.unwrap();
+
+        // the block gap is per node, nodes of different priority tiers may have different gaps
+        if max_block_number - current_block_number > config.block_gap {
+            info!(
+                "Host: {} is not eligible, block number: {}, block gap: {}",
+                config.proxy_uri,
+                current_block_number,
+                config.block_gap
+            );
+            continue;
         }
 
-        Ok(clusters_by_priority)
+        clusters_by_priority.entry(config.priority).or_default().push(config);
     }
 
-    fn get_protocol(&self) -> &str {
-        &self.protocol
+    if clusters_by_priority.is_empty() {
+        log::error!("No eligible cluster found");
+        return ProxyError::NoEligibleCluster.explain("proxy error");
     }
 
-    fn get_special_method_configs(&self) -> &Vec<SpecialMethodConfig> {
-        &self.special_method_configs
-    }
+    Ok(clusters_by_priority)
 }
 
 #[async_trait]
 impl ProxyHttp for NodeProxyApp {
-    type CTX = ();
-    fn new_ctx(&self) {}
+    type CTX = ProxyCtx;
+    fn new_ctx(&self) -> Self::CTX {
+        ProxyCtx::default()
+    }
 
-    async fn upstream_peer(&self, session: &mut Session, ctx: &mut ()) -> Result<Box<HttpPeer>> {
+    async fn request_filter(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<bool> {
+        ProxyBase::request_filter(self, session, ctx).await
+    }
+
+    async fn upstream_peer(&self, session: &mut Session, ctx: &mut Self::CTX) -> Result<Box<HttpPeer>> {
         ProxyBase::upstream_peer(self, session, ctx).await
     }
 
+    async fn upstream_request_filter(
+        &self,
+        session: &mut Session,
+        upstream_request: &mut RequestHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        ProxyBase::upstream_request_filter(self, session, upstream_request, ctx).await
+    }
+
+    async fn request_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        ProxyBase::request_body_filter(self, session, body, end_of_stream, ctx).await
+    }
+
+    async fn response_filter(
+        &self,
+        session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        ProxyBase::response_filter(self, session, upstream_response, ctx).await
+    }
+
+    fn upstream_response_filter(
+        &self,
+        session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut Self::CTX,
+    ) {
+        ProxyBase::upstream_response_filter(self, session, upstream_response, ctx)
+    }
+
+    fn upstream_response_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) {
+        ProxyBase::upstream_response_body_filter(self, session, body, end_of_stream, ctx)
+    }
+
+    fn response_body_filter(
+        &self,
+        session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut Self::CTX,
+    ) -> Result<Option<Duration>> {
+        ProxyBase::response_body_filter(self, session, body, end_of_stream, ctx)
+    }
+
+    fn upstream_response_trailer_filter(
+        &self,
+        session: &mut Session,
+        upstream_trailers: &mut HeaderMap,
+        ctx: &mut Self::CTX,
+    ) -> Result<()> {
+        ProxyBase::upstream_response_trailer_filter(self, session, upstream_trailers, ctx)
+    }
+
+    async fn fail_to_proxy(&self, session: &mut Session, e: &Error, ctx: &mut Self::CTX) -> u16 {
+        ProxyBase::fail_to_proxy(self, session, e, ctx).await
+    }
+
     async fn logging(&self, session: &mut Session, e: Option<&Error>, ctx: &mut Self::CTX) {
         ProxyBase::logging(self, session, e, ctx).await
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingora::ErrorType;
+    use crate::test_util;
+
+    fn host_config(uri: &str, min_height_reporters: usize) -> ChainProxyConfig {
+        ChainProxyConfig {
+            block_gap: 10,
+            min_height_reporters,
+            ..test_util::host_config(uri)
+        }
+    }
+
+    #[test]
+    fn test_eligible_clusters_by_block_number() {
+        let host_configs = vec![host_config("http://node1", 0), host_config("http://node2", 0)];
+        let block_numbers = HashMap::from([
+            ("http://node1".to_string(), 100),
+            ("http://node2".to_string(), 80),
+        ]);
+
+        // node2 is behind more than the block gap
+        let clusters = eligible_clusters_by_block_number(&host_configs, &block_numbers).unwrap();
+        assert_eq!(clusters[&1].len(), 1);
+        assert_eq!(clusters[&1][0].proxy_uri, "http://node1");
+    }
+
+    #[test]
+    fn test_block_gap_per_priority() {
+        // a fast primary held to a tight gap, a slow fallback to a looser one
+        let primary = ChainProxyConfig {
+            proxy_uri: "http://primary".to_string(),
+            priority: 2,
+            block_gap: 5,
+            ..Default::default()
+        };
+        let fallback = ChainProxyConfig {
+            proxy_uri: "http://fallback".to_string(),
+            priority: 1,
+            block_gap: 50,
+            ..Default::default()
+        };
+        let host_configs = vec![primary, fallback];
+
+        let block_numbers = HashMap::from([
+            ("http://primary".to_string(), 94),
+            ("http://fallback".to_string(), 100),
+        ]);
+        let clusters = eligible_clusters_by_block_number(&host_configs, &block_numbers).unwrap();
+        assert!(!clusters.contains_key(&2));
+        assert_eq!(clusters[&1][0].proxy_uri, "http://fallback");
+
+        // the same lag is within the gap of the fallback tier
+        let block_numbers = HashMap::from([
+            ("http://primary".to_string(), 100),
+            ("http://fallback".to_string(), 60),
+        ]);
+        let clusters = eligible_clusters_by_block_number(&host_configs, &block_numbers).unwrap();
+        assert_eq!(clusters[&2][0].proxy_uri, "http://primary");
+        assert_eq!(clusters[&1][0].proxy_uri, "http://fallback");
+    }
+
+    #[test]
+    fn test_min_height_reporters() {
+        let host_configs = vec![
+            host_config("http://node1", 2),
+            host_config("http://node2", 2),
+            host_config("http://node3", 2),
+        ];
+
+        // only one node reports a height, the guard triggers
+        let block_numbers = HashMap::from([("http://node1".to_string(), 100)]);
+        let err = eligible_clusters_by_block_number(&host_configs, &block_numbers).unwrap_err();
+        assert_eq!(err.etype(), &ErrorType::HTTPStatus(503));
+
+        // enough reporters
+        let block_numbers = HashMap::from([
+            ("http://node1".to_string(), 100),
+            ("http://node2".to_string(), 99),
+        ]);
+        let clusters = eligible_clusters_by_block_number(&host_configs, &block_numbers).unwrap();
+        assert_eq!(clusters[&1].len(), 2);
+    }
+}
@@ -1,61 +1,347 @@
-use crate::service::proxy::{ChainProxyConfig, SpecialMethodConfig};
+use crate::service::proxy::{ChainProxyConfig, ProxyOptions, SpecialMethodConfig};
 use async_trait::async_trait;
 use log::{debug};
 use pingora::{
+    protocols::ALPN,
     upstreams::peer::{HttpPeer},
     Error,
-    Custom,
+    ErrorSource,
+    ErrorType,
+    HTTPStatus,
     Result
 };
 
+use pingora_http::{RequestHeader, ResponseHeader};
+use bytes::Bytes;
+use http::HeaderMap;
 use pingora_proxy::ProxyHttp;
 use pingora_proxy::Session;
 use pingora_load_balancing::selection::RoundRobin;
 use pingora_load_balancing::LoadBalancer;
 use std::collections::{HashMap};
-use std::sync::{Arc};
-use rand::seq::SliceRandom;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::OwnedSemaphorePermit;
 use rand::thread_rng;
 use crate::app::config::DEFAULT_PEER_OPTIONS;
-use crate::metrics::inc_proxy_result_counter;
+use crate::error::ProxyError;
+use crate::app::selection::{
+    format_selection_log, health_scores, pick_candidate, prefer_region, Candidates, LatencyEwma, SelectionReason,
+    StickySelection, WarmupRamp,
+};
+use crate::config::{redact_url, Alpn, ChainState, Maintenance, PathRoute, SelectionMode, SELECTION_LOG_TARGET};
+use crate::app::sampling::{ErrorSampler, ERROR_SAMPLE_BODY_LIMIT};
+use crate::app::success_ratio::SuccessRatioWindow;
+use crate::app::load_shedding::{ClientLimiter, ClientPermit, ConcurrencyLimiter, InFlightPermit, LOAD_SHEDDER};
+use crate::app::maintenance::maintenance_response;
+use crate::app::compression::{accepts_gzip, compresses_response, GzipStream};
+use crate::metrics::{
+    inc_grpc_result_counter, inc_proxy_result_counter, inc_request_bytes, inc_response_bytes, observe_sla,
+    set_node_health_score_gauge, set_node_height_gauge, set_node_success_ratio_gauge,
+};
+
+/// Per request context shared across the proxy phases
+#[derive(Debug, Default)]
+pub struct ProxyCtx {
+    // grpc-status sent by the upstream in the response trailers
+    pub grpc_status: Option<String>,
+    // body of a sampled upstream error response, None when the response is not sampled
+    pub error_body: Option<Vec<u8>>,
+    // X-Request-Id of the request, taken from the client or generated
+    pub request_id: String,
+    // when the request was received, for the sla tracking
+    pub start: Option<Instant>,
+    // uri of the node selected for the request
+    pub selected_node: Option<String>,
+    // hostname of the node selected for the request, the host label of its metrics
+    pub upstream_hostname: Option<String>,
+    // request body forwarded instead of the client one, when jsonrpc methods or ids were rewritten
+    pub rewritten_request_body: Option<Bytes>,
+    // original jsonrpc ids of the client by the unique id forwarded upstream, restored in the response
+    pub jsonrpc_ids: HashMap<u64, serde_json::Value>,
+    // upstream response body buffered until complete, when jsonrpc ids are restored
+    pub response_body: Vec<u8>,
+    // bytes of the upstream response body received so far
+    pub response_body_bytes: usize,
+    // the upstream response body exceeded the max size, the response is aborted
+    pub response_body_too_large: bool,
+    // response body bytes received from the upstream, including any past the max size
+    pub response_bytes: usize,
+    // the request is a block height query, its response body is kept to read the height
+    pub passive_height: bool,
+    // response body of a block height query
+    pub passive_height_body: Vec<u8>,
+    // counts the request in flight until the context is dropped, None if the request was shed
+    pub in_flight: Option<InFlightPermit>,
+    // counts the request in flight for its client until the context is dropped
+    pub client_in_flight: Option<ClientPermit>,
+    // holds a slot of the chain concurrency limit until the context is dropped
+    pub concurrency_permit: Option<OwnedSemaphorePermit>,
+    // uris of the nodes which answered a retried status, not selected again for the request
+    pub tried_nodes: Vec<String>,
+    // largest Retry-After in seconds of the nodes which rate limited the request, 0 when they sent none
+    pub retry_after: Option<u64>,
+    // upstream read timeout of the jsonrpc methods of the request, overriding the one of the node
+    pub method_timeout: Option<Duration>,
+    // when the request was sent to the upstream, for the server timing
+    pub upstream_start: Option<Instant>,
+    // gzip encoder of the response body, when the uncompressed upstream response is compressed for the client
+    pub gzip: Option<GzipStream>,
+    // ids of the jsonrpc request read before it is forwarded, answered in the jsonrpc error of a failed request
+    pub request_jsonrpc_ids: Option<JsonrpcRequestIds>,
+}
+
+/// The client ids of a jsonrpc request, or of every call of a batch
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonrpcRequestIds {
+    Single(serde_json::Value),
+    Batch(Vec<serde_json::Value>),
+}
 
 #[async_trait]
-pub trait ProxyBase: Send + Sync + ProxyHttp {
+pub trait ProxyBase: Send + Sync + ProxyHttp<CTX = ProxyCtx> {
     fn get_clusters(&self) -> &HashMap<String, Arc<LoadBalancer<RoundRobin>>>;
     fn get_chain_name(&self) -> &str;
 
-    async fn upstream_peer(&self, session: &mut Session, _ctx: &mut ()) -> Result<Box<HttpPeer>> {
-        let clusters_by_priority = self.get_eligible_clusters(session).await?;
+    async fn request_filter(&self, session: &mut Session, ctx: &mut ProxyCtx) -> Result<bool> {
+        // tag the request with a correlation id, it is forwarded upstream with the request headers
+        ctx.request_id = ensure_request_id(session.as_downstream_mut().req_header_mut());
+        ctx.start = Some(Instant::now());
+
+        // answer the fixed response of a chain under maintenance instead of forwarding
+        if let Some(maintenance) = self.get_maintenance() {
+            debug!("[{}] chain {} under maintenance", ctx.request_id, self.get_chain_name());
+            let (resp, body) = maintenance_response(&maintenance, self.get_protocol() == "jsonrpc");
+            session.write_response_header(Box::new(resp), false).await?;
+            session.write_response_body(Some(body), true).await?;
+            return Ok(true);
+        }
+
+        // shed the request rather than degrade every request in flight
+        ctx.in_flight = LOAD_SHEDDER.try_acquire();
+        if ctx.in_flight.is_none() {
+            log::warn!(
+                "[{}] request to chain {} shed, {} requests in flight",
+                ctx.request_id,
+                self.get_chain_name(),
+                LOAD_SHEDDER.in_flight()
+            );
+            session.respond_error(503).await?;
+            return Ok(true);
+        }
+
+        // limit the requests of a single client so it can't take all the capacity of the chain
+        if let Some(client_limiter) = self.get_client_limiter() {
+            let client_ip = session
+                .client_addr()
+                .and_then(|addr| addr.as_inet())
+                .map(|addr| addr.ip().to_string());
+            if let Some(client_ip) = client_ip {
+                ctx.client_in_flight = client_limiter.try_acquire(&client_ip);
+                if ctx.client_in_flight.is_none() {
+                    log::warn!(
+                        "[{}] request of client {client_ip} to chain {} limited, {} requests in flight",
+                        ctx.request_id,
+                        self.get_chain_name(),
+                        client_limiter.in_flight(&client_ip)
+                    );
+                    session.respond_error(429).await?;
+                    return Ok(true);
+                }
+            }
+        }
+
+        // wait in the queue of the chain for a slot past its concurrency limit
+        if let Some(concurrency_limiter) = self.get_concurrency_limiter() {
+            ctx.concurrency_permit = concurrency_limiter.acquire().await;
+            if ctx.concurrency_permit.is_none() {
+                log::warn!(
+                    "[{}] request to chain {} rejected, {} requests in flight and {} queued",
+                    ctx.request_id,
+                    self.get_chain_name(),
+                    concurrency_limiter.in_flight(),
+                    concurrency_limiter.queued()
+                );
+                session.respond_error(503).await?;
+                return Ok(true);
+            }
+        }
+
+        // reject malformed jsonrpc before selecting a node, find the timeout of its methods and rewrite it,
+        // the body is kept and forwarded with the request
+        let options = self.get_proxy_options();
+        let reads_body = options.validate_jsonrpc_request || !options.method_timeouts.is_empty();
+        let reads_body = reads_body || options.jsonrpc_error_response || self.rewrites_request_body();
+        if self.get_protocol() == "jsonrpc" && reads_body {
+            let request_body = read_retry_buffered_body(session).await;
+            // the ids are kept to answer a jsonrpc error once the body was forwarded
+            ctx.request_jsonrpc_ids = request_body.as_deref().map(jsonrpc_request_ids);
+            if let Some(request_body) = request_body.as_deref().filter(|_| options.validate_jsonrpc_request) {
+                if let Err(reason) = validate_jsonrpc_request(request_body) {
+                    debug!("[{}] invalid jsonrpc request: {reason}", ctx.request_id);
+                    let ids = jsonrpc_request_ids(request_body);
+                    let (resp, body) = jsonrpc_error_response(&ids, JSONRPC_INVALID_REQUEST_CODE, reason);
+                    session.write_response_header(Box::new(resp), false).await?;
+                    session.write_response_body(Some(body), true).await?;
+                    return Ok(true);
+                }
+            }
+            let (timeouts, rewrites) = (&options.method_timeouts, &options.method_rewrites);
+            ctx.method_timeout = match request_body.as_deref() {
+                Some(request_body) => method_timeout(request_body, timeouts, rewrites),
+                None => special_method_header(session).and_then(|method| timeout_of_method(method, timeouts, rewrites)),
+            };
+            // a body too large to be buffered is forwarded unchanged
+            if let Some(request_body) = request_body.filter(|_| self.rewrites_request_body()) {
+                let (rewritten, ids) = rewrite_jsonrpc_request(&request_body, rewrites, options.rewrite_jsonrpc_ids);
+                ctx.rewritten_request_body = rewritten.map(Bytes::from);
+                ctx.jsonrpc_ids = ids;
+            }
+        }
+        Ok(false)
+    }
 
-        // Find the highest priority clusters
-        let max_priority = clusters_by_priority.keys().max().unwrap();
-        let highest_priority_clusters = clusters_by_priority.get(max_priority).unwrap();
+    async fn upstream_peer(&self, session: &mut Session, ctx: &mut ProxyCtx) -> Result<Box<HttpPeer>> {
+        let special_method = self.is_special_method(session);
+        let clusters_by_priority = self.get_eligible_clusters(session).await?;
 
-        // Select a cluster from the highest priority clusters
-        let selected_cluster = if highest_priority_clusters.len() == 1 {
-            highest_priority_clusters[0]
+        // a routed path prefix is only served by its nodes
+        let clusters_by_priority = if special_method {
+            clusters_by_priority
         } else {
-            // Random selection
-            let mut rng = thread_rng();
-            highest_priority_clusters.choose(&mut rng).unwrap()
+            let path = session.req_header().uri.path();
+            match route_by_path(&self.get_proxy_options().path_routes, path, clusters_by_priority) {
+                Some(routed) => routed,
+                None => {
+                    log::error!("[{}] No eligible node for path {path}", ctx.request_id);
+                    return ProxyError::NoEligibleCluster.explain("proxy error");
+                }
+            }
+        };
+        // a retried request goes to another node
+        let clusters_by_priority = exclude_tried_nodes(&ctx.tried_nodes, clusters_by_priority);
+
+        // When every node has a traffic weight, priorities are ignored and all eligible clusters are candidates,
+        // with adaptive priority all eligible clusters are candidates, drawn by their recent latency
+        let selection = Candidates::of(
+            &clusters_by_priority,
+            special_method,
+            self.get_proxy_options().adaptive_priority,
+            self.get_proxy_options().selection,
+        );
+        let candidates = selection.candidates.clone();
+        // clients are sent to the candidates in their region when there are some
+        let candidates = match self.get_proxy_options().region_affinity.as_ref() {
+            Some(affinity) if !special_method => {
+                let region = session
+                    .req_header()
+                    .headers
+                    .get(affinity.header())
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| affinity.client_region(value));
+                prefer_region(candidates, affinity.tag(), region)
+            }
+            _ => candidates,
+        };
 
-            // if you want to use round robin selection, you can add here
+        // nodes which just became eligible get a reduced share of the traffic,
+        // special methods are routed to their own nodes and don't count
+        let now = Instant::now();
+        let warmup_weights = if special_method {
+            None
+        } else {
+            self.get_warmup_ramp().weights(&selection.eligible, &candidates, now)
+        };
+        let latency_weights = if selection.adaptive {
+            self.get_latency_ewma().weights(&candidates)
+        } else {
+            None
         };
+        // with the health score selection the candidates are drawn by their score, special methods and
+        // traffic weights keep their own selection
+        let score_weights = match (selection.health_scored, self.get_chain_state()) {
+            (true, Some(chain_state)) => {
+                let block_numbers = chain_state.lock().unwrap().get_block_numbers().clone();
+                let scores = health_scores(
+                    &candidates,
+                    &block_numbers,
+                    self.get_latency_ewma(),
+                    self.get_success_ratio_window(),
+                    &self.get_proxy_options().health_score_weights,
+                );
+                for (candidate, score) in candidates.iter().zip(scores.iter()) {
+                    set_node_health_score_gauge(self.get_chain_name(), &candidate.proxy_uri, *score);
+                }
+                Some(scores)
+            }
+            _ => None,
+        };
+        let selection_weights: Option<Vec<f64>> = [warmup_weights, latency_weights, score_weights]
+            .into_iter()
+            .flatten()
+            .reduce(|product, weights| product.iter().zip(weights.iter()).map(|(p, w)| p * w).collect());
+
+        // Select a cluster from the candidates,
+        // the last selected one is reused within the sticky window
+        let first_healthy_selection = self.get_proxy_options().selection == SelectionMode::FirstHealthy;
+        // the node is reused unless the selection picks one
+        let mut picked_by = Some(SelectionReason::Sticky);
+        let selected_cluster = self.get_sticky_selection().select(
+            &candidates,
+            now,
+            |candidates| {
+                let weights = selection_weights.as_deref();
+                // the eligible clusters are never empty, neither are the candidates
+                let (selected, reason) =
+                    pick_candidate(candidates, first_healthy_selection, weights, &mut thread_rng()).unwrap();
+                picked_by = reason;
+                selected
+            },
+        );
 
         // check the cluster
         let cluster = self.get_clusters().get(selected_cluster.proxy_uri.as_str());
         if let None = cluster {
-            log::error!("Cluster not found");
-            return Error::e_explain(Custom("Cluster not found"), "proxy error");
+            log::error!("[{}] Cluster not found", ctx.request_id);
+            return ProxyError::ClusterNotFound.explain("proxy error");
         }
 
+        let client_ip = session
+            .client_addr()
+            .and_then(|addr| addr.as_inet())
+            .map(|addr| addr.ip().to_string());
+
+        // log why the node was selected, at its own target so it can be enabled independently
+        let max_priority = self.get_host_configs().iter().map(|c| c.priority).max().unwrap_or(0);
+        let reason = selection.reason(selected_cluster, max_priority, special_method, picked_by);
+        log::info!(
+            target: SELECTION_LOG_TARGET,
+            "{}",
+            format_selection_log(
+                &ctx.request_id,
+                self.get_chain_name(),
+                client_ip.as_deref(),
+                &redact_url(&selected_cluster.proxy_uri),
+                selected_cluster.priority,
+                reason,
+                &selected_cluster.tags,
+            )
+        );
+
+        ctx.selected_node = Some(selected_cluster.proxy_uri.clone());
+        ctx.upstream_hostname = Some(selected_cluster.proxy_hostname.clone());
+
         let session = session.as_downstream_mut();
         let req = session.req_header_mut();
 
+        apply_forwarded_for(req, self.get_proxy_options().forward_client_ip, client_ip.as_deref());
+
         // set session header to host name
         let result = req.insert_header("host", selected_cluster.proxy_hostname.as_str());
         if let Err(e) = result {
-            log::error!("Failed to set host header: {e}");
+            log::error!("[{}] Failed to set host header: {e}", ctx.request_id);
         }
 
         // sometimes we need to set the request path to the cluster path
@@ -72,64 +358,1573 @@ pub trait ProxyBase: Send + Sync + ProxyHttp {
             req.set_uri(new_uri.as_str().parse().unwrap());
         }
 
-        let proxy_to = HttpPeer::new(
-            selected_cluster.proxy_addr.as_str(),
-            selected_cluster.proxy_tls,
-            selected_cluster.proxy_hostname.clone(),
-        );
-        let mut peer = Box::new(proxy_to);
-        peer.options = DEFAULT_PEER_OPTIONS;
+        // connect to the address resolved by the cluster, hostnames are re-resolved periodically,
+        // fall back to resolving the configured address if the cluster has none yet
+        let resolved_addr = cluster
+            .and_then(|cluster| cluster.backends().get_backend().iter().next().cloned())
+            .and_then(|backend| backend.addr.as_inet().cloned());
+        let mut peer = Box::new(node_peer(selected_cluster, resolved_addr));
+        if let Some(method_timeout) = ctx.method_timeout {
+            peer.options.read_timeout = Some(method_timeout);
+        }
 
         // log the selected peer
-        debug!("Selected peer: {peer}");
+        debug!("[{}] Selected peer: {peer}", ctx.request_id);
         Ok(peer)
     }
 
+    /// Whether the jsonrpc request bodies are read to rewrite their methods or ids
+    fn rewrites_request_body(&self) -> bool {
+        let options = self.get_proxy_options();
+        self.get_protocol() == "jsonrpc" && (!options.method_rewrites.is_empty() || options.rewrite_jsonrpc_ids)
+    }
+
+    async fn upstream_request_filter(
+        &self,
+        _session: &mut Session,
+        upstream_request: &mut RequestHeader,
+        ctx: &mut ProxyCtx,
+    ) -> Result<()> {
+        ctx.upstream_start = Some(Instant::now());
+
+        // a rewritten body changes its length
+        if let Some(rewritten) = ctx.rewritten_request_body.as_ref() {
+            upstream_request.insert_header("content-length", rewritten.len().to_string())?;
+        }
+        // the client ids are restored in the response body, which must not be compressed
+        if !ctx.jsonrpc_ids.is_empty() {
+            upstream_request.remove_header("accept-encoding");
+        }
+        Ok(())
+    }
+
+    async fn request_body_filter(
+        &self,
+        _session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut ProxyCtx,
+    ) -> Result<()> {
+        // height queries are small and come in a single chunk, a split one is just missed
+        if self.get_chain_state().is_some() && self.get_proxy_options().passive_height && !ctx.passive_height {
+            ctx.passive_height = body.as_deref().is_some_and(is_height_request);
+        }
+
+        // the whole body was read before, it comes in a single chunk, retries included
+        if let Some(rewritten) = ctx.rewritten_request_body.as_ref() {
+            *body = Some(if end_of_stream { rewritten.clone() } else { Bytes::new() });
+        }
+        Ok(())
+    }
+
+    async fn response_filter(
+        &self,
+        session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut ProxyCtx,
+    ) -> Result<()> {
+        // retry a retryable status on another node before anything is sent to the client
+        let status = upstream_response.status.as_u16();
+        if status == RATE_LIMITED_STATUS {
+            let retry_after = upstream_response.headers.get(http::header::RETRY_AFTER);
+            ctx.retry_after = max_retry_after(ctx.retry_after, retry_after);
+        }
+        if self.retries_on_status(session, status, ctx) {
+            ctx.error_body = None;
+            let mut e = Error::explain(HTTPStatus(status), "retryable upstream status");
+            e.set_retry(true);
+            return Err(e);
+        }
+
+        // every node rate limited the request, the client waits for the one available the latest
+        if let (RATE_LIMITED_STATUS, Some(retry_after @ 1..)) = (status, ctx.retry_after) {
+            upstream_response.insert_header(http::header::RETRY_AFTER, retry_after.to_string())?;
+        }
+
+        // expose the time spent waiting for the upstream, to tell it apart from the proxy latency
+        if self.get_proxy_options().server_timing {
+            if let Some(upstream_start) = ctx.upstream_start {
+                upstream_response.insert_header("server-timing", server_timing(upstream_start.elapsed()))?;
+            }
+        }
+
+        // gzip an uncompressed response for a client accepting it
+        if self.get_proxy_options().compress_responses {
+            let req = session.req_header();
+            if accepts_gzip(req.headers.get(http::header::ACCEPT_ENCODING))
+                && compresses_response(upstream_response, req.method.as_str())
+            {
+                ctx.gzip = Some(GzipStream::start(upstream_response)?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the response status is retried on another node, only for idempotent requests
+    /// whose body can be replayed, the node is then marked as tried
+    fn retries_on_status(&self, session: &Session, status: u16, ctx: &mut ProxyCtx) -> bool {
+        let options = self.get_proxy_options();
+        if !retryable_status(status, &options.retry_on_status) || session.as_downstream().retry_buffer_truncated() {
+            return false;
+        }
+        let request_body = session.as_downstream().get_retry_buffer();
+        let idempotent = is_idempotent_request(
+            self.get_protocol(),
+            session.req_header().method.as_str(),
+            request_body.as_deref(),
+            &options.idempotent_methods,
+        );
+        if !idempotent {
+            return false;
+        }
+        let node = match ctx.selected_node.clone() {
+            Some(node) => node,
+            None => return false,
+        };
+        ctx.tried_nodes.push(node.clone());
+        if ctx.tried_nodes.len() >= self.get_host_configs().len() {
+            // every node was tried, the last response is sent to the client
+            return false;
+        }
+
+        log::warn!(
+            "[{}] node {} of chain {} answered {status}, retrying on another node",
+            ctx.request_id,
+            redact_url(&node),
+            self.get_chain_name()
+        );
+        true
+    }
+
+    fn upstream_response_filter(
+        &self,
+        _session: &mut Session,
+        upstream_response: &mut ResponseHeader,
+        ctx: &mut ProxyCtx,
+    ) {
+        // keep the body of sampled error responses for logging
+        if self.get_error_sampler().sample(upstream_response.status.as_u16()) {
+            ctx.error_body = Some(Vec::new());
+        }
+
+        // restoring the client ids changes the body length, so it is sent chunked
+        if !ctx.jsonrpc_ids.is_empty() && upstream_response.headers.contains_key("content-length") {
+            upstream_response.remove_header("content-length");
+            if let Err(e) = upstream_response.insert_header("transfer-encoding", "chunked") {
+                log::error!("[{}] Failed to set transfer-encoding header: {e}", ctx.request_id);
+            }
+        }
+    }
+
+    fn upstream_response_body_filter(
+        &self,
+        _session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut ProxyCtx,
+    ) {
+        ctx.response_bytes += body.as_ref().map_or(0, |chunk| chunk.len());
+
+        if let (Some(error_body), Some(body)) = (ctx.error_body.as_mut(), body.as_ref()) {
+            let remaining = ERROR_SAMPLE_BODY_LIMIT.saturating_sub(error_body.len());
+            error_body.extend_from_slice(&body[..body.len().min(remaining)]);
+        }
+
+        // nothing past the max size is forwarded, the response is aborted in the response body filter
+        let max_response_body_bytes = self.get_proxy_options().max_response_body_bytes;
+        if limit_response_body(&mut ctx.response_body_bytes, body, max_response_body_bytes) {
+            ctx.response_body_too_large = true;
+        }
+
+        // read the height of the selected node from the response to a height query, supplementing the health check
+        if ctx.passive_height {
+            if let Some(chunk) = body.as_ref() {
+                let remaining = PASSIVE_HEIGHT_BODY_LIMIT.saturating_sub(ctx.passive_height_body.len());
+                ctx.passive_height_body.extend_from_slice(&chunk[..chunk.len().min(remaining)]);
+            }
+            let node = ctx.selected_node.as_ref();
+            if let (true, Some(chain_state), Some(node)) = (end_of_stream, self.get_chain_state(), node) {
+                update_passive_height(chain_state, node, &ctx.passive_height_body);
+            }
+        }
+
+        // hold the chunks back until the whole body is read to restore the client ids,
+        // a larger body is streamed through with the upstream ids
+        if !ctx.jsonrpc_ids.is_empty() && !ctx.response_body_too_large {
+            if !buffer_response_body(&mut ctx.response_body, body, JSONRPC_RESPONSE_BODY_LIMIT) {
+                log::warn!(
+                    "[{}] upstream response body of chain {} too large, its jsonrpc ids are not restored",
+                    ctx.request_id,
+                    self.get_chain_name()
+                );
+                ctx.jsonrpc_ids.clear();
+            } else if end_of_stream {
+                let response_body = std::mem::take(&mut ctx.response_body);
+                *body = match restore_jsonrpc_ids(&response_body, &ctx.jsonrpc_ids) {
+                    Some(restored) => Some(Bytes::from(restored)),
+                    None => Some(Bytes::from(response_body)),
+                };
+            }
+        }
+    }
+
+    fn response_body_filter(
+        &self,
+        _session: &mut Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+        ctx: &mut ProxyCtx,
+    ) -> Result<Option<Duration>> {
+        if ctx.response_body_too_large {
+            log::warn!(
+                "[{}] upstream response body of chain {} exceeds {:?} bytes, aborted",
+                ctx.request_id,
+                self.get_chain_name(),
+                self.get_proxy_options().max_response_body_bytes
+            );
+            return ProxyError::ResponseBodyTooLarge.explain("proxy error");
+        }
+
+        if let Some(gzip) = ctx.gzip.as_mut() {
+            let chunk = body.take().unwrap_or_default();
+            *body = Some(gzip.compress(&chunk, end_of_stream));
+        }
+        Ok(None)
+    }
+
+    fn upstream_response_trailer_filter(
+        &self,
+        _session: &mut Session,
+        upstream_trailers: &mut HeaderMap,
+        ctx: &mut ProxyCtx,
+    ) -> Result<()> {
+        if let Some(grpc_status) = upstream_trailers.get("grpc-status") {
+            ctx.grpc_status = grpc_status.to_str().ok().map(|s| s.to_string());
+        }
+        Ok(())
+    }
+
     async fn logging(
         &self,
         session: &mut Session,
-        _e: Option<&Error>,
-        _: &mut Self::CTX,
+        e: Option<&Error>,
+        ctx: &mut Self::CTX,
     ) {
         let response_code = session
             .response_written()
             .map_or(0, |resp| resp.status.as_u16());
 
+        // grpc-status is in the trailers, or in the headers for trailers-only responses
+        let grpc_status = ctx.grpc_status.clone().or_else(|| {
+            session
+                .response_written()
+                .and_then(|resp| resp.headers.get("grpc-status"))
+                .and_then(|v| v.to_str().ok())
+                .map(|s| s.to_string())
+        });
+
         let session = session.as_downstream();
         let req = session.req_header();
-        if let Some(host) = req.headers.get("host") {
-            let host = host.to_str().unwrap_or("unknown");
+        // the node actually proxied to, not the host sent by the client
+        let host = result_host(ctx).to_string();
+        let host = host.as_str();
+
+        inc_proxy_result_counter(
+            self.get_chain_name(),
+            host,
+            response_code.to_string().as_str(),
+            req.method.as_str(),
+        );
+
+        // a request failing to proxy or answered with a 5xx counts against the node, by its uri as the other
+        // node series, the nodes of a provider sharing a hostname may differ
+        let success = e.is_none() && response_code > 0 && response_code < 500;
+        if let Some(node) = ctx.selected_node.as_deref() {
+            let ratio = self.get_success_ratio_window().record(node, success);
+            set_node_success_ratio_gauge(self.get_chain_name(), node, ratio);
+        }
+
+        // failures may be fast, only successful requests tell how responsive a node is
+        if let (true, Some(node), Some(start)) = (success, ctx.selected_node.as_ref(), ctx.start) {
+            self.get_latency_ewma().record(node, start.elapsed());
+        }
 
-            inc_proxy_result_counter(
+        // read from the client once, a retry forwards the same body again
+        inc_request_bytes(self.get_chain_name(), host, session.body_bytes_read());
+        inc_response_bytes(self.get_chain_name(), host, ctx.response_bytes);
+
+        if let Some(grpc_status) = grpc_status {
+            inc_grpc_result_counter(self.get_chain_name(), host, grpc_status.as_str());
+        }
+
+        if let (Some(sla), Some(start)) = (self.get_proxy_options().sla, ctx.start) {
+            observe_sla(self.get_chain_name(), host, start.elapsed(), sla);
+        }
+
+        if let Some(error_body) = ctx.error_body.take() {
+            log::warn!(
+                "[{}] sampled upstream error, chain: {}, host: {}, status: {}, method: {}, body: {}",
+                ctx.request_id,
                 self.get_chain_name(),
                 host,
-                response_code.to_string().as_str(),
-                req.method.as_str(),
+                response_code,
+                req.method,
+                String::from_utf8_lossy(&error_body)
             );
         }
     }
+
+    async fn fail_to_proxy(&self, session: &mut Session, e: &Error, ctx: &mut ProxyCtx) -> u16 {
+        let code = failure_status(e);
+        if code == 0 {
+            return 0;
+        }
+
+        // the nodes left after the rate limited ones aren't eligible, the client is told when to come back
+        if let (Some(retry_after), Some(ProxyError::NoEligibleCluster)) = (ctx.retry_after, ProxyError::from_error(e)) {
+            let resp = rate_limited_response(retry_after);
+            if let Err(write_err) = session.write_response_header(Box::new(resp), true).await {
+                log::error!("[{}] Failed to write rate limited response: {write_err}", ctx.request_id);
+            }
+            return RATE_LIMITED_STATUS;
+        }
+
+        // jsonrpc clients may retry any non-200 aggressively, answer a jsonrpc error object instead
+        let options = self.get_proxy_options();
+        if code >= 500 && self.get_protocol() == "jsonrpc" && options.jsonrpc_error_response {
+            // a body too large to be read before is still unread when no upstream could be selected
+            let ids = match ctx.request_jsonrpc_ids.take() {
+                Some(ids) => ids,
+                None => jsonrpc_request_ids(&read_request_body(session, JSONRPC_REQUEST_BODY_LIMIT).await),
+            };
+            let (resp, body) = jsonrpc_error_response(&ids, JSONRPC_UNAVAILABLE_CODE, JSONRPC_UNAVAILABLE_MESSAGE);
+
+            let result = match session.write_response_header(Box::new(resp), false).await {
+                Ok(_) => session.write_response_body(Some(body), true).await,
+                Err(write_err) => Err(write_err),
+            };
+            if let Err(write_err) = result {
+                log::error!("[{}] Failed to write jsonrpc error response: {write_err}", ctx.request_id);
+            }
+            return 200;
+        }
+
+        let code = no_upstream_status(e, code, options.no_upstream_status_code);
+        if let Err(write_err) = session.respond_error(code).await {
+            log::error!("[{}] Failed to write error response: {write_err}", ctx.request_id);
+        }
+        code
+    }
+
     async fn get_eligible_clusters(&self, session: &mut Session) -> Result<HashMap<i32, Vec<&ChainProxyConfig>>>;
     fn get_protocol(&self) -> &str;
 
     fn get_special_method_configs(&self) -> &Vec<SpecialMethodConfig>;
 
+    fn get_host_configs(&self) -> &Vec<ChainProxyConfig>;
+
+    /// Whether the request is routed by a special method config
+    fn is_special_method(&self, session: &Session) -> bool {
+        special_method_header(session)
+            .and_then(|method| special_method_config(self.get_special_method_configs(), method))
+            .is_some()
+    }
+
+    fn get_proxy_options(&self) -> &ProxyOptions;
+
+    fn get_sticky_selection(&self) -> &StickySelection;
+
+    fn get_warmup_ramp(&self) -> &WarmupRamp;
+
+    fn get_error_sampler(&self) -> &ErrorSampler;
+
+    fn get_success_ratio_window(&self) -> &SuccessRatioWindow;
+
+    fn get_latency_ewma(&self) -> &LatencyEwma;
+
+    /// The per-client limit of requests in flight, None if the app doesn't limit clients
+    fn get_client_limiter(&self) -> Option<&ClientLimiter> {
+        None
+    }
+
+    /// The concurrency limit of the chain with its request queue, None if the app doesn't limit the requests
+    fn get_concurrency_limiter(&self) -> Option<&ConcurrencyLimiter> {
+        None
+    }
+
+    /// The maintenance of the chain while it is enabled, None if the requests are proxied
+    fn get_maintenance(&self) -> Option<Maintenance> {
+        None
+    }
+
+    /// The block heights of the nodes, None if the app doesn't track them
+    fn get_chain_state(&self) -> Option<&Arc<Mutex<ChainState>>> {
+        None
+    }
+
     async fn get_clusters_by_special_method(&self, session: &mut Session) -> Option<Result<HashMap<i32, Vec<&ChainProxyConfig>>>> {
-        let request_headers = session.as_downstream().req_header();
-        if !self.get_special_method_configs().is_empty() && request_headers.headers.contains_key("X-Proxy-Jsonrpc-Method") {
-            let method = request_headers.headers.get("X-Proxy-Jsonrpc-Method").unwrap();
-            let method = method.to_str().unwrap();
-
-            for config in self.get_special_method_configs().iter() {
-                if config.method_name == method {
-                    let mut clusters_by_priority: HashMap<i32, Vec<&ChainProxyConfig>> = HashMap::new();
-                    for config in config.nodes.iter() {
-                        clusters_by_priority.entry(config.priority).or_insert_with(Vec::new).push(config);
-                    }
-
-                    return Some(Ok(clusters_by_priority));
-                }
+        let method = special_method_header(session)?;
+        clusters_by_special_method(self.get_special_method_configs(), method).map(Ok)
+    }
+}
+
+/// The jsonrpc method of the request routed by the special method configs
+pub(crate) fn special_method_header(session: &Session) -> Option<&str> {
+    session
+        .as_downstream()
+        .req_header()
+        .headers
+        .get("X-Proxy-Jsonrpc-Method")
+        .and_then(|method| method.to_str().ok())
+}
+
+/// The special method config of the method, None if the method is not a special one
+pub(crate) fn special_method_config<'a>(
+    special_method_configs: &'a [SpecialMethodConfig],
+    method: &str,
+) -> Option<&'a SpecialMethodConfig> {
+    special_method_configs.iter().find(|config| config.method_name == method)
+}
+
+/// The nodes of the special method config of the method by priority, None if the method is not a special one
+pub(crate) fn clusters_by_special_method<'a>(
+    special_method_configs: &'a [SpecialMethodConfig],
+    method: &str,
+) -> Option<HashMap<i32, Vec<&'a ChainProxyConfig>>> {
+    let config = special_method_config(special_method_configs, method)?;
+    let mut clusters_by_priority: HashMap<i32, Vec<&ChainProxyConfig>> = HashMap::new();
+    for config in config.nodes.iter() {
+        clusters_by_priority.entry(config.priority).or_default().push(config);
+    }
+    Some(clusters_by_priority)
+}
+
+// max bytes of the request body read to find the jsonrpc id of a failed request
+const JSONRPC_REQUEST_BODY_LIMIT: usize = 1024 * 1024;
+
+// jsonrpc server error code and message answered when no upstream is available
+const JSONRPC_UNAVAILABLE_CODE: i64 = -32000;
+const JSONRPC_UNAVAILABLE_MESSAGE: &str = "no upstream available";
+
+// jsonrpc error code answered to a malformed request
+const JSONRPC_INVALID_REQUEST_CODE: i64 = -32600;
+
+// max bytes of a request body validated, the downstream buffers this much to forward the body after reading it
+const JSONRPC_VALIDATION_BODY_LIMIT: usize = 64 * 1024;
+
+/// Read the whole request body into the downstream retry buffer, so it is still forwarded upstream,
+/// None if the body has no content-length or is too large to be buffered, it is not read then
+async fn read_retry_buffered_body(session: &mut Session) -> Option<Vec<u8>> {
+    let content_length = session
+        .as_downstream()
+        .req_header()
+        .headers
+        .get("content-length")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok())?;
+    if content_length > JSONRPC_VALIDATION_BODY_LIMIT {
+        return None;
+    }
+
+    session.as_downstream_mut().enable_retry_buffering();
+    Some(read_request_body(session, JSONRPC_VALIDATION_BODY_LIMIT).await)
+}
+
+/// Check the body is a jsonrpc 2.0 request, or a non-empty batch of them, with a method
+pub(crate) fn validate_jsonrpc_request(body: &[u8]) -> std::result::Result<(), &'static str> {
+    let request: serde_json::Value = serde_json::from_slice(body).map_err(|_| "request body is not valid json")?;
+
+    let validate_call = |call: &serde_json::Value| {
+        if !call.is_object() {
+            return Err("request is not an object");
+        }
+        if call.get("jsonrpc").and_then(|v| v.as_str()) != Some("2.0") {
+            return Err("jsonrpc version must be 2.0");
+        }
+        if !call.get("method").is_some_and(|m| m.is_string()) {
+            return Err("method must be a string");
+        }
+        Ok(())
+    };
+    match &request {
+        serde_json::Value::Array(calls) if calls.is_empty() => Err("empty batch"),
+        serde_json::Value::Array(calls) => calls.iter().try_for_each(validate_call),
+        call => validate_call(call),
+    }
+}
+
+/// Keep the eligible clusters of the route with the longest prefix matching the path,
+/// all of them when no route matches, None when none of the route nodes is eligible
+pub(crate) fn route_by_path<'a>(
+    routes: &[PathRoute],
+    path: &str,
+    clusters_by_priority: HashMap<i32, Vec<&'a ChainProxyConfig>>,
+) -> Option<HashMap<i32, Vec<&'a ChainProxyConfig>>> {
+    let route = routes
+        .iter()
+        .filter(|route| route.matches(path))
+        .max_by_key(|route| route.path_prefix().trim_end_matches('/').len());
+    let route = match route {
+        Some(route) => route,
+        None => return Some(clusters_by_priority),
+    };
+
+    let routed: HashMap<i32, Vec<&ChainProxyConfig>> = clusters_by_priority
+        .into_iter()
+        .map(|(priority, clusters)| {
+            let clusters: Vec<&ChainProxyConfig> = clusters
+                .into_iter()
+                .filter(|c| route.nodes().contains(&c.proxy_uri))
+                .collect();
+            (priority, clusters)
+        })
+        .filter(|(_, clusters)| !clusters.is_empty())
+        .collect();
+
+    if routed.is_empty() {
+        None
+    } else {
+        Some(routed)
+    }
+}
+
+/// Server-Timing header value of the time spent waiting for the upstream, in milliseconds
+pub(crate) fn server_timing(upstream: Duration) -> String {
+    format!("upstream;dur={:.1}", upstream.as_secs_f64() * 1000.0)
+}
+
+/// Remove the nodes already tried by the request from the eligible clusters,
+/// all of them are kept if every eligible node was tried
+pub(crate) fn exclude_tried_nodes<'a>(
+    tried_nodes: &[String],
+    clusters_by_priority: HashMap<i32, Vec<&'a ChainProxyConfig>>,
+) -> HashMap<i32, Vec<&'a ChainProxyConfig>> {
+    if tried_nodes.is_empty() {
+        return clusters_by_priority;
+    }
+
+    let untried: HashMap<i32, Vec<&ChainProxyConfig>> = clusters_by_priority
+        .iter()
+        .map(|(priority, clusters)| {
+            let clusters: Vec<&ChainProxyConfig> = clusters
+                .iter()
+                .filter(|c| !tried_nodes.contains(&c.proxy_uri))
+                .copied()
+                .collect();
+            (*priority, clusters)
+        })
+        .filter(|(_, clusters)| !clusters.is_empty())
+        .collect();
+
+    if untried.is_empty() {
+        clusters_by_priority
+    } else {
+        untried
+    }
+}
+
+// status of a rate limited response
+const RATE_LIMITED_STATUS: u16 = 429;
+
+/// Whether an upstream status is retried on another node, the configured ones and rate limited responses
+pub(crate) fn retryable_status(status: u16, retry_on_status: &[u16]) -> bool {
+    status == RATE_LIMITED_STATUS || retry_on_status.contains(&status)
+}
+
+/// The seconds of a Retry-After header, its http date form isn't supported
+pub(crate) fn retry_after_secs(value: Option<&http::HeaderValue>) -> Option<u64> {
+    value?.to_str().ok()?.trim().parse().ok()
+}
+
+/// The largest Retry-After of the rate limited responses so far and the one of a new rate limited response,
+/// a response without a valid Retry-After counts as 0
+pub(crate) fn max_retry_after(current: Option<u64>, value: Option<&http::HeaderValue>) -> Option<u64> {
+    current.max(Some(retry_after_secs(value).unwrap_or(0)))
+}
+
+/// The empty 429 response to a request rate limited by every node, with the largest Retry-After of them
+pub(crate) fn rate_limited_response(retry_after: u64) -> ResponseHeader {
+    let mut resp = ResponseHeader::build(RATE_LIMITED_STATUS, Some(2)).unwrap();
+    if retry_after > 0 {
+        resp.insert_header(http::header::RETRY_AFTER, retry_after.to_string()).unwrap();
+    }
+    resp.insert_header(http::header::CONTENT_LENGTH, "0").unwrap();
+    resp
+}
+
+/// Whether a request is safe to retry, every jsonrpc method of the body, batches included,
+/// or the http method for other protocols, must be one of the idempotent methods
+pub(crate) fn is_idempotent_request(
+    protocol: &str,
+    http_method: &str,
+    body: Option<&[u8]>,
+    idempotent_methods: &[String],
+) -> bool {
+    if protocol != "jsonrpc" {
+        return idempotent_methods.iter().any(|method| method.eq_ignore_ascii_case(http_method));
+    }
+
+    let request: serde_json::Value = match body.and_then(|body| serde_json::from_slice(body).ok()) {
+        Some(request) => request,
+        None => return false,
+    };
+    let calls = match request {
+        serde_json::Value::Array(calls) if !calls.is_empty() => calls,
+        serde_json::Value::Array(_) => return false,
+        call => vec![call],
+    };
+    calls.iter().all(|call| {
+        call.get("method")
+            .and_then(|method| method.as_str())
+            .is_some_and(|method| idempotent_methods.iter().any(|m| m == method))
+    })
+}
+
+/// The upstream read timeout of a jsonrpc request, the longest of the timeouts of its methods, batches included,
+/// None to keep the timeout of the node if any of its methods has no timeout
+pub(crate) fn method_timeout(
+    body: &[u8],
+    method_timeouts: &HashMap<String, Duration>,
+    method_rewrites: &HashMap<String, String>,
+) -> Option<Duration> {
+    let request: serde_json::Value = serde_json::from_slice(body).ok()?;
+    let calls = match request {
+        serde_json::Value::Array(calls) => calls,
+        call => vec![call],
+    };
+    calls
+        .iter()
+        .map(|call| {
+            let method = call.get("method")?.as_str()?;
+            timeout_of_method(method, method_timeouts, method_rewrites)
+        })
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .max()
+}
+
+/// The timeout of a jsonrpc method, looked up by the client method and then by its rewritten one
+pub(crate) fn timeout_of_method(
+    method: &str,
+    method_timeouts: &HashMap<String, Duration>,
+    method_rewrites: &HashMap<String, String>,
+) -> Option<Duration> {
+    method_timeouts
+        .get(method)
+        .or_else(|| method_timeouts.get(method_rewrites.get(method)?))
+        .copied()
+}
+
+// host label of the requests failing before a node is selected
+pub(crate) const NO_UPSTREAM_HOST: &str = "none";
+
+/// The peer of a node at its resolved address, or else its configured one, with the tls server name of the node
+pub(crate) fn node_peer(config: &ChainProxyConfig, resolved_addr: Option<SocketAddr>) -> HttpPeer {
+    let mut peer = match resolved_addr {
+        Some(addr) => HttpPeer::new(addr, config.proxy_tls, config.proxy_sni.clone()),
+        None => HttpPeer::new(config.proxy_addr.as_str(), config.proxy_tls, config.proxy_sni.clone()),
+    };
+    peer.options = DEFAULT_PEER_OPTIONS;
+    if let Some(read_timeout) = config.upstream_read_timeout {
+        peer.options.read_timeout = Some(read_timeout);
+    }
+    if let Some(alpn) = config.alpn {
+        peer.options.alpn = match alpn {
+            Alpn::H1 => ALPN::H1,
+            Alpn::H2 => ALPN::H2,
+            Alpn::H2H1 => ALPN::H2H1,
+        };
+    }
+    peer
+}
+
+/// Host label of the request metrics, the hostname of the selected node
+pub(crate) fn result_host(ctx: &ProxyCtx) -> &str {
+    ctx.upstream_hostname.as_deref().unwrap_or(NO_UPSTREAM_HOST)
+}
+
+/// The status answered for a failed request, same as the pingora default, 0 when the downstream is gone
+pub(crate) fn failure_status(e: &Error) -> u16 {
+    match e.etype() {
+        HTTPStatus(code) => *code,
+        _ => match e.esource() {
+            ErrorSource::Upstream => 502,
+            ErrorSource::Downstream => match e.etype() {
+                ErrorType::WriteError | ErrorType::ReadError | ErrorType::ConnectionClosed => 0,
+                _ => 400,
+            },
+            ErrorSource::Internal | ErrorSource::Unset => 500,
+        },
+    }
+}
+
+/// Replace the status of a request no upstream was available for with the configured no upstream status,
+/// no node was eligible or none could be connected to, other failures keep their status
+pub(crate) fn no_upstream_status(e: &Error, code: u16, no_upstream_status_code: Option<u16>) -> u16 {
+    let no_upstream = matches!(
+        ProxyError::from_error(e),
+        Some(ProxyError::NoEligibleCluster) | Some(ProxyError::NoBlockNumber)
+    ) || matches!(
+        e.etype(),
+        ErrorType::ConnectTimedout
+            | ErrorType::ConnectRefused
+            | ErrorType::ConnectNoRoute
+            | ErrorType::ConnectError
+            | ErrorType::ConnectProxyFailure
+    );
+    match no_upstream_status_code {
+        Some(configured) if no_upstream => configured,
+        _ => code,
+    }
+}
+
+/// Read the rest of the request body, up to `limit` bytes
+async fn read_request_body(session: &mut Session, limit: usize) -> Vec<u8> {
+    let mut body = Vec::new();
+    while body.len() < limit {
+        match session.read_request_body().await {
+            Ok(Some(chunk)) => body.extend_from_slice(&chunk),
+            Ok(None) => break,
+            Err(e) => {
+                debug!("Failed to read request body: {e}");
+                break;
             }
         }
+    }
+    body
+}
+
+/// Count a chunk of the upstream response body, the chunk is dropped and true returned
+/// once the body exceeds the limit
+pub(crate) fn limit_response_body(received: &mut usize, body: &mut Option<Bytes>, limit: Option<usize>) -> bool {
+    let (limit, chunk) = match (limit, body.as_ref()) {
+        (Some(limit), Some(chunk)) => (limit, chunk),
+        _ => return false,
+    };
+
+    *received += chunk.len();
+    if *received > limit {
+        *body = Some(Bytes::new());
+        return true;
+    }
+    false
+}
+
+// jsonrpc methods whose result is the block height of the node
+const PASSIVE_HEIGHT_METHODS: [&str; 1] = ["eth_blockNumber"];
+
+// max bytes of an upstream response body buffered to restore the client jsonrpc ids
+const JSONRPC_RESPONSE_BODY_LIMIT: usize = 4 * 1024 * 1024;
+
+/// Hold a response chunk back until the whole body is read, false once the body exceeds the limit,
+/// the chunks held back are then forwarded with the current one
+pub(crate) fn buffer_response_body(buffered: &mut Vec<u8>, body: &mut Option<Bytes>, limit: usize) -> bool {
+    if let Some(chunk) = body.take() {
+        buffered.extend_from_slice(&chunk);
+    }
+    if buffered.len() > limit {
+        *body = Some(Bytes::from(std::mem::take(buffered)));
+        return false;
+    }
+    true
+}
+
+// max bytes of a height query response kept, larger ones are not height responses
+const PASSIVE_HEIGHT_BODY_LIMIT: usize = 1024;
+
+/// Whether the request body is a single jsonrpc call of a block height method
+pub(crate) fn is_height_request(body: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Value>(body)
+        .ok()
+        .and_then(|request| request.get("method")?.as_str().map(|m| PASSIVE_HEIGHT_METHODS.contains(&m)))
+        .unwrap_or(false)
+}
+
+/// Update the height of the node from the response to a height query, return the height if one was read.
+/// Errors are expected in live traffic and silently ignored.
+pub(crate) fn update_passive_height(chain_state: &Mutex<ChainState>, node: &str, response: &[u8]) -> Option<u64> {
+    let response: serde_json::Value = serde_json::from_slice(response).ok()?;
+    let height = response
+        .get("result")?
+        .as_str()?
+        .strip_prefix("0x")
+        .and_then(|hex| u64::from_str_radix(hex, 16).ok())?;
+
+    let mut state = chain_state.lock().unwrap();
+    state.update_block_number(node, height);
+    set_node_height_gauge(&state.chain_name, node, height);
+    Some(height)
+}
+
+/// Rewrite the methods of a jsonrpc request body and, if enabled, its ids, which stay unique upstream across clients,
+/// returns the rewritten body, None if unchanged, and the client ids by the forwarded ones
+pub(crate) fn rewrite_jsonrpc_request(
+    body: &[u8],
+    method_rewrites: &HashMap<String, String>,
+    rewrite_ids: bool,
+) -> (Option<Vec<u8>>, HashMap<u64, serde_json::Value>) {
+    let rewritten = rewrite_jsonrpc_methods(body, method_rewrites);
+    if !rewrite_ids {
+        return (rewritten, HashMap::new());
+    }
+    match rewrite_jsonrpc_ids(rewritten.as_deref().unwrap_or(body), &NEXT_JSONRPC_ID) {
+        Some((rewritten, ids)) => (Some(rewritten), ids),
+        None => (rewritten, HashMap::new()),
+    }
+}
+
+/// Rewrite the method of a jsonrpc request, or of every call of a batch, by the rewrites map,
+/// None if no method is rewritten so the body is forwarded untouched
+pub(crate) fn rewrite_jsonrpc_methods(body: &[u8], rewrites: &HashMap<String, String>) -> Option<Vec<u8>> {
+    let mut request: serde_json::Value = serde_json::from_slice(body).ok()?;
+
+    let mut rewritten = false;
+    let mut rewrite = |call: &mut serde_json::Value| {
+        if let Some(serde_json::Value::String(method)) = call.get_mut("method") {
+            if let Some(canonical) = rewrites.get(method.as_str()) {
+                *method = canonical.clone();
+                rewritten = true;
+            }
+        }
+    };
+    match &mut request {
+        serde_json::Value::Array(calls) => calls.iter_mut().for_each(&mut rewrite),
+        call => rewrite(call),
+    }
+
+    if rewritten {
+        serde_json::to_vec(&request).ok()
+    } else {
         None
     }
 }
+
+// next jsonrpc id forwarded upstream when ids are rewritten, unique within the process
+static NEXT_JSONRPC_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Rewrite the id of a jsonrpc request, or of every call of a batch, to a unique one taken from the counter,
+/// returns the rewritten body and the client ids by the forwarded ones, None if there is no id to rewrite.
+/// Notifications have no id and are left untouched.
+pub(crate) fn rewrite_jsonrpc_ids(
+    body: &[u8],
+    next_id: &AtomicU64,
+) -> Option<(Vec<u8>, HashMap<u64, serde_json::Value>)> {
+    let mut request: serde_json::Value = serde_json::from_slice(body).ok()?;
+
+    let mut ids = HashMap::new();
+    let mut rewrite = |call: &mut serde_json::Value| {
+        if let Some(id) = call.get_mut("id") {
+            let forwarded = next_id.fetch_add(1, Ordering::Relaxed);
+            ids.insert(forwarded, std::mem::replace(id, serde_json::Value::from(forwarded)));
+        }
+    };
+    match &mut request {
+        serde_json::Value::Array(calls) => calls.iter_mut().for_each(&mut rewrite),
+        call => rewrite(call),
+    }
+
+    if ids.is_empty() {
+        return None;
+    }
+    Some((serde_json::to_vec(&request).ok()?, ids))
+}
+
+/// Restore the client ids in a jsonrpc response, or in every response of a batch,
+/// None if the body is not json so it is forwarded untouched
+pub(crate) fn restore_jsonrpc_ids(body: &[u8], ids: &HashMap<u64, serde_json::Value>) -> Option<Vec<u8>> {
+    let mut response: serde_json::Value = serde_json::from_slice(body).ok()?;
+
+    let restore = |call: &mut serde_json::Value| {
+        if let Some(id) = call.get_mut("id") {
+            if let Some(original) = id.as_u64().and_then(|forwarded| ids.get(&forwarded)) {
+                *id = original.clone();
+            }
+        }
+    };
+    match &mut response {
+        serde_json::Value::Array(calls) => calls.iter_mut().for_each(restore),
+        call => restore(call),
+    }
+
+    serde_json::to_vec(&response).ok()
+}
+
+/// The client ids of a jsonrpc request, a batch has the ids of its calls, notifications aside,
+/// a request without a parsable id has a null id
+pub(crate) fn jsonrpc_request_ids(request_body: &[u8]) -> JsonrpcRequestIds {
+    let request = serde_json::from_slice::<serde_json::Value>(request_body).unwrap_or_default();
+    if let serde_json::Value::Array(calls) = &request {
+        let ids: Vec<_> = calls.iter().filter_map(|call| call.get("id").cloned()).collect();
+        if !ids.is_empty() {
+            return JsonrpcRequestIds::Batch(ids);
+        }
+    }
+    JsonrpcRequestIds::Single(request.get("id").cloned().unwrap_or_default())
+}
+
+/// Build a 200 response carrying a jsonrpc error object, or an array of them for a batch,
+/// the ids of the request are echoed back
+pub(crate) fn jsonrpc_error_response(ids: &JsonrpcRequestIds, code: i64, message: &str) -> (ResponseHeader, Bytes) {
+    let error = |id: &serde_json::Value| {
+        serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {
+                "code": code,
+                "message": message,
+            },
+        })
+    };
+    let body = match ids {
+        JsonrpcRequestIds::Single(id) => error(id),
+        JsonrpcRequestIds::Batch(ids) => serde_json::Value::Array(ids.iter().map(error).collect()),
+    };
+    let body = Bytes::from(body.to_string());
+
+    let mut resp = ResponseHeader::build(200, Some(2)).unwrap();
+    resp.insert_header("content-type", "application/json").unwrap();
+    resp.insert_header("content-length", body.len().to_string()).unwrap();
+    (resp, body)
+}
+
+// max length of a client provided X-Request-Id, longer ones are replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Return the X-Request-Id of the request, a new id is generated and set on the request if it is absent or invalid
+pub(crate) fn ensure_request_id(req: &mut RequestHeader) -> String {
+    let incoming = req
+        .headers
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic()))
+        .map(|id| id.to_string());
+    if let Some(request_id) = incoming {
+        return request_id;
+    }
+
+    let request_id = format!("{:032x}", rand::random::<u128>());
+    if let Err(e) = req.insert_header("x-request-id", request_id.as_str()) {
+        log::error!("Failed to set x-request-id header: {e}");
+    }
+    request_id
+}
+
+/// Forward or strip the client ip in the X-Forwarded-For header of the upstream request
+/// * Some(true): append the client ip to X-Forwarded-For
+/// * Some(false): strip any incoming X-Forwarded-For
+/// * None: leave the header untouched
+pub(crate) fn apply_forwarded_for(req: &mut RequestHeader, forward_client_ip: Option<bool>, client_ip: Option<&str>) {
+    match forward_client_ip {
+        Some(true) => {
+            let client_ip = match client_ip {
+                Some(ip) => ip,
+                None => return,
+            };
+
+            let forwarded_for = match req.headers.get("x-forwarded-for").and_then(|v| v.to_str().ok()) {
+                Some(existing) if !existing.is_empty() => format!("{existing}, {client_ip}"),
+                _ => client_ip.to_string(),
+            };
+
+            if let Err(e) = req.insert_header("x-forwarded-for", forwarded_for) {
+                log::error!("Failed to set x-forwarded-for header: {e}");
+            }
+        }
+        Some(false) => {
+            req.remove_header("x-forwarded-for");
+        }
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_id_generated() {
+        let mut req = RequestHeader::build("POST", b"/", None).unwrap();
+        let request_id = ensure_request_id(&mut req);
+
+        assert_eq!(request_id.len(), 32);
+        assert!(request_id.chars().all(|c| c.is_ascii_hexdigit()));
+        // the generated id is forwarded upstream and stays the same for the request
+        assert_eq!(req.headers.get("x-request-id").unwrap(), request_id.as_str());
+        assert_eq!(ensure_request_id(&mut req), request_id);
+
+        // every request gets its own id
+        let mut other = RequestHeader::build("POST", b"/", None).unwrap();
+        assert_ne!(ensure_request_id(&mut other), request_id);
+    }
+
+    #[test]
+    fn test_request_id_incoming() {
+        let mut req = RequestHeader::build("POST", b"/", None).unwrap();
+        req.insert_header("x-request-id", "client-trace-1").unwrap();
+        assert_eq!(ensure_request_id(&mut req), "client-trace-1");
+        assert_eq!(req.headers.get("x-request-id").unwrap(), "client-trace-1");
+
+        // an invalid incoming id is replaced
+        let mut req = RequestHeader::build("POST", b"/", None).unwrap();
+        req.insert_header("x-request-id", "a".repeat(MAX_REQUEST_ID_LEN + 1)).unwrap();
+        let request_id = ensure_request_id(&mut req);
+        assert_eq!(request_id.len(), 32);
+        assert_eq!(req.headers.get("x-request-id").unwrap(), request_id.as_str());
+    }
+
+    #[test]
+    fn test_limit_response_body() {
+        let mut received = 0;
+
+        let mut body = Some(Bytes::from_static(b"0123456789"));
+        assert!(!limit_response_body(&mut received, &mut body, Some(16)));
+        assert_eq!(body.as_deref(), Some(&b"0123456789"[..]));
+
+        // the chunk crossing the limit is dropped and the response terminated
+        let mut body = Some(Bytes::from_static(b"0123456789"));
+        assert!(limit_response_body(&mut received, &mut body, Some(16)));
+        assert_eq!(body.as_deref(), Some(&b""[..]));
+        assert_eq!(received, 20);
+
+        // no limit
+        let mut received = 0;
+        let mut body = Some(Bytes::from(vec![0u8; 1 << 20]));
+        assert!(!limit_response_body(&mut received, &mut body, None));
+        assert_eq!(body.map(|b| b.len()), Some(1 << 20));
+    }
+
+    #[test]
+    fn test_rewrite_jsonrpc_methods() {
+        let rewrites = HashMap::from([("eth_getBlockByNumberLegacy".to_string(), "eth_getBlockByNumber".to_string())]);
+
+        let body = br#"{"jsonrpc":"2.0","method":"eth_getBlockByNumberLegacy","params":["latest",false],"id":1}"#;
+        let rewritten = rewrite_jsonrpc_methods(body, &rewrites).unwrap();
+        let rewritten: serde_json::Value = serde_json::from_slice(&rewritten).unwrap();
+        assert_eq!(rewritten["method"], "eth_getBlockByNumber");
+        assert_eq!(rewritten["params"], serde_json::json!(["latest", false]));
+        assert_eq!(rewritten["id"], 1);
+
+        // other methods pass through untouched
+        let body = br#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":2}"#;
+        assert!(rewrite_jsonrpc_methods(body, &rewrites).is_none());
+
+        // every call of a batch is rewritten
+        let body = br#"[{"jsonrpc":"2.0","method":"eth_blockNumber","id":1},{"jsonrpc":"2.0","method":"eth_getBlockByNumberLegacy","id":2}]"#;
+        let rewritten = rewrite_jsonrpc_methods(body, &rewrites).unwrap();
+        let rewritten: serde_json::Value = serde_json::from_slice(&rewritten).unwrap();
+        assert_eq!(rewritten[0]["method"], "eth_blockNumber");
+        assert_eq!(rewritten[1]["method"], "eth_getBlockByNumber");
+
+        assert!(rewrite_jsonrpc_methods(b"not json", &rewrites).is_none());
+    }
+
+    #[test]
+    fn test_rewrite_jsonrpc_ids() {
+        let next_id = AtomicU64::new(100);
+
+        let body = br#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":"client-1"}"#;
+        let (rewritten, ids) = rewrite_jsonrpc_ids(body, &next_id).unwrap();
+        let rewritten: serde_json::Value = serde_json::from_slice(&rewritten).unwrap();
+        assert_eq!(rewritten["id"], 100);
+        assert_eq!(rewritten["method"], "eth_blockNumber");
+
+        // the client's original id is restored in the response
+        let response = br#"{"jsonrpc":"2.0","id":100,"result":"0x10"}"#;
+        let restored = restore_jsonrpc_ids(response, &ids).unwrap();
+        let restored: serde_json::Value = serde_json::from_slice(&restored).unwrap();
+        assert_eq!(restored["id"], "client-1");
+        assert_eq!(restored["result"], "0x10");
+
+        // duplicated client ids of a batch get unique ids upstream, a notification keeps having none
+        let body = br#"[{"jsonrpc":"2.0","method":"eth_blockNumber","id":1},{"jsonrpc":"2.0","method":"eth_chainId","id":1},{"jsonrpc":"2.0","method":"eth_subscribe"}]"#;
+        let (rewritten, ids) = rewrite_jsonrpc_ids(body, &next_id).unwrap();
+        let rewritten: serde_json::Value = serde_json::from_slice(&rewritten).unwrap();
+        assert_eq!(rewritten[0]["id"], 101);
+        assert_eq!(rewritten[1]["id"], 102);
+        assert!(rewritten[2].get("id").is_none());
+        assert_eq!(ids.len(), 2);
+
+        // batch responses may come back in any order
+        let response = br#"[{"jsonrpc":"2.0","id":102,"result":"0x1"},{"jsonrpc":"2.0","id":101,"result":"0x10"}]"#;
+        let restored = restore_jsonrpc_ids(response, &ids).unwrap();
+        let restored: serde_json::Value = serde_json::from_slice(&restored).unwrap();
+        assert_eq!(restored[0]["id"], 1);
+        assert_eq!(restored[0]["result"], "0x1");
+        assert_eq!(restored[1]["id"], 1);
+
+        // nothing to rewrite
+        let body = br#"{"jsonrpc":"2.0","method":"eth_subscribe"}"#;
+        assert!(rewrite_jsonrpc_ids(body, &next_id).is_none());
+        assert!(rewrite_jsonrpc_ids(b"not json", &next_id).is_none());
+        assert!(restore_jsonrpc_ids(b"not json", &ids).is_none());
+    }
+
+    #[test]
+    fn test_buffer_response_body() {
+        let mut buffered = Vec::new();
+
+        // the chunks are held back until the end of the body
+        let mut body = Some(Bytes::from_static(b"0123"));
+        assert!(buffer_response_body(&mut buffered, &mut body, 8));
+        assert!(body.is_none());
+        let mut body = Some(Bytes::from_static(b"4567"));
+        assert!(buffer_response_body(&mut buffered, &mut body, 8));
+        assert_eq!(buffered, b"01234567");
+
+        // past the limit, everything held back is forwarded
+        let mut body = Some(Bytes::from_static(b"89"));
+        assert!(!buffer_response_body(&mut buffered, &mut body, 8));
+        assert_eq!(body.as_deref(), Some(&b"0123456789"[..]));
+        assert!(buffered.is_empty());
+    }
+
+    #[test]
+    fn test_rewrite_jsonrpc_request() {
+        let rewrites = HashMap::from([("eth_getBlockByNumberLegacy".to_string(), "eth_getBlockByNumber".to_string())]);
+
+        // the methods and the ids are rewritten in one body
+        let body = br#"{"jsonrpc":"2.0","method":"eth_getBlockByNumberLegacy","params":["latest",false],"id":7}"#;
+        let (rewritten, ids) = rewrite_jsonrpc_request(body, &rewrites, true);
+        let rewritten: serde_json::Value = serde_json::from_slice(&rewritten.unwrap()).unwrap();
+        assert_eq!(rewritten["method"], "eth_getBlockByNumber");
+        assert_eq!(ids.get(&rewritten["id"].as_u64().unwrap()), Some(&serde_json::json!(7)));
+
+        // an unchanged body is forwarded as is
+        let body = br#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":7}"#;
+        let (rewritten, ids) = rewrite_jsonrpc_request(body, &rewrites, false);
+        assert!(rewritten.is_none());
+        assert!(ids.is_empty());
+        let (rewritten, ids) = rewrite_jsonrpc_request(b"not json", &rewrites, true);
+        assert!(rewritten.is_none());
+        assert!(ids.is_empty());
+    }
+
+    #[test]
+    fn test_passive_height() {
+        assert!(is_height_request(br#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":1}"#));
+        assert!(!is_height_request(br#"{"jsonrpc":"2.0","method":"eth_chainId","params":[],"id":1}"#));
+        assert!(!is_height_request(br#"[{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":1}]"#));
+        assert!(!is_height_request(b"not json"));
+
+        // a parsed response updates the chain state
+        let chain_state = Mutex::new(ChainState::new("ethereum"));
+        let response = br#"{"jsonrpc":"2.0","id":1,"result":"0x1312d00"}"#;
+        assert_eq!(update_passive_height(&chain_state, "https://node1.com", response), Some(20000000));
+        assert_eq!(chain_state.lock().unwrap().get_block_numbers().get("https://node1.com"), Some(&20000000));
+
+        // errors leave the height untouched
+        let response = br#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"header not found"}}"#;
+        assert_eq!(update_passive_height(&chain_state, "https://node1.com", response), None);
+        assert_eq!(update_passive_height(&chain_state, "https://node1.com", b"{\"jsonrpc\""), None);
+        assert_eq!(chain_state.lock().unwrap().get_block_numbers().get("https://node1.com"), Some(&20000000));
+    }
+
+    #[test]
+    fn test_jsonrpc_error_response() {
+        let request = br#"{"jsonrpc":"2.0","id":42,"method":"eth_blockNumber","params":[]}"#;
+        let ids = jsonrpc_request_ids(request);
+        let (resp, body) = jsonrpc_error_response(&ids, JSONRPC_UNAVAILABLE_CODE, JSONRPC_UNAVAILABLE_MESSAGE);
+
+        assert_eq!(resp.status.as_u16(), 200);
+        assert_eq!(resp.headers.get("content-type").unwrap(), "application/json");
+        assert_eq!(resp.headers.get("content-length").unwrap(), body.len().to_string().as_str());
+
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["jsonrpc"], "2.0");
+        assert_eq!(body["id"], 42);
+        assert_eq!(body["error"]["code"], JSONRPC_UNAVAILABLE_CODE);
+        assert_eq!(body["error"]["message"], JSONRPC_UNAVAILABLE_MESSAGE);
+        assert!(body.get("result").is_none());
+
+        // string ids are echoed as is, a request without a parsable id gets a null id
+        let ids = jsonrpc_request_ids(br#"{"jsonrpc":"2.0","id":"abc","method":"getSlot"}"#);
+        let (_, body) = jsonrpc_error_response(&ids, -32000, "error");
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["id"], "abc");
+
+        let ids = jsonrpc_request_ids(b"not json");
+        let (_, body) = jsonrpc_error_response(&ids, JSONRPC_INVALID_REQUEST_CODE, "error");
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert!(body["id"].is_null());
+        assert_eq!(body["error"]["code"], JSONRPC_INVALID_REQUEST_CODE);
+
+        // a batch gets an error for every call, notifications aside
+        let batch = br#"[{"jsonrpc":"2.0","id":1,"method":"eth_blockNumber"},{"jsonrpc":"2.0","method":"eth_subscription"},{"jsonrpc":"2.0","id":"b","method":"eth_chainId"}]"#;
+        let ids = jsonrpc_request_ids(batch);
+        assert_eq!(ids, JsonrpcRequestIds::Batch(vec![serde_json::json!(1), serde_json::json!("b")]));
+        let (_, body) = jsonrpc_error_response(&ids, JSONRPC_UNAVAILABLE_CODE, JSONRPC_UNAVAILABLE_MESSAGE);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let errors = body.as_array().unwrap();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0]["id"], 1);
+        assert_eq!(errors[1]["id"], "b");
+        assert_eq!(errors[1]["error"]["code"], JSONRPC_UNAVAILABLE_CODE);
+
+        // a batch of notifications only gets a single error
+        let ids = jsonrpc_request_ids(br#"[{"jsonrpc":"2.0","method":"eth_subscription"}]"#);
+        assert_eq!(ids, JsonrpcRequestIds::Single(serde_json::Value::Null));
+    }
+
+    #[test]
+    fn test_validate_jsonrpc_request() {
+        let valid = [
+            &br#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":1}"#[..],
+            br#"{"jsonrpc":"2.0","method":"eth_subscription"}"#,
+            br#"[{"jsonrpc":"2.0","method":"eth_blockNumber","id":1},{"jsonrpc":"2.0","method":"eth_chainId","id":2}]"#,
+        ];
+        for body in valid {
+            assert!(validate_jsonrpc_request(body).is_ok(), "{}", String::from_utf8_lossy(body));
+        }
+
+        let malformed = [
+            &b"not json"[..],
+            b"",
+            br#"{"method":"eth_blockNumber","id":1}"#,
+            br#"{"jsonrpc":"1.0","method":"eth_blockNumber","id":1}"#,
+            br#"{"jsonrpc":"2.0","id":1}"#,
+            br#"{"jsonrpc":"2.0","method":42,"id":1}"#,
+            br#"[]"#,
+            br#"[{"jsonrpc":"2.0","method":"eth_blockNumber","id":1},1]"#,
+            br#""eth_blockNumber""#,
+        ];
+        for body in malformed {
+            assert!(validate_jsonrpc_request(body).is_err(), "{}", String::from_utf8_lossy(body));
+        }
+
+        // the malformed request is answered with an invalid request error
+        let body = br#"{"jsonrpc":"2.0","id":7}"#;
+        let reason = validate_jsonrpc_request(body).unwrap_err();
+        let (resp, body) = jsonrpc_error_response(&jsonrpc_request_ids(body), JSONRPC_INVALID_REQUEST_CODE, reason);
+        assert_eq!(resp.status.as_u16(), 200);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["id"], 7);
+        assert_eq!(body["error"]["code"], -32600);
+        assert_eq!(body["error"]["message"], "method must be a string");
+    }
+
+    #[test]
+    fn test_route_by_path() {
+        let node = |uri: &str, priority: i32| ChainProxyConfig {
+            proxy_uri: uri.to_string(),
+            priority,
+            ..Default::default()
+        };
+        let api = node("https://api.example.com", 1);
+        let rosetta = node("https://rosetta.example.com", 1);
+        let rosetta_fallback = node("https://rosetta-fallback.example.com", 0);
+        let clusters_by_priority = || {
+            HashMap::from([(1, vec![&api, &rosetta]), (0, vec![&rosetta_fallback])])
+        };
+        let routes = vec![
+            PathRoute::new("/api/v1", vec!["https://api.example.com".to_string()]),
+            PathRoute::new(
+                "/rosetta",
+                vec!["https://rosetta.example.com".to_string(), "https://rosetta-fallback.example.com".to_string()],
+            ),
+        ];
+        let uris = |routed: &HashMap<i32, Vec<&ChainProxyConfig>>, priority: i32| -> Vec<String> {
+            routed.get(&priority).map_or(Vec::new(), |c| c.iter().map(|c| c.proxy_uri.clone()).collect())
+        };
+
+        // the two prefixes are served by distinct pools
+        let routed = route_by_path(&routes, "/api/v1/blocks/latest", clusters_by_priority()).unwrap();
+        assert_eq!(uris(&routed, 1), vec!["https://api.example.com"]);
+        assert!(!routed.contains_key(&0));
+
+        let routed = route_by_path(&routes, "/rosetta/network/status", clusters_by_priority()).unwrap();
+        assert_eq!(uris(&routed, 1), vec!["https://rosetta.example.com"]);
+        assert_eq!(uris(&routed, 0), vec!["https://rosetta-fallback.example.com"]);
+
+        // other paths are served by all the nodes
+        let routed = route_by_path(&routes, "/health", clusters_by_priority()).unwrap();
+        assert_eq!(uris(&routed, 1).len(), 2);
+        assert_eq!(uris(&routed, 0).len(), 1);
+
+        // the longest prefix wins
+        let mut routes = routes;
+        routes.push(PathRoute::new("/api/v1/admin", vec!["https://rosetta.example.com".to_string()]));
+        let routed = route_by_path(&routes, "/api/v1/admin/peers", clusters_by_priority()).unwrap();
+        assert_eq!(uris(&routed, 1), vec!["https://rosetta.example.com"]);
+
+        // no eligible node of the route, the request is not sent to the other pool
+        let clusters_by_priority = HashMap::from([(1, vec![&rosetta])]);
+        assert!(route_by_path(&routes, "/api/v1/blocks", clusters_by_priority).is_none());
+    }
+
+    #[test]
+    fn test_server_timing() {
+        assert_eq!(server_timing(Duration::from_millis(123)), "upstream;dur=123.0");
+        assert_eq!(server_timing(Duration::from_micros(4_560)), "upstream;dur=4.6");
+        assert_eq!(server_timing(Duration::ZERO), "upstream;dur=0.0");
+    }
+
+    #[test]
+    fn test_is_idempotent_request() {
+        let idempotent_methods = vec!["eth_call".to_string(), "eth_getBalance".to_string()];
+
+        // a read is retried
+        let read = br#"{"jsonrpc":"2.0","id":1,"method":"eth_call","params":[]}"#;
+        assert!(is_idempotent_request("jsonrpc", "POST", Some(read), &idempotent_methods));
+        let batch = br#"[{"jsonrpc":"2.0","id":1,"method":"eth_call"},{"jsonrpc":"2.0","id":2,"method":"eth_getBalance"}]"#;
+        assert!(is_idempotent_request("jsonrpc", "POST", Some(batch), &idempotent_methods));
+
+        // a write is never retried, alone or in a batch
+        let write = br#"{"jsonrpc":"2.0","id":1,"method":"eth_sendRawTransaction","params":["0x02"]}"#;
+        assert!(!is_idempotent_request("jsonrpc", "POST", Some(write), &idempotent_methods));
+        let batch = br#"[{"jsonrpc":"2.0","id":1,"method":"eth_call"},{"jsonrpc":"2.0","id":2,"method":"eth_sendRawTransaction"}]"#;
+        assert!(!is_idempotent_request("jsonrpc", "POST", Some(batch), &idempotent_methods));
+
+        // nothing to check, not retried
+        assert!(!is_idempotent_request("jsonrpc", "POST", None, &idempotent_methods));
+        assert!(!is_idempotent_request("jsonrpc", "POST", Some(b"[]"), &idempotent_methods));
+        assert!(!is_idempotent_request("jsonrpc", "POST", Some(read), &[]));
+
+        // other protocols check the http method
+        let http_methods = vec!["GET".to_string(), "HEAD".to_string()];
+        assert!(is_idempotent_request("http", "GET", None, &http_methods));
+        assert!(!is_idempotent_request("http", "POST", Some(read), &http_methods));
+    }
+
+    #[test]
+    fn test_method_timeout() {
+        let method_timeouts = HashMap::from([
+            ("debug_traceBlockByNumber".to_string(), Duration::from_secs(120)),
+            ("eth_getLogs".to_string(), Duration::from_secs(30)),
+        ]);
+
+        let method_rewrites = HashMap::from([("eth_getLogsLegacy".to_string(), "eth_getLogs".to_string())]);
+
+        // a heavy method gets its own timeout, a light one keeps the timeout of the node
+        let heavy = br#"{"jsonrpc":"2.0","id":1,"method":"debug_traceBlockByNumber","params":["latest"]}"#;
+        assert_eq!(method_timeout(heavy, &method_timeouts, &method_rewrites), Some(Duration::from_secs(120)));
+        let light = br#"{"jsonrpc":"2.0","id":1,"method":"eth_blockNumber","params":[]}"#;
+        assert_eq!(method_timeout(light, &method_timeouts, &method_rewrites), None);
+
+        // a rewritten method gets the timeout of the forwarded method
+        let legacy = br#"{"jsonrpc":"2.0","id":1,"method":"eth_getLogsLegacy","params":[]}"#;
+        assert_eq!(method_timeout(legacy, &method_timeouts, &method_rewrites), Some(Duration::from_secs(30)));
+
+        // a batch waits for its slowest method
+        let batch = br#"[{"jsonrpc":"2.0","id":1,"method":"eth_getLogs"},{"jsonrpc":"2.0","id":2,"method":"debug_traceBlockByNumber"}]"#;
+        assert_eq!(method_timeout(batch, &method_timeouts, &method_rewrites), Some(Duration::from_secs(120)));
+
+        // a batch with a method without timeout keeps the timeout of the node
+        let mixed = br#"[{"jsonrpc":"2.0","id":1,"method":"eth_getLogs"},{"jsonrpc":"2.0","id":2,"method":"eth_blockNumber"}]"#;
+        assert_eq!(method_timeout(mixed, &method_timeouts, &method_rewrites), None);
+        assert_eq!(method_timeout(b"not json", &method_timeouts, &method_rewrites), None);
+    }
+
+    #[test]
+    fn test_exclude_tried_nodes() {
+        let node = |uri: &str, priority: i32| ChainProxyConfig {
+            proxy_uri: uri.to_string(),
+            priority,
+            ..Default::default()
+        };
+        let node1 = node("https://node1.com", 1);
+        let node2 = node("https://node2.com", 1);
+        let node3 = node("https://node3.com", 0);
+        let clusters_by_priority = || HashMap::from([(1, vec![&node1, &node2]), (0, vec![&node3])]);
+        let uris = |clusters: &HashMap<i32, Vec<&ChainProxyConfig>>, priority: i32| -> Vec<String> {
+            clusters.get(&priority).map_or(Vec::new(), |c| c.iter().map(|c| c.proxy_uri.clone()).collect())
+        };
+
+        // the retry goes to another node
+        let tried = vec!["https://node1.com".to_string()];
+        let clusters = exclude_tried_nodes(&tried, clusters_by_priority());
+        assert_eq!(uris(&clusters, 1), vec!["https://node2.com"]);
+        assert_eq!(uris(&clusters, 0), vec!["https://node3.com"]);
+
+        // the lower priority is used once the top priority nodes were tried
+        let tried = vec!["https://node1.com".to_string(), "https://node2.com".to_string()];
+        let clusters = exclude_tried_nodes(&tried, clusters_by_priority());
+        assert!(!clusters.contains_key(&1));
+        assert_eq!(uris(&clusters, 0), vec!["https://node3.com"]);
+
+        // every node was tried, all are kept
+        let tried = vec!["https://node1.com".to_string(), "https://node2.com".to_string(), "https://node3.com".to_string()];
+        let clusters = exclude_tried_nodes(&tried, clusters_by_priority());
+        assert_eq!(uris(&clusters, 1).len(), 2);
+    }
+
+    #[test]
+    fn test_rate_limited_retry() {
+        // a rate limited response is retried on another node like the configured statuses
+        assert!(retryable_status(429, &[]));
+        assert!(retryable_status(503, &[502, 503]));
+        assert!(!retryable_status(503, &[]));
+
+        let header = |value: &str| http::HeaderValue::from_str(value).unwrap();
+        assert_eq!(retry_after_secs(Some(&header("30"))), Some(30));
+        assert_eq!(retry_after_secs(Some(&header(" 5 "))), Some(5));
+        assert_eq!(retry_after_secs(Some(&header("Wed, 21 Oct 2015 07:28:00 GMT"))), None);
+        assert_eq!(retry_after_secs(None), None);
+
+        // every node rate limited the request, the largest Retry-After is passed through
+        let retry_after = max_retry_after(None, Some(&header("5")));
+        let retry_after = max_retry_after(retry_after, None);
+        let retry_after = max_retry_after(retry_after, Some(&header("30")));
+        assert_eq!(retry_after, Some(30));
+        assert_eq!(max_retry_after(retry_after, Some(&header("10"))), Some(30));
+        let resp = rate_limited_response(retry_after.unwrap());
+        assert_eq!(resp.status.as_u16(), 429);
+        assert_eq!(resp.headers.get(http::header::RETRY_AFTER).unwrap(), "30");
+        assert_eq!(resp.headers.get(http::header::CONTENT_LENGTH).unwrap(), "0");
+
+        // none sent a Retry-After
+        assert_eq!(max_retry_after(max_retry_after(None, None), Some(&header("soon"))), Some(0));
+        let resp = rate_limited_response(0);
+        assert!(resp.headers.get(http::header::RETRY_AFTER).is_none());
+    }
+
+    #[test]
+    fn test_result_host() {
+        use crate::metrics::Metrics;
+
+        let metrics = Metrics::new("result_host_test");
+
+        // the client sent its own host header, the counter uses the hostname of the selected node
+        let mut req = RequestHeader::build("POST", b"/", None).unwrap();
+        req.insert_header("host", "proxy.example.com").unwrap();
+        let ctx = ProxyCtx {
+            upstream_hostname: Some("eth-mainnet.provider.io".to_string()),
+            ..Default::default()
+        };
+        metrics.inc_proxy_result_counter("ethereum", result_host(&ctx), "200", req.method.as_str());
+
+        let count = metrics
+            .proxy_result_counter
+            .with_label_values(&["ethereum", "eth-mainnet.provider.io", "200", "POST"])
+            .get();
+        assert_eq!(count, 1.0);
+        let count = metrics
+            .proxy_result_counter
+            .with_label_values(&["ethereum", "proxy.example.com", "200", "POST"])
+            .get();
+        assert_eq!(count, 0.0);
+
+    }
+
+    #[test]
+    fn test_result_host_no_upstream() {
+        use crate::metrics::Metrics;
+
+        let metrics = Metrics::new("result_host_no_upstream_test");
+
+        // requests failing before a node is selected share one series, whatever host the clients sent
+        let ctx = ProxyCtx::default();
+        metrics.inc_proxy_result_counter("ethereum", result_host(&ctx), "502", "POST");
+        metrics.inc_proxy_result_counter("ethereum", result_host(&ctx), "502", "POST");
+
+        let count = metrics
+            .proxy_result_counter
+            .with_label_values(&["ethereum", NO_UPSTREAM_HOST, "502", "POST"])
+            .get();
+        assert_eq!(count, 2.0);
+        assert_eq!(result_host(&ctx), "none");
+    }
+
+    #[test]
+    fn test_node_peer_sni() {
+        let addr: SocketAddr = "10.0.0.1:8545".parse().unwrap();
+        let config = ChainProxyConfig {
+            proxy_addr: "10.0.0.1:8545".to_string(),
+            proxy_tls: true,
+            proxy_hostname: "10.0.0.1".to_string(),
+            proxy_sni: "eth.example.com".to_string(),
+            ..Default::default()
+        };
+        let peer = node_peer(&config, Some(addr));
+        assert_eq!(peer.sni, "eth.example.com");
+        assert_eq!(peer.options.read_timeout, DEFAULT_PEER_OPTIONS.read_timeout);
+        assert_eq!(peer._address.to_string(), "10.0.0.1:8545");
+        assert_eq!(node_peer(&config, None).sni, "eth.example.com");
+
+        // sni off connects to the same address without a server name
+        let config = ChainProxyConfig { proxy_sni: String::new(), ..config };
+        let peer = node_peer(&config, Some(addr));
+        assert!(peer.sni.is_empty());
+        assert_eq!(peer._address.to_string(), "10.0.0.1:8545");
+
+        let config = ChainProxyConfig { upstream_read_timeout: Some(Duration::from_secs(5)), ..config };
+        assert_eq!(node_peer(&config, Some(addr)).options.read_timeout, Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_node_peer_alpn() {
+        let addr: SocketAddr = "10.0.0.1:443".parse().unwrap();
+        let config = ChainProxyConfig {
+            proxy_addr: "10.0.0.1:443".to_string(),
+            proxy_tls: true,
+            proxy_sni: "eth.example.com".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(node_peer(&config, Some(addr)).options.alpn.to_string(), "H1");
+
+        for (alpn, expected) in [(Alpn::H1, "H1"), (Alpn::H2, "H2"), (Alpn::H2H1, "H2H1")] {
+            let config = ChainProxyConfig { alpn: Some(alpn), ..config.clone() };
+            assert_eq!(node_peer(&config, Some(addr)).options.alpn.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn test_failure_status() {
+        assert_eq!(failure_status(&Error::new(HTTPStatus(503))), 503);
+        assert_eq!(failure_status(&Error::new(ErrorType::ConnectRefused).into_up()), 502);
+        assert_eq!(failure_status(&Error::new(ErrorType::ReadError).into_down()), 0);
+
+        let e: Result<()> = ProxyError::NoEligibleCluster.explain("proxy error");
+        assert_eq!(failure_status(&e.unwrap_err()), 500);
+    }
+
+    #[test]
+    fn test_no_upstream_status() {
+        let e = ProxyError::NoEligibleCluster.explain::<()>("proxy error").unwrap_err();
+        assert_eq!(no_upstream_status(&e, failure_status(&e), Some(503)), 503);
+        assert_eq!(no_upstream_status(&e, failure_status(&e), None), 500);
+        let e = ProxyError::NoBlockNumber.explain::<()>("proxy error").unwrap_err();
+        assert_eq!(no_upstream_status(&e, failure_status(&e), Some(503)), 503);
+
+        let e = Error::new(ErrorType::ConnectRefused).into_up();
+        assert_eq!(no_upstream_status(&e, failure_status(&e), Some(522)), 522);
+
+        // a failure after an upstream was reached keeps its status
+        let e = Error::new(ErrorType::ReadError).into_up();
+        assert_eq!(no_upstream_status(&e, failure_status(&e), Some(503)), 502);
+        let e = Error::new(ErrorType::ReadTimedout).into_up();
+        assert_eq!(no_upstream_status(&e, failure_status(&e), Some(503)), 502);
+
+        // the other server side failures keep theirs, as client errors and closed downstreams
+        let e = Error::new(HTTPStatus(503));
+        assert_eq!(no_upstream_status(&e, failure_status(&e), Some(521)), 503);
+        let e = Error::new(ErrorType::InternalError);
+        assert_eq!(no_upstream_status(&e, failure_status(&e), Some(503)), 500);
+        let e = Error::new(ErrorType::InvalidHTTPHeader).into_down();
+        assert_eq!(no_upstream_status(&e, failure_status(&e), Some(503)), 400);
+    }
+
+    #[test]
+    fn test_forwarded_for_append() {
+        let mut req = RequestHeader::build("POST", b"/", None).unwrap();
+        apply_forwarded_for(&mut req, Some(true), Some("10.0.0.1"));
+        assert_eq!(req.headers.get("x-forwarded-for").unwrap(), "10.0.0.1");
+
+        let mut req = RequestHeader::build("POST", b"/", None).unwrap();
+        req.insert_header("x-forwarded-for", "1.2.3.4").unwrap();
+        apply_forwarded_for(&mut req, Some(true), Some("10.0.0.1"));
+        assert_eq!(req.headers.get("x-forwarded-for").unwrap(), "1.2.3.4, 10.0.0.1");
+    }
+
+    #[test]
+    fn test_forwarded_for_strip() {
+        let mut req = RequestHeader::build("POST", b"/", None).unwrap();
+        req.insert_header("x-forwarded-for", "1.2.3.4").unwrap();
+        apply_forwarded_for(&mut req, Some(false), Some("10.0.0.1"));
+        assert!(req.headers.get("x-forwarded-for").is_none());
+
+        let mut req = RequestHeader::build("POST", b"/", None).unwrap();
+        req.insert_header("x-forwarded-for", "1.2.3.4").unwrap();
+        apply_forwarded_for(&mut req, None, Some("10.0.0.1"));
+        assert_eq!(req.headers.get("x-forwarded-for").unwrap(), "1.2.3.4");
+    }
+}
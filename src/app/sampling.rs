@@ -0,0 +1,60 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// max bytes of a sampled error body kept for logging
+pub const ERROR_SAMPLE_BODY_LIMIT: usize = 4096;
+
+/// Error sampler
+///
+/// Picks 1 in `rate` upstream error responses to be logged with their body,
+/// so intermittent upstream errors can be caught without logging every response.
+pub struct ErrorSampler {
+    // 0 disables sampling
+    rate: u64,
+    // number of errors seen so far
+    seen: AtomicU64,
+}
+
+impl ErrorSampler {
+    pub fn new(rate: u64) -> Self {
+        ErrorSampler {
+            rate,
+            seen: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether the response with the given status should be sampled
+    pub fn sample(&self, status: u16) -> bool {
+        if self.rate == 0 || status < 500 {
+            return false;
+        }
+
+        self.seen.fetch_add(1, Ordering::Relaxed).is_multiple_of(self.rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_sampler() {
+        let sampler = ErrorSampler::new(100);
+
+        // only 5xx responses are counted and sampled
+        assert!(!sampler.sample(200));
+        assert!(!sampler.sample(404));
+
+        let sampled = (0..1000).filter(|_| sampler.sample(502)).count();
+        assert_eq!(sampled, 10);
+
+        // every error is sampled with rate 1
+        let sampler = ErrorSampler::new(1);
+        assert!((0..10).all(|_| sampler.sample(500)));
+    }
+
+    #[test]
+    fn test_error_sampler_disabled() {
+        let sampler = ErrorSampler::new(0);
+        assert!((0..100).all(|_| !sampler.sample(500)));
+    }
+}
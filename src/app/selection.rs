@@ -0,0 +1,838 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use rand::seq::SliceRandom;
+use rand::Rng;
+
+use crate::app::success_ratio::SuccessRatioWindow;
+use crate::config::{HealthScoreWeights, SelectionMode};
+use crate::service::proxy::ChainProxyConfig;
+
+/// Sticky selection
+///
+/// Within the sticky window, the last selected node is reused as long as it is still a candidate,
+/// which keeps consecutive requests on the same node instead of bouncing between nodes.
+/// After the window expires the next selection is made again by the fallback.
+pub struct StickySelection {
+    window: Option<Duration>,
+    // last selection time and the selected node uri
+    last: Mutex<Option<(Instant, String)>>,
+}
+
+impl StickySelection {
+    pub fn new(window: Option<Duration>) -> Self {
+        StickySelection {
+            window,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Select a node from the candidates, `pick` is used when there is no sticky node
+    pub fn select<'a, F>(&self, candidates: &[&'a ChainProxyConfig], now: Instant, pick: F) -> &'a ChainProxyConfig
+    where
+        F: FnOnce(&[&'a ChainProxyConfig]) -> &'a ChainProxyConfig,
+    {
+        let window = match self.window {
+            Some(window) => window,
+            None => return pick(candidates),
+        };
+
+        let mut last = self.last.lock().unwrap();
+        if let Some((selected_at, uri)) = last.as_ref() {
+            if now.duration_since(*selected_at) < window {
+                if let Some(config) = candidates.iter().find(|c| &c.proxy_uri == uri) {
+                    return config;
+                }
+            }
+        }
+
+        let selected = pick(candidates);
+        *last = Some((now, selected.proxy_uri.clone()));
+        selected
+    }
+}
+
+/// Why a node was selected for a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelectionReason {
+    // routed by a special method config
+    Special,
+    // a node of the highest priority within the block gap
+    BlockGap,
+    // a lower priority node, the higher priority ones are not eligible
+    Fallback,
+    // any eligible node, drawn by its recent latency
+    Adaptive,
+    // a node of the highest priority drawn by its health score
+    HealthScore,
+    // the node selected last, reused within the sticky window
+    Sticky,
+    // a node drawn by its traffic weight
+    Weighted,
+    // the first healthy node in config order
+    FirstHealthy,
+}
+
+impl SelectionReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SelectionReason::Special => "special",
+            SelectionReason::BlockGap => "block-gap",
+            SelectionReason::Fallback => "fallback",
+            SelectionReason::Adaptive => "adaptive",
+            SelectionReason::HealthScore => "health-score",
+            SelectionReason::Sticky => "sticky",
+            SelectionReason::Weighted => "weighted",
+            SelectionReason::FirstHealthy => "first-healthy",
+        }
+    }
+
+    /// Why a node was selected: the special method routing first, then the selection which picked the node
+    /// (sticky, weighted or first healthy), then how its candidates were drawn (adaptive or health score),
+    /// and else whether its priority is lower than the highest one
+    pub fn of(
+        special: bool,
+        picked_by: Option<SelectionReason>,
+        drawn_by: Option<SelectionReason>,
+        fallback: bool,
+    ) -> SelectionReason {
+        if special {
+            return SelectionReason::Special;
+        }
+        match picked_by.or(drawn_by) {
+            Some(reason) => reason,
+            None if fallback => SelectionReason::Fallback,
+            None => SelectionReason::BlockGap,
+        }
+    }
+}
+
+/// Format the upstream selection log line of a request, the node tags are appended as `tags=key:value,...`
+pub fn format_selection_log(
+    request_id: &str,
+    chain: &str,
+    client: Option<&str>,
+    node: &str,
+    priority: i32,
+    reason: SelectionReason,
+    tags: &BTreeMap<String, String>,
+) -> String {
+    let line = format!(
+        "request_id={} chain={} client={} node={} priority={} reason={}",
+        request_id,
+        chain,
+        client.unwrap_or("-"),
+        node,
+        priority,
+        reason.as_str()
+    );
+    if tags.is_empty() {
+        return line;
+    }
+
+    let tags: Vec<String> = tags.iter().map(|(key, value)| format!("{key}:{value}")).collect();
+    format!("{line} tags={}", tags.join(","))
+}
+
+/// Whether every candidate has a traffic weight, the weighted draw replaces the priority selection then
+pub fn has_traffic_weights(candidates: &[&ChainProxyConfig]) -> bool {
+    !candidates.is_empty() && candidates.iter().all(|c| c.traffic_weight.is_some())
+}
+
+/// The candidates whose region tag is the region of the client, all of them when the region is unknown
+/// or none is in it
+pub fn prefer_region<'a>(
+    candidates: Vec<&'a ChainProxyConfig>,
+    tag: &str,
+    region: Option<&str>,
+) -> Vec<&'a ChainProxyConfig> {
+    let region = match region {
+        Some(region) => region,
+        None => return candidates,
+    };
+    let local: Vec<&ChainProxyConfig> = candidates
+        .iter()
+        .filter(|c| c.tags.get(tag).is_some_and(|r| r.eq_ignore_ascii_case(region)))
+        .copied()
+        .collect();
+    if local.is_empty() {
+        candidates
+    } else {
+        local
+    }
+}
+
+/// The candidates of the highest priority, in config order
+pub fn highest_priority<'a>(candidates: &[&'a ChainProxyConfig]) -> Vec<&'a ChainProxyConfig> {
+    let max_priority = candidates.iter().map(|c| c.priority).max();
+    candidates.iter().filter(|c| Some(c.priority) == max_priority).copied().collect()
+}
+
+/// The first candidate in config order among the highest priority ones, for a deterministic selection
+pub fn first_healthy<'a>(candidates: &[&'a ChainProxyConfig]) -> Option<&'a ChainProxyConfig> {
+    highest_priority(candidates).first().copied()
+}
+
+/// Weighted random draw over the candidates' traffic weights
+///
+/// Returns None if a candidate has no weight or all weights are zero.
+pub fn weighted_select<'a, R: Rng>(candidates: &[&'a ChainProxyConfig], rng: &mut R) -> Option<&'a ChainProxyConfig> {
+    if !has_traffic_weights(candidates) {
+        return None;
+    }
+
+    let total: u32 = candidates.iter().filter_map(|c| c.traffic_weight).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut draw = rng.gen_range(0..total);
+    for candidate in candidates.iter() {
+        let weight = candidate.traffic_weight.unwrap_or(0);
+        if draw < weight {
+            return Some(*candidate);
+        }
+        draw -= weight;
+    }
+
+    None
+}
+
+/// The candidates of the upstream selection among the eligible clusters by priority
+pub struct Candidates<'a> {
+    // every eligible node
+    pub eligible: Vec<&'a ChainProxyConfig>,
+    // the nodes the selected one is drawn from
+    pub candidates: Vec<&'a ChainProxyConfig>,
+    // every eligible node has a traffic weight, priorities are ignored
+    pub weighted: bool,
+    // every eligible node is a candidate, drawn by its recent latency
+    pub adaptive: bool,
+    // the candidates are drawn by their health score
+    pub health_scored: bool,
+}
+
+impl<'a> Candidates<'a> {
+    /// Every eligible node when all of them have a traffic weight or the priority is adaptive, else the highest
+    /// priority ones. Special methods keep the priorities and their own selection.
+    pub fn of(
+        clusters_by_priority: &HashMap<i32, Vec<&'a ChainProxyConfig>>,
+        special_method: bool,
+        adaptive_priority: bool,
+        selection: SelectionMode,
+    ) -> Self {
+        let eligible: Vec<&ChainProxyConfig> = clusters_by_priority.values().flatten().copied().collect();
+        let weighted = has_traffic_weights(&eligible);
+        let adaptive = !weighted && !special_method && adaptive_priority;
+        let health_scored = !weighted && !special_method && selection == SelectionMode::HealthScore;
+        let candidates = if weighted || adaptive {
+            eligible.clone()
+        } else {
+            highest_priority(&eligible)
+        };
+        Candidates { eligible, candidates, weighted, adaptive, health_scored }
+    }
+
+    /// Why the selected node was chosen, `max_priority` is the highest priority of the nodes of the chain
+    pub fn reason(
+        &self,
+        selected: &ChainProxyConfig,
+        max_priority: i32,
+        special_method: bool,
+        picked_by: Option<SelectionReason>,
+    ) -> SelectionReason {
+        let drawn_by = if self.adaptive {
+            Some(SelectionReason::Adaptive)
+        } else {
+            self.health_scored.then_some(SelectionReason::HealthScore)
+        };
+        let fallback = !self.weighted && selected.priority < max_priority;
+        SelectionReason::of(special_method, picked_by, drawn_by, fallback)
+    }
+}
+
+/// Pick a node among the candidates: drawn by traffic weight, the first healthy one with the first healthy
+/// selection, drawn by the selection weights, e.g. warm-up and latency, else at random.
+///
+/// Returns the selection which picked the node, None when it was drawn, or None when there is no candidate.
+pub fn pick_candidate<'a, R: Rng>(
+    candidates: &[&'a ChainProxyConfig],
+    first_healthy_selection: bool,
+    selection_weights: Option<&[f64]>,
+    rng: &mut R,
+) -> Option<(&'a ChainProxyConfig, Option<SelectionReason>)> {
+    if let Some(selected) = weighted_select(candidates, rng) {
+        // Weighted selection by traffic percent
+        Some((selected, Some(SelectionReason::Weighted)))
+    } else if let Some(selected) = first_healthy_selection.then(|| first_healthy(candidates)).flatten() {
+        // Deterministic selection by config order
+        Some((selected, Some(SelectionReason::FirstHealthy)))
+    } else if candidates.len() == 1 {
+        Some((candidates[0], None))
+    } else if let Some(selected) = selection_weights.and_then(|weights| select_by_weights(candidates, weights, rng)) {
+        // Weighted selection by warm-up and latency
+        Some((selected, None))
+    } else {
+        // Random selection
+        candidates.choose(rng).map(|selected| (*selected, None))
+
+        // if you want to use round robin selection, you can add here
+    }
+}
+
+/// Warm-up ramp
+///
+/// A node becoming eligible again, e.g. a restarted node with a cold cache, gets a selection weight
+/// growing linearly from 0 to 1 over the warm-up instead of its full share of traffic at once.
+/// The nodes eligible when the ramp first observes them are considered warm.
+pub struct WarmupRamp {
+    warmup: Option<Duration>,
+    // when each eligible node became eligible, None until the first observation
+    eligible_since: Mutex<Option<HashMap<String, Instant>>>,
+}
+
+impl WarmupRamp {
+    pub fn new(warmup: Option<Duration>) -> Self {
+        WarmupRamp {
+            warmup,
+            eligible_since: Mutex::new(None),
+        }
+    }
+
+    /// Record the currently eligible nodes and return the warm-up weight of every candidate,
+    /// None if the warm-up is disabled or all candidates are warm
+    pub fn weights(&self, eligible: &[&ChainProxyConfig], candidates: &[&ChainProxyConfig], now: Instant) -> Option<Vec<f64>> {
+        let warmup = self.warmup?;
+
+        let mut eligible_since = self.eligible_since.lock().unwrap();
+        let since = eligible_since.get_or_insert_with(|| {
+            let warm = now.checked_sub(warmup).unwrap_or(now);
+            eligible.iter().map(|c| (c.proxy_uri.clone(), warm)).collect()
+        });
+        since.retain(|uri, _| eligible.iter().any(|c| &c.proxy_uri == uri));
+        for config in eligible.iter() {
+            since.entry(config.proxy_uri.clone()).or_insert(now);
+        }
+
+        let weights: Vec<f64> = candidates
+            .iter()
+            .map(|c| {
+                let elapsed = since.get(&c.proxy_uri).map_or(warmup, |at| now.duration_since(*at));
+                (elapsed.as_secs_f64() / warmup.as_secs_f64()).min(1.0)
+            })
+            .collect();
+
+        if weights.iter().all(|w| *w >= 1.0) {
+            None
+        } else {
+            Some(weights)
+        }
+    }
+}
+
+// weight of the latest latency sample in the moving average
+const LATENCY_EWMA_ALPHA: f64 = 0.2;
+
+/// Latency moving average
+///
+/// Keeps an exponentially weighted moving average of the latency of every node, so the nodes can be
+/// drawn inversely to their recent latency: a slow primary yields traffic to a faster node while staying in rotation.
+pub struct LatencyEwma {
+    // average latency in milliseconds by node uri
+    latencies: Mutex<HashMap<String, f64>>,
+}
+
+impl LatencyEwma {
+    pub fn new() -> Self {
+        LatencyEwma {
+            latencies: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Fold the latency of a request to the node into its average
+    pub fn record(&self, uri: &str, latency: Duration) {
+        let latency = latency.as_secs_f64() * 1000.0;
+        let mut latencies = self.latencies.lock().unwrap();
+        latencies
+            .entry(uri.to_string())
+            .and_modify(|average| *average = LATENCY_EWMA_ALPHA * latency + (1.0 - LATENCY_EWMA_ALPHA) * *average)
+            .or_insert(latency);
+    }
+
+    /// The weight of every candidate, inverse to its average latency, None if no candidate was measured yet.
+    /// A candidate without a measurement gets the weight of the fastest one, so it gets measured.
+    pub fn weights(&self, candidates: &[&ChainProxyConfig]) -> Option<Vec<f64>> {
+        let latencies = self.latencies.lock().unwrap();
+        let weights: Vec<Option<f64>> = candidates
+            .iter()
+            .map(|c| latencies.get(&c.proxy_uri).map(|average| 1.0 / average.max(1.0)))
+            .collect();
+
+        let fastest = weights.iter().flatten().copied().reduce(f64::max)?;
+        Some(weights.into_iter().map(|w| w.unwrap_or(fastest)).collect())
+    }
+}
+
+/// Health score of every candidate, from 0 to 1
+///
+/// Combines the freshness of the node height, 1 at the highest height down to 0 past the block gap, the recent
+/// latency relative to the fastest candidate and the success ratio of the recent requests, weighted average by
+/// the configured weights. A component not measured yet, e.g. the latency of a node without traffic, counts as 1.
+pub fn health_scores(
+    candidates: &[&ChainProxyConfig],
+    block_numbers: &HashMap<String, u64>,
+    latency_ewma: &LatencyEwma,
+    success_ratio_window: &SuccessRatioWindow,
+    weights: &HealthScoreWeights,
+) -> Vec<f64> {
+    let max_block_number = candidates.iter().filter_map(|c| block_numbers.get(&c.proxy_uri)).max().copied();
+    let latency_weights = latency_ewma.weights(candidates);
+    let fastest = latency_weights.iter().flatten().copied().reduce(f64::max);
+    let total_weight = weights.freshness() + weights.latency() + weights.success();
+
+    candidates
+        .iter()
+        .enumerate()
+        .map(|(i, c)| {
+            let freshness = match (max_block_number, block_numbers.get(&c.proxy_uri)) {
+                (Some(max), Some(block_number)) => {
+                    1.0 - (max - block_number) as f64 / (c.block_gap as f64 + 1.0)
+                }
+                (Some(_), None) => 0.0,
+                (None, _) => 1.0,
+            };
+            let latency = match (latency_weights.as_ref(), fastest) {
+                (Some(latency_weights), Some(fastest)) => latency_weights[i] / fastest,
+                _ => 1.0,
+            };
+            let success = success_ratio_window.ratio(&c.proxy_uri).unwrap_or(1.0);
+
+            let score = weights.freshness() * freshness.clamp(0.0, 1.0)
+                + weights.latency() * latency
+                + weights.success() * success;
+            if total_weight > 0.0 {
+                score / total_weight
+            } else {
+                1.0
+            }
+        })
+        .collect()
+}
+
+/// Weighted random draw over per candidate weights, e.g. the warm-up or latency weights
+///
+/// Returns None if all weights are zero, e.g. every candidate just became eligible.
+pub fn select_by_weights<'a, R: Rng>(candidates: &[&'a ChainProxyConfig], weights: &[f64], rng: &mut R) -> Option<&'a ChainProxyConfig> {
+    let total: f64 = weights.iter().sum();
+    if total <= 0.0 {
+        return None;
+    }
+
+    let mut draw = rng.gen_range(0.0..total);
+    for (candidate, weight) in candidates.iter().zip(weights.iter()) {
+        if draw < *weight {
+            return Some(*candidate);
+        }
+        draw -= weight;
+    }
+
+    candidates.last().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use crate::test_util::host_config;
+
+    #[test]
+    fn test_sticky_selection() {
+        let node1 = host_config("http://node1");
+        let node2 = host_config("http://node2");
+        let candidates = vec![&node1, &node2];
+
+        let sticky = StickySelection::new(Some(Duration::from_millis(100)));
+        let now = Instant::now();
+
+        let selected = sticky.select(&candidates, now, |c| c[0]);
+        assert_eq!(selected.proxy_uri, "http://node1");
+
+        // within the window the last node is reused
+        let selected = sticky.select(&candidates, now + Duration::from_millis(50), |c| c[1]);
+        assert_eq!(selected.proxy_uri, "http://node1");
+
+        // after the window the selection is made again
+        let selected = sticky.select(&candidates, now + Duration::from_millis(150), |c| c[1]);
+        assert_eq!(selected.proxy_uri, "http://node2");
+    }
+
+    #[test]
+    fn test_sticky_selection_disabled() {
+        let node1 = host_config("http://node1");
+        let node2 = host_config("http://node2");
+        let candidates = vec![&node1, &node2];
+
+        let sticky = StickySelection::new(None);
+        let now = Instant::now();
+
+        assert_eq!(sticky.select(&candidates, now, |c| c[0]).proxy_uri, "http://node1");
+        assert_eq!(sticky.select(&candidates, now, |c| c[1]).proxy_uri, "http://node2");
+    }
+
+    #[test]
+    fn test_format_selection_log() {
+        let line = format_selection_log(
+            "abc123",
+            "ethereum",
+            Some("10.0.0.1"),
+            "https://node1.example.com/",
+            2,
+            SelectionReason::BlockGap,
+            &BTreeMap::new(),
+        );
+        assert_eq!(
+            line,
+            "request_id=abc123 chain=ethereum client=10.0.0.1 node=https://node1.example.com/ priority=2 reason=block-gap"
+        );
+
+        let line = format_selection_log(
+            "abc123",
+            "ethereum",
+            None,
+            "http://node2/",
+            1,
+            SelectionReason::Fallback,
+            &BTreeMap::new(),
+        );
+        assert_eq!(line, "request_id=abc123 chain=ethereum client=- node=http://node2/ priority=1 reason=fallback");
+
+        // the tags attribute the node
+        let tags = BTreeMap::from([
+            ("region".to_string(), "us-east".to_string()),
+            ("provider".to_string(), "infura".to_string()),
+        ]);
+        let line =
+            format_selection_log("abc123", "ethereum", None, "http://node2/", 1, SelectionReason::Fallback, &tags);
+        assert_eq!(
+            line,
+            "request_id=abc123 chain=ethereum client=- node=http://node2/ priority=1 reason=fallback \
+             tags=provider:infura,region:us-east"
+        );
+
+        assert_eq!(SelectionReason::Special.as_str(), "special");
+    }
+
+    #[test]
+    fn test_selection_reason() {
+        use SelectionReason::*;
+
+        assert_eq!(SelectionReason::of(true, Some(Sticky), None, false), Special);
+        // the selection which picked the node comes before the candidates
+        assert_eq!(SelectionReason::of(false, Some(Sticky), Some(Adaptive), true), Sticky);
+        assert_eq!(SelectionReason::of(false, Some(Weighted), None, false), Weighted);
+        assert_eq!(SelectionReason::of(false, Some(FirstHealthy), Some(HealthScore), false), FirstHealthy);
+        assert_eq!(SelectionReason::of(false, None, Some(HealthScore), true), HealthScore);
+        assert_eq!(SelectionReason::of(false, None, None, true), Fallback);
+        assert_eq!(SelectionReason::of(false, None, None, false), BlockGap);
+        assert_eq!(FirstHealthy.as_str(), "first-healthy");
+    }
+
+    #[test]
+    fn test_first_healthy() {
+        let node1 = host_config("http://node1");
+        let node2 = host_config("http://node2");
+        let mut fallback = host_config("http://fallback");
+        fallback.priority = 0;
+
+        // the first node in config order is always chosen
+        let candidates = vec![&fallback, &node1, &node2];
+        for _ in 0..100 {
+            assert_eq!(first_healthy(&candidates).unwrap().proxy_uri, "http://node1");
+        }
+
+        // the next one once it is not eligible any more
+        assert_eq!(first_healthy(&[&fallback, &node2]).unwrap().proxy_uri, "http://node2");
+        assert_eq!(first_healthy(&[&fallback]).unwrap().proxy_uri, "http://fallback");
+        assert!(first_healthy(&[]).is_none());
+
+        // the highest priority candidates keep their config order
+        let uris: Vec<&str> = highest_priority(&candidates).iter().map(|c| c.proxy_uri.as_str()).collect();
+        assert_eq!(uris, vec!["http://node1", "http://node2"]);
+        assert!(highest_priority(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_prefer_region() {
+        let regional = |uri: &str, region: &str| ChainProxyConfig {
+            tags: BTreeMap::from([("region".to_string(), region.to_string())]),
+            ..host_config(uri)
+        };
+        let us1 = regional("http://us1", "us-east");
+        let us2 = regional("http://us2", "US-EAST");
+        let eu = regional("http://eu", "eu-west");
+        let untagged = host_config("http://untagged");
+        let candidates = || vec![&us1, &eu, &us2, &untagged];
+        let uris = |candidates: Vec<&ChainProxyConfig>| -> Vec<String> {
+            candidates.iter().map(|c| c.proxy_uri.clone()).collect()
+        };
+
+        // the nodes of the client region are preferred
+        assert_eq!(uris(prefer_region(candidates(), "region", Some("us-east"))), ["http://us1", "http://us2"]);
+        assert_eq!(uris(prefer_region(candidates(), "region", Some("eu-west"))), ["http://eu"]);
+
+        // any node when none is in the region, the region is unknown, or the nodes are tagged otherwise
+        assert_eq!(uris(prefer_region(candidates(), "region", Some("ap-south"))).len(), 4);
+        assert_eq!(uris(prefer_region(candidates(), "region", None)).len(), 4);
+        assert_eq!(uris(prefer_region(candidates(), "zone", Some("us-east"))).len(), 4);
+    }
+
+    #[test]
+    fn test_weighted_select() {
+        let mut node1 = host_config("http://node1");
+        node1.traffic_weight = Some(70);
+        let mut node2 = host_config("http://node2");
+        node2.traffic_weight = Some(30);
+        let candidates = vec![&node1, &node2];
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let draws = 10000;
+        let mut node1_count = 0;
+        for _ in 0..draws {
+            if weighted_select(&candidates, &mut rng).unwrap().proxy_uri == "http://node1" {
+                node1_count += 1;
+            }
+        }
+
+        // roughly 70% of the traffic goes to node1
+        let share = node1_count as f64 / draws as f64;
+        assert!((share - 0.7).abs() < 0.03, "node1 share: {share}");
+    }
+
+    #[test]
+    fn test_weighted_select_without_weights() {
+        let mut node1 = host_config("http://node1");
+        node1.traffic_weight = Some(100);
+        let node2 = host_config("http://node2");
+        let mut rng = StdRng::seed_from_u64(42);
+
+        // a node without weight falls back to the priority selection
+        assert!(weighted_select(&[&node1, &node2], &mut rng).is_none());
+
+        node1.traffic_weight = Some(0);
+        assert!(weighted_select(&[&node1], &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_candidates() {
+        let node1 = host_config("http://node1");
+        let node2 = host_config("http://node2");
+        let mut fallback = host_config("http://fallback");
+        fallback.priority = 0;
+        let clusters_by_priority = HashMap::from([(1, vec![&node1, &node2]), (0, vec![&fallback])]);
+        let uris = |candidates: &[&ChainProxyConfig]| -> Vec<String> {
+            candidates.iter().map(|c| c.proxy_uri.clone()).collect()
+        };
+
+        // the highest priority nodes are the candidates
+        let selection = Candidates::of(&clusters_by_priority, false, false, SelectionMode::Random);
+        assert_eq!(uris(&selection.candidates), ["http://node1", "http://node2"]);
+        assert_eq!(selection.eligible.len(), 3);
+        assert_eq!(selection.reason(&node1, 1, false, None), SelectionReason::BlockGap);
+        assert_eq!(selection.reason(&fallback, 1, false, None), SelectionReason::Fallback);
+
+        // every eligible node with adaptive priority, but not for special methods
+        let selection = Candidates::of(&clusters_by_priority, false, true, SelectionMode::Random);
+        assert_eq!(selection.candidates.len(), 3);
+        assert_eq!(selection.reason(&node1, 1, false, None), SelectionReason::Adaptive);
+        let selection = Candidates::of(&clusters_by_priority, true, true, SelectionMode::Random);
+        assert_eq!(selection.candidates.len(), 2);
+        assert_eq!(selection.reason(&node1, 1, true, None), SelectionReason::Special);
+
+        let selection = Candidates::of(&clusters_by_priority, false, false, SelectionMode::HealthScore);
+        assert_eq!(selection.reason(&node1, 1, false, None), SelectionReason::HealthScore);
+        assert!(Candidates::of(&HashMap::new(), false, false, SelectionMode::Random).candidates.is_empty());
+    }
+
+    #[test]
+    fn test_pick_candidate() {
+        let node1 = host_config("http://node1");
+        let node2 = host_config("http://node2");
+        let mut rng = StdRng::seed_from_u64(42);
+
+        let (selected, picked_by) = pick_candidate(&[&node1, &node2], true, None, &mut rng).unwrap();
+        assert_eq!((selected.proxy_uri.as_str(), picked_by), ("http://node1", Some(SelectionReason::FirstHealthy)));
+
+        // drawn by the selection weights
+        for _ in 0..100 {
+            let (selected, picked_by) = pick_candidate(&[&node1, &node2], false, Some(&[0.0, 1.0]), &mut rng).unwrap();
+            assert_eq!((selected.proxy_uri.as_str(), picked_by), ("http://node2", None));
+        }
+
+        let mut weighted = host_config("http://weighted");
+        weighted.traffic_weight = Some(100);
+        let (_, picked_by) = pick_candidate(&[&weighted], true, None, &mut rng).unwrap();
+        assert_eq!(picked_by, Some(SelectionReason::Weighted));
+
+        assert!(pick_candidate(&[], false, None, &mut rng).is_none());
+    }
+
+    #[test]
+    fn test_warmup_ramp() {
+        let node1 = host_config("http://node1");
+        let node2 = host_config("http://node2");
+        let ramp = WarmupRamp::new(Some(Duration::from_secs(10)));
+        let start = Instant::now();
+
+        // the nodes eligible at the first observation are warm
+        assert_eq!(ramp.weights(&[&node1], &[&node1], start), None);
+
+        // node2 becomes eligible and ramps up linearly
+        let weights = ramp.weights(&[&node1, &node2], &[&node1, &node2], start + Duration::from_secs(1));
+        assert_eq!(weights, Some(vec![1.0, 0.0]));
+        let weights = ramp.weights(&[&node1, &node2], &[&node1, &node2], start + Duration::from_secs(6));
+        assert_eq!(weights, Some(vec![1.0, 0.5]));
+        assert_eq!(ramp.weights(&[&node1, &node2], &[&node1, &node2], start + Duration::from_secs(11)), None);
+
+        // dropping out of the eligible nodes restarts the ramp
+        ramp.weights(&[&node1], &[&node1], start + Duration::from_secs(12));
+        let weights = ramp.weights(&[&node1, &node2], &[&node1, &node2], start + Duration::from_secs(13));
+        assert_eq!(weights, Some(vec![1.0, 0.0]));
+
+        assert_eq!(WarmupRamp::new(None).weights(&[&node1], &[&node1], start), None);
+    }
+
+    #[test]
+    fn test_latency_ewma() {
+        let node1 = host_config("http://node1");
+        let node2 = host_config("http://node2");
+        let ewma = LatencyEwma::new();
+        assert_eq!(ewma.weights(&[&node1, &node2]), None);
+
+        ewma.record("http://node1", Duration::from_millis(100));
+        // the unmeasured node gets the weight of the fastest one
+        assert_eq!(ewma.weights(&[&node1, &node2]), Some(vec![0.01, 0.01]));
+
+        // the average moves a fifth of the way to the latest sample
+        ewma.record("http://node1", Duration::from_millis(200));
+        ewma.record("http://node2", Duration::from_millis(50));
+        let weights = ewma.weights(&[&node1, &node2]).unwrap();
+        assert!((weights[0] - 1.0 / 120.0).abs() < 1e-9);
+        assert!((weights[1] - 1.0 / 50.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_latency_ewma_traffic_share() {
+        // node1 is the primary but got slow
+        let node1 = host_config("http://node1");
+        let node2 = host_config("http://node2");
+        let candidates = vec![&node1, &node2];
+        let ewma = LatencyEwma::new();
+        for _ in 0..20 {
+            ewma.record("http://node1", Duration::from_millis(300));
+            ewma.record("http://node2", Duration::from_millis(100));
+        }
+        let weights = ewma.weights(&candidates).unwrap();
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let draws = 10000;
+        let node1_count = (0..draws)
+            .filter(|_| select_by_weights(&candidates, &weights, &mut rng).unwrap().proxy_uri == "http://node1")
+            .count();
+
+        // a third of the latency weight, so a quarter of the traffic, but still in rotation
+        let share = node1_count as f64 / draws as f64;
+        assert!((share - 0.25).abs() < 0.03, "node1 share: {share}");
+        assert!(node1_count > 0);
+    }
+
+    #[test]
+    fn test_health_scores() {
+        let node = |uri: &str, hostname: &str| ChainProxyConfig {
+            proxy_uri: uri.to_string(),
+            proxy_hostname: hostname.to_string(),
+            priority: 1,
+            block_gap: 9,
+            ..Default::default()
+        };
+        let node1 = node("http://node1", "node1");
+        let node2 = node("http://node2", "node2");
+        let candidates = vec![&node1, &node2];
+        let weights = HealthScoreWeights::default();
+
+        // nothing measured yet
+        let ewma = LatencyEwma::new();
+        let window = SuccessRatioWindow::new(10);
+        let scores = health_scores(&candidates, &HashMap::new(), &ewma, &window, &weights);
+        assert_eq!(scores, vec![1.0, 1.0]);
+
+        // node2 lags 5 blocks out of a gap of 9, is twice as slow and fails half of its requests
+        let block_numbers = HashMap::from([("http://node1".to_string(), 100), ("http://node2".to_string(), 95)]);
+        ewma.record("http://node1", Duration::from_millis(50));
+        ewma.record("http://node2", Duration::from_millis(100));
+        for success in [true, false] {
+            window.record("http://node1", true);
+            window.record("http://node2", success);
+        }
+        let scores = health_scores(&candidates, &block_numbers, &ewma, &window, &weights);
+        assert!((scores[0] - 1.0).abs() < 1e-9);
+        assert!((scores[1] - 0.5).abs() < 1e-9);
+
+        // only the freshness counts
+        let freshness_only = HealthScoreWeights::new(1.0, 0.0, 0.0);
+        let scores = health_scores(&candidates, &block_numbers, &ewma, &window, &freshness_only);
+        assert!((scores[1] - 0.5).abs() < 1e-9);
+
+        // the better scored node gets more traffic, the other one stays in rotation
+        let scores = health_scores(&candidates, &block_numbers, &ewma, &window, &weights);
+        let mut rng = StdRng::seed_from_u64(42);
+        let draws = 10000;
+        let node1_count = (0..draws)
+            .filter(|_| select_by_weights(&candidates, &scores, &mut rng).unwrap().proxy_uri == "http://node1")
+            .count();
+        let share = node1_count as f64 / draws as f64;
+        assert!((share - 2.0 / 3.0).abs() < 0.03, "node1 share: {share}");
+        assert!(node1_count < draws);
+
+        // two keys of a provider share a hostname, only the failing one is scored down
+        let key1 = node("https://eth.provider.io/v2/key1", "eth.provider.io");
+        let key2 = node("https://eth.provider.io/v2/key2", "eth.provider.io");
+        let window = SuccessRatioWindow::new(10);
+        for _ in 0..4 {
+            window.record(&key1.proxy_uri, true);
+            window.record(&key2.proxy_uri, false);
+        }
+        let scores = health_scores(&[&key1, &key2], &HashMap::new(), &LatencyEwma::new(), &window, &weights);
+        assert_eq!(scores[0], 1.0);
+        assert!(scores[1] < scores[0]);
+    }
+
+    #[test]
+    fn test_warmup_select() {
+        let node1 = host_config("http://node1");
+        let node2 = host_config("http://node2");
+        let candidates = vec![&node1, &node2];
+        let ramp = WarmupRamp::new(Some(Duration::from_secs(10)));
+        let start = Instant::now();
+        ramp.weights(&[&node1], &[&node1], start);
+
+        // node2 turned healthy 2s ago, it gets a fifth of node1's weight
+        let ramp_start = start + Duration::from_secs(1);
+        ramp.weights(&candidates, &candidates, ramp_start);
+        let weights = ramp.weights(&candidates, &candidates, ramp_start + Duration::from_secs(2)).unwrap();
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let draws = 10000;
+        let node2_count = (0..draws)
+            .filter(|_| select_by_weights(&candidates, &weights, &mut rng).unwrap().proxy_uri == "http://node2")
+            .count();
+
+        // 0.2 / 1.2 of the traffic instead of half
+        let share = node2_count as f64 / draws as f64;
+        assert!((share - 0.2 / 1.2).abs() < 0.03, "node2 share: {share}");
+
+        // a node which just turned healthy is only used when no other node has a weight
+        assert!(select_by_weights(&candidates, &[0.0, 0.0], &mut rng).is_none());
+    }
+}
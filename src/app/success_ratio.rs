@@ -0,0 +1,78 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+// number of recent proxy outcomes kept per host when no window is configured
+pub const DEFAULT_SUCCESS_RATIO_WINDOW: usize = 100;
+
+/// Success ratio window
+///
+/// Keeps the outcomes of the last `size` proxied requests of every host,
+/// so a node degrading before it fails its health check can be spotted by its recent success ratio.
+pub struct SuccessRatioWindow {
+    size: usize,
+    // recent outcomes by host, true for a success
+    outcomes: Mutex<HashMap<String, VecDeque<bool>>>,
+}
+
+impl SuccessRatioWindow {
+    /// Create a window of `size` outcomes per host, 0 uses the default size
+    pub fn new(size: usize) -> Self {
+        SuccessRatioWindow {
+            size: if size == 0 { DEFAULT_SUCCESS_RATIO_WINDOW } else { size },
+            outcomes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record the outcome of a request to the host and return the host's success ratio within the window
+    pub fn record(&self, host: &str, success: bool) -> f64 {
+        let mut outcomes = self.outcomes.lock().unwrap();
+        let window = outcomes.entry(host.to_string()).or_default();
+        window.push_back(success);
+        while window.len() > self.size {
+            window.pop_front();
+        }
+
+        let successes = window.iter().filter(|s| **s).count();
+        successes as f64 / window.len() as f64
+    }
+
+    /// The success ratio of the host within the window, None before its first request
+    pub fn ratio(&self, host: &str) -> Option<f64> {
+        let outcomes = self.outcomes.lock().unwrap();
+        let window = outcomes.get(host).filter(|window| !window.is_empty())?;
+        let successes = window.iter().filter(|s| **s).count();
+        Some(successes as f64 / window.len() as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_success_ratio_window() {
+        let window = SuccessRatioWindow::new(4);
+        assert_eq!(window.record("a", true), 1.0);
+        assert_eq!(window.record("a", false), 0.5);
+        assert_eq!(window.record("a", true), 2.0 / 3.0);
+        assert_eq!(window.record("a", true), 0.75);
+
+        // the oldest outcome leaves the window
+        assert_eq!(window.record("a", true), 0.75);
+        assert_eq!(window.record("a", true), 1.0);
+
+        // hosts are tracked separately
+        assert_eq!(window.record("b", false), 0.0);
+        assert_eq!(window.ratio("a"), Some(1.0));
+        assert_eq!(window.ratio("c"), None);
+    }
+
+    #[test]
+    fn test_success_ratio_default_window() {
+        let window = SuccessRatioWindow::new(0);
+        for _ in 0..DEFAULT_SUCCESS_RATIO_WINDOW {
+            window.record("a", false);
+        }
+        assert_eq!(window.record("a", true), 1.0 / DEFAULT_SUCCESS_RATIO_WINDOW as f64);
+    }
+}
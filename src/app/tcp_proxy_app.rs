@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use async_trait::async_trait;
+use pingora::apps::ServerApp;
+use pingora::connectors::TransportConnector;
+use pingora::protocols::Stream;
+use pingora::server::ShutdownWatch;
+use pingora::upstreams::peer::BasicPeer;
+use pingora::Result;
+use pingora_load_balancing::{Backend, LoadBalancer};
+use pingora_load_balancing::prelude::RoundRobin;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use crate::app::config::DEFAULT_PEER_OPTIONS;
+use crate::app::selection::{highest_priority, weighted_select};
+use crate::config::redact_url;
+use crate::error::ProxyError;
+use crate::service::proxy::ChainProxyConfig;
+
+/// Tcp proxy app
+///
+/// Forwards the raw bytes of every downstream connection to a node selected at connection time,
+/// for node ports which don't speak http, e.g. bitcoin p2p.
+pub struct TcpProxyApp {
+    chain_name: String,
+
+    // key is the node uri, value is the cluster checked by connecting to the node
+    clusters: HashMap<String, Arc<LoadBalancer<RoundRobin>>>,
+
+    // host configs
+    host_configs: Vec<ChainProxyConfig>,
+
+    connector: TransportConnector,
+}
+
+impl TcpProxyApp {
+    pub fn new(
+        chain_name: String,
+        host_configs: Vec<ChainProxyConfig>,
+        clusters: HashMap<String, Arc<LoadBalancer<RoundRobin>>>,
+    ) -> Self {
+        TcpProxyApp {
+            chain_name,
+            clusters,
+            host_configs,
+            connector: TransportConnector::new(None),
+        }
+    }
+
+    /// Select a healthy node, the highest priority ones are preferred unless every node has a traffic weight
+    fn select_node(&self) -> Option<(&ChainProxyConfig, Backend)> {
+        let mut healthy = Vec::new();
+        let mut backends = HashMap::new();
+        for config in self.host_configs.iter() {
+            let backend = self
+                .clusters
+                .get(config.proxy_uri.as_str())
+                .and_then(|cluster| cluster.select(b"", 256));
+            if let Some(backend) = backend {
+                healthy.push(config);
+                backends.insert(config.proxy_uri.as_str(), backend);
+            }
+        }
+
+        let mut rng = thread_rng();
+        let selected = match weighted_select(&healthy, &mut rng) {
+            Some(selected) => selected,
+            None => *highest_priority(&healthy).choose(&mut rng)?,
+        };
+        let backend = backends.remove(selected.proxy_uri.as_str())?;
+        Some((selected, backend))
+    }
+
+    /// Connect to a selected node
+    async fn connect_upstream(&self) -> Result<(&ChainProxyConfig, Stream)> {
+        let (config, backend) = match self.select_node() {
+            Some(selected) => selected,
+            None => return ProxyError::NoEligibleCluster.explain("tcp proxy error"),
+        };
+
+        let mut peer = BasicPeer::new(&backend.addr.to_string());
+        peer.options.connection_timeout = DEFAULT_PEER_OPTIONS.connection_timeout;
+        let stream = self.connector.new_stream(&peer).await?;
+        Ok((config, stream))
+    }
+}
+
+#[async_trait]
+impl ServerApp for TcpProxyApp {
+    async fn process_new(self: &Arc<Self>, mut downstream: Stream, _shutdown: &ShutdownWatch) -> Option<Stream> {
+        let (config, mut upstream) = match self.connect_upstream().await {
+            Ok(connected) => connected,
+            Err(e) => {
+                log::error!("Chain {} failed to connect a tcp node: {e}", self.chain_name);
+                return None;
+            }
+        };
+
+        let node = redact_url(&config.proxy_uri);
+        match tokio::io::copy_bidirectional(&mut downstream, &mut upstream).await {
+            Ok((sent, received)) => {
+                log::debug!("Chain {} tcp connection to {node} closed, sent {sent}, received {received}", self.chain_name);
+            }
+            Err(e) => {
+                log::warn!("Chain {} tcp connection to {node} failed: {e}", self.chain_name);
+            }
+        }
+
+        // the raw stream is never reused
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingora::protocols::l4::stream::Stream as L4Stream;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use crate::test_util;
+
+    // spawn a tcp server writing back whatever it reads
+    async fn spawn_echo_server() -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = stream.split();
+                    let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                });
+            }
+        });
+        addr
+    }
+
+    // spawn the tcp proxy app on a local listener
+    async fn spawn_proxy(app: TcpProxyApp) -> std::net::SocketAddr {
+        let app = Arc::new(app);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (_tx, shutdown) = tokio::sync::watch::channel(false);
+            while let Ok((stream, _)) = listener.accept().await {
+                let app = app.clone();
+                let shutdown = shutdown.clone();
+                tokio::spawn(async move {
+                    let stream: Stream = Box::new(L4Stream::from(stream));
+                    app.process_new(stream, &shutdown).await;
+                });
+            }
+        });
+        addr
+    }
+
+    fn host_config(addr: std::net::SocketAddr, priority: i32) -> ChainProxyConfig {
+        ChainProxyConfig {
+            proxy_addr: addr.to_string(),
+            priority,
+            ..test_util::host_config(&format!("tcp://{addr}"))
+        }
+    }
+
+    fn clusters(host_configs: &[ChainProxyConfig]) -> HashMap<String, Arc<LoadBalancer<RoundRobin>>> {
+        host_configs
+            .iter()
+            .map(|c| {
+                let cluster = LoadBalancer::try_from_iter([c.proxy_addr.as_str()]).unwrap();
+                (c.proxy_uri.clone(), Arc::new(cluster))
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_tcp_proxy_echo() {
+        let echo = spawn_echo_server().await;
+        // the higher priority node is selected
+        let unused = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+        let host_configs = vec![host_config(echo, 2), host_config(unused, 1)];
+        let app = TcpProxyApp::new("bitcoin".to_string(), host_configs.clone(), clusters(&host_configs));
+        let proxy = spawn_proxy(app).await;
+
+        let mut client = TcpStream::connect(proxy).await.unwrap();
+        client.write_all(b"\xf9\xbe\xb4\xd9version").await.unwrap();
+        let mut buf = [0u8; 11];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"\xf9\xbe\xb4\xd9version");
+    }
+
+    #[tokio::test]
+    async fn test_select_node() {
+        let host_configs = vec![
+            host_config("127.0.0.1:8333".parse().unwrap(), 1),
+            host_config("127.0.0.1:18333".parse().unwrap(), 2),
+        ];
+        let app = TcpProxyApp::new("bitcoin".to_string(), host_configs.clone(), clusters(&host_configs));
+
+        let (config, backend) = app.select_node().unwrap();
+        assert_eq!(config.proxy_uri, "tcp://127.0.0.1:18333");
+        assert_eq!(backend.addr.to_string(), "127.0.0.1:18333");
+
+        let app = TcpProxyApp::new("bitcoin".to_string(), host_configs, HashMap::new());
+        assert!(app.select_node().is_none());
+    }
+}
@@ -0,0 +1,189 @@
+use std::fmt;
+
+use pingora::{Custom, Error, ErrorType, HTTPStatus, Result};
+
+/// Errors raised by the proxy apps and health checks
+///
+/// Each variant maps to a fixed message, which is carried as a [Custom] pingora error type,
+/// so an error can be matched back to its variant, e.g. for metric labels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProxyError {
+    // no node reported a block number yet
+    NoBlockNumber,
+    // no node passed the eligibility filter
+    NoEligibleCluster,
+    // the selected node has no cluster
+    ClusterNotFound,
+    // the health check response is not valid json
+    InvalidJson,
+    // the health check response is not a valid jsonrpc response
+    InvalidJsonrpc,
+    // the block number in the health check response can't be parsed
+    InvalidBlockNumber,
+    // the health check response reports a bad status
+    InvalidStatus,
+    // the configured health check method is not a valid http method
+    InvalidRequestMethod,
+    // the block height response header is missing or invalid
+    InvalidHeightHeader,
+    // the health check request can't be sent
+    FailedToSendRequest,
+    // the health check response body can't be read
+    FailedToReadResponseBody,
+    // the health check response body is rejected by the validator
+    FailedToValidateResponseBody,
+    // the upstream answered with an error
+    UpstreamError,
+    // the node is still syncing, e.g. in initial block download
+    NodeSyncing,
+    // the node has fewer peers than required
+    InsufficientPeers,
+    // fewer nodes than required report a block number
+    InsufficientHeightReporters,
+    // the node reports a height but its ledger stopped advancing
+    StaleLedger,
+    // the upstream response body exceeds the configured max size
+    ResponseBodyTooLarge,
+    // the node's tls certificate expires sooner than allowed
+    CertificateExpiring,
+    // the health check response status is not one of the expected ones
+    UnexpectedStatus,
+    // the verified state of a zk rollup node lags its latest block by more than allowed
+    VerificationLagging,
+    // the node was removed from the config by a reload, it is no longer probed
+    NodeRemoved,
+}
+
+impl ProxyError {
+    pub const ALL: [ProxyError; 22] = [
+        ProxyError::NoBlockNumber,
+        ProxyError::NoEligibleCluster,
+        ProxyError::ClusterNotFound,
+        ProxyError::InvalidJson,
+        ProxyError::InvalidJsonrpc,
+        ProxyError::InvalidBlockNumber,
+        ProxyError::InvalidStatus,
+        ProxyError::InvalidRequestMethod,
+        ProxyError::InvalidHeightHeader,
+        ProxyError::FailedToSendRequest,
+        ProxyError::FailedToReadResponseBody,
+        ProxyError::FailedToValidateResponseBody,
+        ProxyError::UpstreamError,
+        ProxyError::NodeSyncing,
+        ProxyError::InsufficientPeers,
+        ProxyError::InsufficientHeightReporters,
+        ProxyError::StaleLedger,
+        ProxyError::ResponseBodyTooLarge,
+        ProxyError::CertificateExpiring,
+        ProxyError::UnexpectedStatus,
+        ProxyError::VerificationLagging,
+        ProxyError::NodeRemoved,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProxyError::NoBlockNumber => {
+                "No block number found, maybe health check is unavailable or system is starting"
+            }
+            ProxyError::NoEligibleCluster => "No eligible cluster found",
+            ProxyError::ClusterNotFound => "Cluster not found",
+            ProxyError::InvalidJson => "invalid json",
+            ProxyError::InvalidJsonrpc => "invalid jsonrpc",
+            ProxyError::InvalidBlockNumber => "invalid block number",
+            ProxyError::InvalidStatus => "invalid status",
+            ProxyError::InvalidRequestMethod => "invalid request method",
+            ProxyError::InvalidHeightHeader => "invalid height header",
+            ProxyError::FailedToSendRequest => "failed to send request",
+            ProxyError::FailedToReadResponseBody => "failed to read response body",
+            ProxyError::FailedToValidateResponseBody => "failed to validate response body",
+            ProxyError::UpstreamError => "request failed",
+            ProxyError::NodeSyncing => "node is syncing",
+            ProxyError::InsufficientPeers => "insufficient peers",
+            ProxyError::InsufficientHeightReporters => "insufficient height reporters",
+            ProxyError::StaleLedger => "stale ledger",
+            ProxyError::ResponseBodyTooLarge => "response body too large",
+            ProxyError::CertificateExpiring => "certificate expiring",
+            ProxyError::UnexpectedStatus => "unexpected status",
+            ProxyError::VerificationLagging => "verification lagging",
+            ProxyError::NodeRemoved => "node removed",
+        }
+    }
+
+    /// The pingora error type of this error
+    pub fn error_type(&self) -> ErrorType {
+        Custom(self.as_str())
+    }
+
+    /// Return a pingora error of this kind with the given context
+    pub fn explain<T>(self, context: &'static str) -> Result<T> {
+        Error::e_explain(self.error_type(), context)
+    }
+
+    /// Return a pingora error answered to the client with the given http status,
+    /// the error message is kept as context
+    pub fn http_status<T>(self, code: u16) -> Result<T> {
+        Error::e_explain(HTTPStatus(code), self.as_str())
+    }
+
+    /// Find the [ProxyError] a pingora error was built from
+    pub fn from_error(e: &Error) -> Option<ProxyError> {
+        match e.etype() {
+            Custom(s) => ProxyError::ALL.iter().find(|v| v.as_str() == *s).copied(),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::error::Error for ProxyError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proxy_error_display() {
+        assert_eq!(
+            ProxyError::NoBlockNumber.to_string(),
+            "No block number found, maybe health check is unavailable or system is starting"
+        );
+        assert_eq!(ProxyError::NoEligibleCluster.to_string(), "No eligible cluster found");
+        assert_eq!(ProxyError::ClusterNotFound.to_string(), "Cluster not found");
+        assert_eq!(ProxyError::InvalidJson.to_string(), "invalid json");
+        assert_eq!(ProxyError::InvalidJsonrpc.to_string(), "invalid jsonrpc");
+        assert_eq!(ProxyError::InvalidBlockNumber.to_string(), "invalid block number");
+        assert_eq!(ProxyError::InvalidStatus.to_string(), "invalid status");
+        assert_eq!(ProxyError::InvalidRequestMethod.to_string(), "invalid request method");
+        assert_eq!(ProxyError::InvalidHeightHeader.to_string(), "invalid height header");
+        assert_eq!(ProxyError::FailedToSendRequest.to_string(), "failed to send request");
+        assert_eq!(ProxyError::FailedToReadResponseBody.to_string(), "failed to read response body");
+        assert_eq!(
+            ProxyError::FailedToValidateResponseBody.to_string(),
+            "failed to validate response body"
+        );
+        assert_eq!(ProxyError::UpstreamError.to_string(), "request failed");
+        assert_eq!(ProxyError::NodeSyncing.to_string(), "node is syncing");
+        assert_eq!(ProxyError::InsufficientPeers.to_string(), "insufficient peers");
+        assert_eq!(ProxyError::InsufficientHeightReporters.to_string(), "insufficient height reporters");
+        assert_eq!(ProxyError::StaleLedger.to_string(), "stale ledger");
+        assert_eq!(ProxyError::ResponseBodyTooLarge.to_string(), "response body too large");
+        assert_eq!(ProxyError::CertificateExpiring.to_string(), "certificate expiring");
+        assert_eq!(ProxyError::VerificationLagging.to_string(), "verification lagging");
+    }
+
+    #[test]
+    fn test_proxy_error_from_error() {
+        for variant in ProxyError::ALL.iter() {
+            let e: Result<()> = variant.explain("proxy error");
+            assert_eq!(ProxyError::from_error(&e.unwrap_err()), Some(*variant));
+        }
+
+        let e = Error::explain(ErrorType::ConnectTimedout, "connect");
+        assert_eq!(ProxyError::from_error(&e), None);
+    }
+}
@@ -3,7 +3,7 @@ use clap::Parser;
 use pingora::server::configuration::ServerConf;
 use pingora::{
     server::{configuration::Opt, Server},
-    services::Service,
+    services::{background::background_service, Service},
 };
 use std::sync::Arc;
 use structopt::StructOpt;
@@ -20,10 +20,12 @@ static GLOBAL: jemallocator::Jemalloc = jemallocator::Jemalloc;
 #[macro_use]
 extern crate lazy_static;
 
-use crate::config::{Config, Node, Chain, Common};
+use crate::config::{redact_url, Config, Node, Chain, Common};
 use crate::config::LOG_CONFIG;
+use crate::app::load_shedding::LOAD_SHEDDER;
 use std::path::PathBuf;
 use std::sync::RwLock;
+use std::time::Duration;
 use url::Url;
 
 lazy_static! {
@@ -32,8 +34,11 @@ lazy_static! {
 
 mod app;
 mod config;
+mod error;
 mod service;
 mod metrics;
+#[cfg(test)]
+mod test_util;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "chain-proxy")]
@@ -45,10 +50,20 @@ struct ChainOpt {
     /// Perform an upgrade
     #[structopt(long)]
     upgrade: bool,
+
+    /// Run one health check against every node, print the results and exit
+    #[structopt(long)]
+    probe_nodes: bool,
+
+    /// Log why every request is sent to its upstream node
+    #[structopt(long)]
+    selection_log: bool,
 }
 
 fn create_chain_proxy_config(node: &Node, chain: &Chain) -> Option<service::proxy::ChainProxyConfig> {
-    let node_url = node.address();
+    // a missing scheme or port is filled in from the chain type
+    let node_url = config::effective_node_address(node.address(), chain.chain_type());
+    let node_url = node_url.as_str();
     let url = Url::parse(node_url).ok()?;
     let host_str = url.host_str()?;
     let port = match url.scheme() {
@@ -61,6 +76,8 @@ fn create_chain_proxy_config(node: &Node, chain: &Chain) -> Option<service::prox
         proxy_addr: format!("{}:{}", host_str, port),
         proxy_tls: url.scheme() == "https",
         proxy_hostname: host_str.to_string(),
+        proxy_sni: node.tls_sni(host_str),
+        insecure_skip_verify: node.insecure_skip_verify(),
         proxy_uri: node_url.to_string(),
         priority: node.priority(),
         path: chain.health_check().path().to_string(),
@@ -68,7 +85,40 @@ fn create_chain_proxy_config(node: &Node, chain: &Chain) -> Option<service::prox
         request_body: Option::from(chain.health_check().request_body().as_bytes().to_vec()),
         chain_type: chain.chain_type().to_string(),
         interval: chain.interval(),
-        block_gap: chain.block_gap(),
+        // the gap of the node's priority tier, validated at config load
+        block_gap: chain.block_gap().for_priority(node.priority()).unwrap_or(0),
+        retries: chain.health_check_retries(),
+        retry_backoff_ms: chain.health_check_retry_backoff_ms(),
+        height_header: chain.health_check_height_header().map(|h| h.to_string()),
+        expected_status: chain.health_check_expected_status(),
+        traffic_weight: node.traffic_weight_percent(),
+        result_path: chain.health_check_result_path().map(|p| p.to_string()),
+        result_type: chain.health_check_result_type().unwrap_or_default(),
+        min_peer_count: chain.min_peer_count(),
+        max_syncing_gap: chain.max_syncing_gap(),
+        check_gas_price: chain.check_gas_price(),
+        expected_spec_name: chain.expected_spec_name().map(|s| s.to_string()),
+        system_health_min_peers: chain.system_health_min_peers(),
+        verification_gap: chain.verification_gap().cloned(),
+        min_height_reporters: chain.min_height_reporters(),
+        block_tag: chain.health_check_block_tag(),
+        dns_refresh_interval: chain.dns_refresh_interval(),
+        health_check_workers: chain.health_check_workers(),
+        consecutive_success: chain.health_threshold(node.priority()).consecutive_success(),
+        consecutive_failure: chain.health_threshold(node.priority()).consecutive_failure(),
+        vars: node.vars().clone(),
+        tags: node.tags().clone(),
+        max_ledger_age: chain.max_ledger_age_secs().map(Duration::from_secs),
+        secondary_probes: chain.secondary_health_checks().to_vec(),
+        upstream_proxy: chain.upstream_proxy().map(|p| p.to_string()),
+        cert_expiry_warn_days: chain.cert_expiry_warn_days(),
+        cert_expiry_min_days: chain.cert_expiry_min_days(),
+        health_check_timeout: chain.health_check_timeout_ms().map(Duration::from_millis),
+        upstream_read_timeout: chain.upstream_read_timeout_ms().map(Duration::from_millis),
+        alpn: chain.alpn(),
+        healthy_status_codes: Vec::new(),
+        healthy_status_ranges: Vec::new(),
+        ignore_status_codes: Vec::new(),
     })
 }
 
@@ -79,6 +129,8 @@ fn create_common_proxy_config(node: &Node, common: &Common) -> Option<service::p
     let port = match url.scheme() {
         "http" => url.port().unwrap_or(80),
         "https" => url.port().unwrap_or(443),
+        // raw tcp nodes of the tcp protocol
+        "tcp" => url.port()?,
         _ => return None,
     };
 
@@ -86,6 +138,8 @@ fn create_common_proxy_config(node: &Node, common: &Common) -> Option<service::p
         proxy_addr: format!("{}:{}", host_str, port),
         proxy_tls: url.scheme() == "https",
         proxy_hostname: host_str.to_string(),
+        proxy_sni: node.tls_sni(host_str),
+        insecure_skip_verify: node.insecure_skip_verify(),
         proxy_uri: node_url.to_string(),
         priority: node.priority(),
         path: common.health_check().path().to_string(),
@@ -94,10 +148,131 @@ fn create_common_proxy_config(node: &Node, common: &Common) -> Option<service::p
         interval: common.interval(),
         block_gap: 0,
         chain_type: "".to_string(),
+        retries: 0,
+        retry_backoff_ms: 0,
+        height_header: None,
+        expected_status: Vec::new(),
+        traffic_weight: node.traffic_weight_percent(),
+        result_path: None,
+        result_type: Default::default(),
+        min_peer_count: None,
+        max_syncing_gap: None,
+        check_gas_price: false,
+        expected_spec_name: None,
+        system_health_min_peers: None,
+        verification_gap: None,
+        min_height_reporters: 0,
+        block_tag: None,
+        dns_refresh_interval: config::DEFAULT_DNS_REFRESH_INTERVAL,
+        health_check_workers: 0,
+        consecutive_success: 1,
+        consecutive_failure: 1,
+        vars: node.vars().clone(),
+        tags: node.tags().clone(),
+        max_ledger_age: None,
+        secondary_probes: Vec::new(),
+        upstream_proxy: None,
+        cert_expiry_warn_days: None,
+        cert_expiry_min_days: None,
+        health_check_timeout: common.health_check_timeout_ms().map(Duration::from_millis),
+        upstream_read_timeout: common.upstream_read_timeout_ms().map(Duration::from_millis),
+        alpn: None,
+        healthy_status_codes: common.healthy_status_codes().to_vec(),
+        healthy_status_ranges: common.healthy_status_ranges(),
+        ignore_status_codes: common.ignore_status_codes().to_vec(),
     })
 }
 
 
+/// Export the tags of every node, labelled with the hostname as the request metrics of the node
+fn set_node_tags(config: &Config) {
+    for chain in &config.chains {
+        for node in chain.nodes().iter() {
+            if let Some(host_config) = create_chain_proxy_config(node, chain) {
+                metrics::set_node_tags(chain.name(), &host_config.proxy_hostname, node.tags());
+            }
+        }
+    }
+    for common in &config.commons {
+        for node in common.nodes().iter() {
+            if let Some(host_config) = create_common_proxy_config(node, common) {
+                metrics::set_node_tags(common.name(), &host_config.proxy_hostname, node.tags());
+            }
+        }
+    }
+}
+
+fn create_chain_proxy_options(chain: &Chain) -> service::proxy::ProxyOptions {
+    service::proxy::ProxyOptions {
+        forward_client_ip: chain.forward_client_ip(),
+        sticky_window: match chain.sticky_window_ms() {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        },
+        error_sample_rate: chain.error_sample_rate(),
+        jsonrpc_error_response: chain.jsonrpc_error_response(),
+        validate_jsonrpc_request: chain.validate_jsonrpc_request(),
+        no_upstream_status_code: chain.no_upstream_status_code(),
+        sla: match chain.sla_ms() {
+            0 => None,
+            ms => Some(Duration::from_millis(ms)),
+        },
+        success_ratio_window: chain.success_ratio_window(),
+        max_client_in_flight: chain.max_client_in_flight_requests(),
+        max_concurrent: chain.max_concurrent_requests(),
+        queue_timeout: Duration::from_millis(chain.queue_timeout_ms()),
+        max_queued: chain.max_queued_requests(),
+        method_rewrites: chain.method_rewrites().clone(),
+        method_timeouts: chain
+            .method_timeouts()
+            .iter()
+            .map(|(method, secs)| (method.clone(), Duration::from_secs(*secs)))
+            .collect(),
+        rewrite_jsonrpc_ids: chain.rewrite_jsonrpc_ids(),
+        retry_on_status: chain.retry_on_status().to_vec(),
+        idempotent_methods: chain.idempotent_methods().to_vec(),
+        server_timing: chain.server_timing(),
+        adaptive_priority: chain.adaptive_priority(),
+        selection: chain.selection(),
+        health_score_weights: chain.health_score_weights(),
+        region_affinity: chain.region_affinity().cloned(),
+        // the route nodes are matched against the effective node addresses
+        path_routes: chain
+            .path_routes()
+            .iter()
+            .map(|route| {
+                let nodes = route
+                    .nodes()
+                    .iter()
+                    .map(|address| config::effective_node_address(address, chain.chain_type()))
+                    .collect();
+                config::PathRoute::new(route.path_prefix(), nodes)
+            })
+            .collect(),
+        passive_height: chain.passive_height(),
+        warmup: match chain.warmup_seconds() {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        },
+        max_response_body_bytes: match chain.max_response_body_bytes() {
+            0 => None,
+            bytes => Some(bytes),
+        },
+        compress_responses: chain.compress_responses(),
+        tls: chain.tls().cloned(),
+    }
+}
+
+fn create_common_proxy_options(common: &Common) -> service::proxy::ProxyOptions {
+    service::proxy::ProxyOptions {
+        max_response_body_bytes: match common.max_response_body_bytes() {
+            0 => None,
+            bytes => Some(bytes),
+        },
+        ..Default::default()
+    }
+}
+
 fn create_services_from_config(server_conf: &Arc<ServerConf>) -> Vec<Box<dyn Service>> {
     let mut services: Vec<Box<dyn Service>> = Vec::new();
 
@@ -142,22 +317,31 @@ fn create_services_from_config(server_conf: &Arc<ServerConf>) -> Vec<Box<dyn Ser
         }
 
 
-        let (chain_proxy_service, cluster_services) = service::proxy::new_chain_proxy_service(
+        let result = service::proxy::new_chain_proxy_service(
             chain.name(),
             chain.protocol(),
             server_conf,
             &format!("0.0.0.0:{http_port}"),
             host_configs,
             special_method_configs,
+            create_chain_proxy_options(chain),
         );
+        let (chain_proxy_service, cluster_services) = match result {
+            Ok(result) => result,
+            Err(e) => {
+                log::error!("Failed to create chain {} proxy service: {e}", chain.name());
+                std::process::exit(1);
+            }
+        };
 
         let chain_name = chain.name();
         // print chain proxy info
         log::info!(
-            "Chain {} proxy service created, listening on {}, \
-            interval: {}, block_gap: {}",
+            "Chain {} proxy service created, listening on {}, tls: {}, \
+            interval: {}, block_gap: {:?}",
             chain_name,
             http_port,
+            chain.tls().is_some(),
             chain.interval(),
             chain.block_gap()
         );
@@ -183,6 +367,26 @@ fn create_services_from_config(server_conf: &Arc<ServerConf>) -> Vec<Box<dyn Ser
             }
         }
 
+        // a tcp common forwards raw bytes, there are no special methods
+        if common.protocol() == "tcp" {
+            let (tcp_proxy_service, cluster_services) = service::proxy::new_tcp_proxy_service(
+                common.name(),
+                &format!("0.0.0.0:{http_port}"),
+                host_configs,
+            );
+
+            log::info!(
+                "Common {} tcp proxy service created, listening on {}, interval: {}",
+                common.name(),
+                http_port,
+                common.interval()
+            );
+
+            services.push(Box::new(tcp_proxy_service));
+            services.extend(cluster_services);
+            continue;
+        }
+
         // from common config to special method config
         let special_methods = common.special_methods();
         let mut special_method_configs = Vec::new();
@@ -213,6 +417,7 @@ fn create_services_from_config(server_conf: &Arc<ServerConf>) -> Vec<Box<dyn Ser
             &format!("0.0.0.0:{http_port}"),
             host_configs,
             special_method_configs,
+            create_common_proxy_options(common),
         );
 
         let common_name = common.name();
@@ -234,10 +439,52 @@ fn create_services_from_config(server_conf: &Arc<ServerConf>) -> Vec<Box<dyn Ser
     services
 }
 
+/// Probe every node of the chains and commons once and print a table of the results,
+/// return whether all nodes passed
+fn probe_nodes() -> bool {
+    let config = CONFIG.read().unwrap();
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            log::error!("Failed to create runtime: {e}");
+            return false;
+        }
+    };
+
+    let results = runtime.block_on(async {
+        let mut results = Vec::new();
+        for chain in &config.chains {
+            let host_configs: Vec<_> = chain
+                .nodes()
+                .iter()
+                .filter_map(|node| create_chain_proxy_config(node, chain))
+                .collect();
+            results.extend(service::probe::probe_chain_nodes(chain.name(), &host_configs).await);
+        }
+        // tcp commons have no http health check to probe
+        for common in config.commons.iter().filter(|common| common.protocol() != "tcp") {
+            let host_configs: Vec<_> = common
+                .nodes()
+                .iter()
+                .filter_map(|node| create_common_proxy_config(node, common))
+                .collect();
+            results.extend(service::probe::probe_common_nodes(common.name(), &host_configs).await);
+        }
+        results
+    });
+
+    println!("{}", service::probe::format_probe_table(&results));
+    results.iter().all(|probe| probe.passed())
+}
+
 pub fn main() {
-    // init log
+    let chain_opt = ChainOpt::from_args();
+
+    // init log, the selection logs have their own logger
+    let log_config = format!("{LOG_CONFIG}{}", config::selection_logger_config(chain_opt.selection_log));
     let config =
-        serde_yaml::from_str::<log4rs::config::RawConfig>(&LOG_CONFIG.to_string()).unwrap();
+        serde_yaml::from_str::<log4rs::config::RawConfig>(&log_config).unwrap();
 
     // Initialize log4rs with the parsed configuration
     log4rs::init_raw_config(config).unwrap();
@@ -246,7 +493,6 @@ pub fn main() {
     service::chain_health_check::init_chain_checker();
 
     // load config
-    let chain_opt = ChainOpt::from_args();
     let config_path = chain_opt.config.unwrap_or_else(|| "config.yaml".into());
     match Config::load_config(&config_path) {
         Ok(_) => {
@@ -258,6 +504,12 @@ pub fn main() {
         }
     }
 
+    // probe the nodes once instead of starting the proxy
+    if chain_opt.probe_nodes {
+        let passed = probe_nodes();
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+
     let mut opts: Vec<String> = vec![
         "chain-proxy".into(),
         "-c".into(),
@@ -281,17 +533,58 @@ pub fn main() {
 
     my_server.add_services(services);
 
+    // apply the maintenance of the chains, then reload it from the config file on SIGHUP
+    service::reload::apply_maintenance(&CONFIG.read().unwrap());
+    my_server.add_service(background_service(
+        "config reloader",
+        service::reload::ConfigReloader::new(config_path),
+    ));
+
     // init metrics
-    metrics::init_metrics(CONFIG.read().unwrap().monitor.system()).unwrap();
+    {
+        let config = CONFIG.read().unwrap();
+        metrics::init_metrics(config.monitor.system(), config.monitor.labels(), config.monitor.tag_labels()).unwrap();
+        set_node_tags(&config);
+        LOAD_SHEDDER.set_max_in_flight(config.monitor.max_in_flight_requests());
+    }
 
     // add prometheus service
-    let monitor_listen = CONFIG.read().unwrap().monitor.listen();
+    let monitor_listen = CONFIG.read().unwrap().monitor.listen_addr();
     let mut prometheus_service_http =
         pingora::services::listening::Service::prometheus_http_service();
-    prometheus_service_http.add_tcp(format!("0.0.0.0:{monitor_listen}").as_str());
+    prometheus_service_http.add_tcp(monitor_listen.as_str());
 
     log::info!("Prometheus service created, listening on {monitor_listen}");
     my_server.add_service(prometheus_service_http);
 
+    // add otlp exporter if configured, pushing the same metrics
+    let otlp_exporter = {
+        let config = CONFIG.read().unwrap();
+        config.monitor.otlp_endpoint().map(|endpoint| {
+            service::otlp::OtlpExporter::new(
+                endpoint,
+                config.monitor.system(),
+                Duration::from_secs(config.monitor.otlp_interval()),
+            )
+        })
+    };
+    if let Some(otlp_exporter) = otlp_exporter {
+        log::info!("Otlp exporter created, pushing to {}", redact_url(otlp_exporter.endpoint()));
+        my_server.add_service(background_service("otlp exporter", otlp_exporter));
+    }
+
+    // add admin service if configured
+    let admin_listen = CONFIG.read().unwrap().admin.as_ref().map(|admin| admin.listen_addr());
+    if let Some(admin_listen) = admin_listen {
+        let mut admin_service = pingora::services::listening::Service::new(
+            "admin".to_string(),
+            service::admin::AdminApp,
+        );
+        admin_service.add_tcp(admin_listen.as_str());
+
+        log::info!("Admin service created, listening on {admin_listen}");
+        my_server.add_service(admin_service);
+    }
+
     my_server.run_forever();
 }
@@ -1,7 +1,11 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Mutex;
+use std::time::Duration;
 
 use lazy_static::lazy_static;
-use prometheus::{GaugeVec, CounterVec, Opts, default_registry};
+use prometheus::core::Collector;
+use prometheus::proto::MetricFamily;
+use prometheus::{GaugeVec, CounterVec, HistogramOpts, HistogramVec, Opts, default_registry};
 
 #[derive(Clone)]
 pub struct Metrics {
@@ -12,32 +16,149 @@ pub struct Metrics {
 
     // node health gauge
     pub node_health_gauge: GaugeVec,
+
+    // grpc result counter, by the grpc-status of the response
+    pub grpc_result_counter: CounterVec,
+
+    // requests exceeding the chain sla
+    pub sla_violation_counter: CounterVec,
+
+    // success ratio of the recent proxied requests of a node
+    pub node_success_ratio_gauge: GaugeVec,
+
+    // composite health score of a node, from 0 to 1
+    pub node_health_score_gauge: GaugeVec,
+
+    // request body bytes sent to a node
+    pub request_bytes_counter: CounterVec,
+
+    // response body bytes received from a node
+    pub response_bytes_counter: CounterVec,
+
+    // days until the tls certificate of a node expires
+    pub cert_expiry_days_gauge: GaugeVec,
+
+    // elapsed time of the health check probes of a node
+    pub health_check_latency_histogram: HistogramVec,
+
+    // tags of a node, always 1, the configured tag keys are its labels
+    pub node_tags_gauge: GaugeVec,
+
+    // tag keys exported as labels, in the label order of the node tags gauge
+    tag_labels: Vec<String>,
+
+    // hosts of the nodes removed from the config by chain, their node series are deleted and no longer set
+    removed_hosts: HashMap<String, HashSet<String>>,
 }
 
+// buckets of the health check latency in seconds, up to the default probe timeout
+const HEALTH_CHECK_LATENCY_BUCKETS: [f64; 11] = [0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 60.0];
+
 impl Metrics {
     pub fn new(namespace: &str) -> Self {
+        Metrics::with_labels(namespace, &HashMap::new(), &[])
+    }
+
+    /// Create the metrics with constant labels on every series, e.g. the env of a multi-tenant deployment, and the node tag keys exported as labels of the node tags gauge
+    pub fn with_labels(namespace: &str, const_labels: &HashMap<String, String>, tag_labels: &[String]) -> Self {
+        let opts = |name: &str, help: &str| {
+            Opts::new(name, help)
+                .namespace(namespace)
+                .const_labels(const_labels.clone())
+        };
+
         let node_height_gauge = GaugeVec::new(
-            Opts::new("node_height_gauge", "node height gauge").namespace(namespace),
+            opts("node_height_gauge", "node height gauge"),
             &["chain", "host"],
         )
             .unwrap();
 
         let proxy_result_counter = CounterVec::new(
-            Opts::new("proxy_result_counter", "proxy result counter").namespace(namespace),
+            opts("proxy_result_counter", "proxy result counter"),
             &["chain", "host", "code", "method"],
         )
             .unwrap();
 
         let node_health_gauge = GaugeVec::new(
-            Opts::new("node_health_gauge", "node health gauge").namespace(namespace),
+            opts("node_health_gauge", "node health gauge"),
             &["node", "host"],
         )
             .unwrap();
 
+        let grpc_result_counter = CounterVec::new(
+            opts("grpc_result_counter", "grpc result counter"),
+            &["chain", "host", "grpc_status"],
+        )
+            .unwrap();
+
+        let sla_violation_counter = CounterVec::new(
+            opts("sla_violation_total", "requests exceeding the chain sla"),
+            &["chain", "host"],
+        )
+            .unwrap();
+
+        let node_success_ratio_gauge = GaugeVec::new(
+            opts("node_success_ratio_gauge", "success ratio of the recent proxied requests"),
+            &["chain", "host"],
+        )
+            .unwrap();
+
+        let node_health_score_gauge = GaugeVec::new(
+            opts("node_health_score_gauge", "composite health score of freshness, latency and success ratio"),
+            &["chain", "host"],
+        )
+            .unwrap();
+
+        let request_bytes_counter = CounterVec::new(
+            opts("request_bytes_total", "request body bytes sent to the node"),
+            &["chain", "host"],
+        )
+            .unwrap();
+
+        let response_bytes_counter = CounterVec::new(
+            opts("response_bytes_total", "response body bytes received from the node"),
+            &["chain", "host"],
+        )
+            .unwrap();
+
+        let cert_expiry_days_gauge = GaugeVec::new(
+            opts("cert_expiry_days_gauge", "days until the node tls certificate expires"),
+            &["chain", "host"],
+        )
+            .unwrap();
+
+        let health_check_latency_histogram = HistogramVec::new(
+            HistogramOpts::new("health_check_latency_seconds", "elapsed time of the node health check probes")
+                .namespace(namespace)
+                .const_labels(const_labels.clone())
+                .buckets(HEALTH_CHECK_LATENCY_BUCKETS.to_vec()),
+            &["chain", "host"],
+        )
+            .unwrap();
+
+        let node_tags_labels: Vec<&str> =
+            ["chain", "host"].into_iter().chain(tag_labels.iter().map(|label| label.as_str())).collect();
+        let node_tags_gauge = GaugeVec::new(
+            opts("node_tags", "tags of the nodes, always 1, to join with the metrics of a node"),
+            &node_tags_labels,
+        )
+            .unwrap();
+
         Metrics {
             node_height_gauge,
             proxy_result_counter,
             node_health_gauge,
+            grpc_result_counter,
+            sla_violation_counter,
+            node_success_ratio_gauge,
+            node_health_score_gauge,
+            request_bytes_counter,
+            response_bytes_counter,
+            cert_expiry_days_gauge,
+            health_check_latency_histogram,
+            node_tags_gauge,
+            tag_labels: tag_labels.to_vec(),
+            removed_hosts: HashMap::new(),
         }
     }
 
@@ -46,11 +167,42 @@ impl Metrics {
         registry.register(Box::new(self.node_height_gauge.clone()))?;
         registry.register(Box::new(self.proxy_result_counter.clone()))?;
         registry.register(Box::new(self.node_health_gauge.clone()))?;
+        registry.register(Box::new(self.grpc_result_counter.clone()))?;
+        registry.register(Box::new(self.sla_violation_counter.clone()))?;
+        registry.register(Box::new(self.node_success_ratio_gauge.clone()))?;
+        registry.register(Box::new(self.node_health_score_gauge.clone()))?;
+        registry.register(Box::new(self.request_bytes_counter.clone()))?;
+        registry.register(Box::new(self.response_bytes_counter.clone()))?;
+        registry.register(Box::new(self.cert_expiry_days_gauge.clone()))?;
+        registry.register(Box::new(self.health_check_latency_histogram.clone()))?;
+        registry.register(Box::new(self.node_tags_gauge.clone()))?;
 
         Ok(self)
     }
 
+    /// The current values of every metric, the same ones served to prometheus
+    pub fn collect(&self) -> Vec<MetricFamily> {
+        let collectors: [&dyn Collector; 12] = [
+            &self.node_height_gauge,
+            &self.proxy_result_counter,
+            &self.node_health_gauge,
+            &self.grpc_result_counter,
+            &self.sla_violation_counter,
+            &self.node_success_ratio_gauge,
+            &self.node_health_score_gauge,
+            &self.request_bytes_counter,
+            &self.response_bytes_counter,
+            &self.cert_expiry_days_gauge,
+            &self.health_check_latency_histogram,
+            &self.node_tags_gauge,
+        ];
+        collectors.iter().flat_map(|collector| collector.collect()).collect()
+    }
+
     pub fn set_node_height_gauge(&self, chain: &str, host: &str, height: u64) {
+        if self.is_removed(chain, host) {
+            return;
+        }
         self.node_height_gauge
             .with_label_values(&[chain, &host])
             .set(height as f64);
@@ -63,19 +215,129 @@ impl Metrics {
     }
 
     pub fn set_node_health_gauge(&self, node: &str, host: &str, is_healthy: bool) {
+        if self.is_removed(node, host) {
+            return;
+        }
         let value = if is_healthy { 1.0 } else { 0.0 };
         self.node_health_gauge
             .with_label_values(&[node, host])
             .set(value);
     }
+
+    pub fn inc_grpc_result_counter(&self, chain: &str, host: &str, grpc_status: &str) {
+        self.grpc_result_counter
+            .with_label_values(&[chain, host, grpc_status])
+            .inc();
+    }
+
+    /// Count the request as a sla violation if its latency exceeds the sla
+    pub fn observe_sla(&self, chain: &str, host: &str, latency: Duration, sla: Duration) {
+        if latency > sla {
+            self.sla_violation_counter
+                .with_label_values(&[chain, host])
+                .inc();
+        }
+    }
+
+    pub fn set_node_success_ratio_gauge(&self, chain: &str, host: &str, ratio: f64) {
+        if self.is_removed(chain, host) {
+            return;
+        }
+        self.node_success_ratio_gauge
+            .with_label_values(&[chain, host])
+            .set(ratio);
+    }
+
+    pub fn set_node_health_score_gauge(&self, chain: &str, host: &str, score: f64) {
+        if self.is_removed(chain, host) {
+            return;
+        }
+        self.node_health_score_gauge
+            .with_label_values(&[chain, host])
+            .set(score);
+    }
+
+    pub fn inc_request_bytes(&self, chain: &str, host: &str, bytes: usize) {
+        self.request_bytes_counter
+            .with_label_values(&[chain, host])
+            .inc_by(bytes as f64);
+    }
+
+    pub fn inc_response_bytes(&self, chain: &str, host: &str, bytes: usize) {
+        self.response_bytes_counter
+            .with_label_values(&[chain, host])
+            .inc_by(bytes as f64);
+    }
+
+    pub fn set_cert_expiry_days_gauge(&self, chain: &str, host: &str, days: i64) {
+        if self.is_removed(chain, host) {
+            return;
+        }
+        self.cert_expiry_days_gauge
+            .with_label_values(&[chain, host])
+            .set(days as f64);
+    }
+
+    pub fn observe_health_check_latency(&self, chain: &str, host: &str, latency: Duration) {
+        if self.is_removed(chain, host) {
+            return;
+        }
+        self.health_check_latency_histogram
+            .with_label_values(&[chain, host])
+            .observe(latency.as_secs_f64());
+    }
+
+    /// Set the tags of a node, only the configured tag keys are labels, a missing one is empty
+    pub fn set_node_tags(&self, chain: &str, host: &str, tags: &BTreeMap<String, String>) {
+        if self.tag_labels.is_empty() {
+            return;
+        }
+
+        let mut values = vec![chain, host];
+        values.extend(self.tag_labels.iter().map(|label| tags.get(label).map_or("", |v| v.as_str())));
+        self.node_tags_gauge.with_label_values(&values).set(1.0);
+    }
+
+    /// Delete the node series of a host, the height, health, success ratio, health score, cert expiry and
+    /// health check latency, a missing series is ignored
+    pub fn remove_node_series(&self, chain: &str, host: &str) {
+        let labels = [chain, host];
+        let _ = self.node_height_gauge.remove_label_values(&labels);
+        let _ = self.node_health_gauge.remove_label_values(&labels);
+        let _ = self.node_success_ratio_gauge.remove_label_values(&labels);
+        let _ = self.node_health_score_gauge.remove_label_values(&labels);
+        let _ = self.cert_expiry_days_gauge.remove_label_values(&labels);
+        let _ = self.health_check_latency_histogram.remove_label_values(&labels);
+    }
+
+    /// Set the hosts of the nodes removed from the config of a chain, their node series are deleted and
+    /// no longer set while the running proxy still probes them, a host added back is exported again
+    pub fn set_removed_hosts(&mut self, chain: &str, hosts: HashSet<String>) {
+        for host in hosts.iter() {
+            self.remove_node_series(chain, host);
+        }
+        if hosts.is_empty() {
+            self.removed_hosts.remove(chain);
+        } else {
+            self.removed_hosts.insert(chain.to_string(), hosts);
+        }
+    }
+
+    fn is_removed(&self, chain: &str, host: &str) -> bool {
+        self.removed_hosts.get(chain).is_some_and(|hosts| hosts.contains(host))
+    }
 }
 
 lazy_static! {
     pub static ref METRICS: Mutex<Option<Metrics>> = Mutex::new(None);
 }
 
-pub fn init_metrics(system: &str) -> Result<(), prometheus::Error> {
-    let metrics = Metrics::new(system).register()?;
+pub fn init_metrics(
+    system: &str,
+    const_labels: &HashMap<String, String>,
+    tag_labels: &[String],
+) -> Result<(), prometheus::Error> {
+    let metrics = Metrics::with_labels(system, const_labels, tag_labels).register()?;
     let mut metrics_lock = METRICS.lock().unwrap();
     *metrics_lock = Some(metrics);
 
@@ -103,6 +365,91 @@ pub fn set_node_health_gauge(node: &str, host: &str, is_healthy: bool) {
     }
 }
 
+pub fn inc_grpc_result_counter(chain: &str, host: &str, grpc_status: &str) {
+    let metrics_lock = METRICS.lock().unwrap();
+    if let Some(metrics) = &*metrics_lock {
+        metrics.inc_grpc_result_counter(chain, host, grpc_status);
+    }
+}
+
+pub fn observe_sla(chain: &str, host: &str, latency: Duration, sla: Duration) {
+    let metrics_lock = METRICS.lock().unwrap();
+    if let Some(metrics) = &*metrics_lock {
+        metrics.observe_sla(chain, host, latency, sla);
+    }
+}
+
+pub fn set_node_success_ratio_gauge(chain: &str, host: &str, ratio: f64) {
+    let metrics_lock = METRICS.lock().unwrap();
+    if let Some(metrics) = &*metrics_lock {
+        metrics.set_node_success_ratio_gauge(chain, host, ratio);
+    }
+}
+
+pub fn set_node_health_score_gauge(chain: &str, host: &str, score: f64) {
+    let metrics_lock = METRICS.lock().unwrap();
+    if let Some(metrics) = &*metrics_lock {
+        metrics.set_node_health_score_gauge(chain, host, score);
+    }
+}
+
+pub fn inc_request_bytes(chain: &str, host: &str, bytes: usize) {
+    let metrics_lock = METRICS.lock().unwrap();
+    if let Some(metrics) = &*metrics_lock {
+        metrics.inc_request_bytes(chain, host, bytes);
+    }
+}
+
+pub fn inc_response_bytes(chain: &str, host: &str, bytes: usize) {
+    let metrics_lock = METRICS.lock().unwrap();
+    if let Some(metrics) = &*metrics_lock {
+        metrics.inc_response_bytes(chain, host, bytes);
+    }
+}
+
+pub fn set_cert_expiry_days_gauge(chain: &str, host: &str, days: i64) {
+    let metrics_lock = METRICS.lock().unwrap();
+    if let Some(metrics) = &*metrics_lock {
+        metrics.set_cert_expiry_days_gauge(chain, host, days);
+    }
+}
+
+pub fn observe_health_check_latency(chain: &str, host: &str, latency: Duration) {
+    let metrics_lock = METRICS.lock().unwrap();
+    if let Some(metrics) = &*metrics_lock {
+        metrics.observe_health_check_latency(chain, host, latency);
+    }
+}
+
+pub fn set_node_tags(chain: &str, host: &str, tags: &BTreeMap<String, String>) {
+    let metrics_lock = METRICS.lock().unwrap();
+    if let Some(metrics) = &*metrics_lock {
+        metrics.set_node_tags(chain, host, tags);
+    }
+}
+
+pub fn remove_node_series(chain: &str, host: &str) {
+    let metrics_lock = METRICS.lock().unwrap();
+    if let Some(metrics) = &*metrics_lock {
+        metrics.remove_node_series(chain, host);
+    }
+}
+
+pub fn set_removed_hosts(chain: &str, hosts: HashSet<String>) {
+    let mut metrics_lock = METRICS.lock().unwrap();
+    if let Some(metrics) = &mut *metrics_lock {
+        metrics.set_removed_hosts(chain, hosts);
+    }
+}
+
+pub fn collect_metrics() -> Vec<MetricFamily> {
+    let metrics_lock = METRICS.lock().unwrap();
+    match &*metrics_lock {
+        Some(metrics) => metrics.collect(),
+        None => Vec::new(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -110,7 +457,7 @@ mod tests {
     #[test]
     fn test_metrics() {
         // Initialize metrics
-        init_metrics("wallet").unwrap();
+        init_metrics("wallet", &HashMap::new(), &[]).unwrap();
 
         // Set a test value
         set_node_height_gauge("test_chain", "test_host", 42);
@@ -135,4 +482,249 @@ mod tests {
         let proxy_result_counter = proxy_result_counter.get_metric();
         assert_eq!(proxy_result_counter.len(), 3);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_grpc_result_counter() {
+        // use an unregistered instance, the global one is owned by test_metrics
+        let metrics = Metrics::new("grpc_test");
+        metrics.inc_grpc_result_counter("test_chain", "test_host", "0");
+        metrics.inc_grpc_result_counter("test_chain", "test_host", "14");
+        metrics.inc_grpc_result_counter("test_chain", "test_host", "14");
+
+        let unavailable = metrics
+            .grpc_result_counter
+            .with_label_values(&["test_chain", "test_host", "14"])
+            .get();
+        assert_eq!(unavailable, 2.0);
+    }
+
+    #[test]
+    fn test_sla_violation_counter() {
+        let metrics = Metrics::new("sla_test");
+        let sla = Duration::from_millis(500);
+
+        // a compliant request is not counted
+        metrics.observe_sla("test_chain", "test_host", Duration::from_millis(120), sla);
+        let violations = metrics
+            .sla_violation_counter
+            .with_label_values(&["test_chain", "test_host"])
+            .get();
+        assert_eq!(violations, 0.0);
+
+        // a violating request is
+        metrics.observe_sla("test_chain", "test_host", Duration::from_millis(750), sla);
+        let violations = metrics
+            .sla_violation_counter
+            .with_label_values(&["test_chain", "test_host"])
+            .get();
+        assert_eq!(violations, 1.0);
+    }
+
+    #[test]
+    fn test_node_success_ratio_gauge() {
+        use crate::app::success_ratio::SuccessRatioWindow;
+
+        let metrics = Metrics::new("success_ratio_test");
+        let window = SuccessRatioWindow::new(10);
+
+        // 7 successes and 3 failures
+        let outcomes = [true, true, false, true, true, false, true, true, false, true];
+        for success in outcomes {
+            let ratio = window.record("test_host", success);
+            metrics.set_node_success_ratio_gauge("test_chain", "test_host", ratio);
+        }
+        let ratio = metrics
+            .node_success_ratio_gauge
+            .with_label_values(&["test_chain", "test_host"])
+            .get();
+        assert!((ratio - 0.7).abs() < 1e-9);
+
+        // the window slides, 5 more failures push out 5 of the older outcomes
+        for _ in 0..5 {
+            let ratio = window.record("test_host", false);
+            metrics.set_node_success_ratio_gauge("test_chain", "test_host", ratio);
+        }
+        let ratio = metrics
+            .node_success_ratio_gauge
+            .with_label_values(&["test_chain", "test_host"])
+            .get();
+        assert!((ratio - 0.3).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_node_health_score_gauge() {
+        let metrics = Metrics::new("health_score_test");
+        metrics.set_node_health_score_gauge("test_chain", "test_host", 0.75);
+        let score = metrics
+            .node_health_score_gauge
+            .with_label_values(&["test_chain", "test_host"])
+            .get();
+        assert!((score - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_body_bytes_counters() {
+        let metrics = Metrics::new("body_bytes_test");
+        metrics.inc_request_bytes("test_chain", "test_host", 64);
+        metrics.inc_request_bytes("test_chain", "test_host", 36);
+        metrics.inc_response_bytes("test_chain", "test_host", 4096);
+        // an empty body still touches the series
+        metrics.inc_response_bytes("test_chain", "other_host", 0);
+
+        let request_bytes = metrics
+            .request_bytes_counter
+            .with_label_values(&["test_chain", "test_host"])
+            .get();
+        assert_eq!(request_bytes, 100.0);
+
+        let response_bytes = metrics
+            .response_bytes_counter
+            .with_label_values(&["test_chain", "test_host"])
+            .get();
+        assert_eq!(response_bytes, 4096.0);
+
+        let response_bytes = metrics
+            .response_bytes_counter
+            .with_label_values(&["test_chain", "other_host"])
+            .get();
+        assert_eq!(response_bytes, 0.0);
+    }
+
+    #[test]
+    fn test_const_labels() {
+        use prometheus::core::Metric;
+
+        let const_labels = HashMap::from([("env".to_string(), "staging".to_string())]);
+        let metrics = Metrics::with_labels("const_labels_test", &const_labels, &[]);
+        metrics.set_node_height_gauge("test_chain", "test_host", 42);
+        metrics.inc_proxy_result_counter("test_chain", "test_host", "200", "POST");
+
+        let metric = metrics.node_height_gauge.with_label_values(&["test_chain", "test_host"]).metric();
+        let labels: Vec<(&str, &str)> = metric
+            .get_label()
+            .iter()
+            .map(|label| (label.get_name(), label.get_value()))
+            .collect();
+        assert!(labels.contains(&("env", "staging")));
+        assert!(labels.contains(&("chain", "test_chain")));
+
+        let metric = metrics
+            .proxy_result_counter
+            .with_label_values(&["test_chain", "test_host", "200", "POST"])
+            .metric();
+        assert!(metric.get_label().iter().any(|label| label.get_name() == "env" && label.get_value() == "staging"));
+    }
+
+    #[test]
+    fn test_health_check_latency_histogram() {
+        let metrics = Metrics::new("health_check_latency_test");
+        metrics.observe_health_check_latency("test_chain", "test_host", Duration::from_millis(120));
+
+        let latency = metrics
+            .health_check_latency_histogram
+            .with_label_values(&["test_chain", "test_host"]);
+        assert_eq!(latency.get_sample_count(), 1);
+        assert!((latency.get_sample_sum() - 0.12).abs() < 1e-9);
+
+        // the sample is exported with the other metrics
+        let family = metrics
+            .collect()
+            .into_iter()
+            .find(|family| family.get_name() == "health_check_latency_test_health_check_latency_seconds")
+            .unwrap();
+        let buckets = family.get_metric()[0].get_histogram().get_bucket();
+        let bucket = buckets.iter().find(|bucket| bucket.get_upper_bound() == 0.25).unwrap();
+        assert_eq!(bucket.get_cumulative_count(), 1);
+    }
+
+    #[test]
+    fn test_node_tags_gauge() {
+        let tag_labels = ["provider".to_string(), "region".to_string()];
+        let metrics = Metrics::with_labels("node_tags_test", &HashMap::new(), &tag_labels);
+
+        // only the configured tag keys are labels, a missing one is empty
+        let tags = BTreeMap::from([
+            ("provider".to_string(), "infura".to_string()),
+            ("region".to_string(), "us-east".to_string()),
+            ("contract".to_string(), "c-1234".to_string()),
+        ]);
+        metrics.set_node_tags("test_chain", "node1.example.com", &tags);
+        let tags = BTreeMap::from([("provider".to_string(), "alchemy".to_string())]);
+        metrics.set_node_tags("test_chain", "node2.example.com", &tags);
+
+        let family = metrics
+            .collect()
+            .into_iter()
+            .find(|family| family.get_name() == "node_tags_test_node_tags")
+            .unwrap();
+        let series: Vec<Vec<(&str, &str)>> = family
+            .get_metric()
+            .iter()
+            .map(|metric| metric.get_label().iter().map(|l| (l.get_name(), l.get_value())).collect())
+            .collect();
+        assert_eq!(series.len(), 2);
+        assert!(series.contains(&vec![
+            ("chain", "test_chain"),
+            ("host", "node1.example.com"),
+            ("provider", "infura"),
+            ("region", "us-east"),
+        ]));
+        assert!(series.contains(&vec![
+            ("chain", "test_chain"),
+            ("host", "node2.example.com"),
+            ("provider", "alchemy"),
+            ("region", ""),
+        ]));
+        assert!(family.get_metric().iter().all(|metric| metric.get_gauge().get_value() == 1.0));
+
+        // without tag labels the tags are not exported
+        let metrics = Metrics::new("node_tags_unset_test");
+        metrics.set_node_tags("test_chain", "node1.example.com", &tags);
+        let family = metrics
+            .collect()
+            .into_iter()
+            .find(|family| family.get_name() == "node_tags_unset_test_node_tags")
+            .unwrap();
+        assert!(family.get_metric().is_empty());
+    }
+
+    #[test]
+    fn test_removed_node_series() {
+        let mut metrics = Metrics::new("removed_node_test");
+        let heights = |metrics: &Metrics| -> Vec<String> {
+            let family = metrics
+                .collect()
+                .into_iter()
+                .find(|family| family.get_name() == "removed_node_test_node_height_gauge")
+                .unwrap();
+            family
+                .get_metric()
+                .iter()
+                .flat_map(|metric| metric.get_label().iter().filter(|l| l.get_name() == "host"))
+                .map(|label| label.get_value().to_string())
+                .collect()
+        };
+
+        metrics.set_node_height_gauge("ethereum", "https://node1.example.com", 100);
+        metrics.set_node_height_gauge("ethereum", "https://node2.example.com", 100);
+        metrics.set_node_health_gauge("ethereum", "https://node1.example.com", true);
+        assert_eq!(heights(&metrics).len(), 2);
+
+        // removing a node deletes its series, which are not set again while it is still probed
+        let removed = HashSet::from(["https://node1.example.com".to_string()]);
+        metrics.set_removed_hosts("ethereum", removed);
+        assert_eq!(heights(&metrics), vec!["https://node2.example.com".to_string()]);
+        assert!(metrics.node_health_gauge.remove_label_values(&["ethereum", "https://node1.example.com"]).is_err());
+        metrics.set_node_height_gauge("ethereum", "https://node1.example.com", 101);
+        assert_eq!(heights(&metrics).len(), 1);
+
+        // the same host of another chain is kept
+        metrics.set_node_height_gauge("bsc", "https://node1.example.com", 200);
+        assert_eq!(heights(&metrics).len(), 2);
+
+        // a node added back is exported again
+        metrics.set_removed_hosts("ethereum", HashSet::new());
+        metrics.set_node_height_gauge("ethereum", "https://node1.example.com", 102);
+        assert_eq!(heights(&metrics).len(), 3);
+    }
+}
@@ -0,0 +1,243 @@
+/// Minimal CBOR decoder and encoder
+///
+/// Decodes the definite length items used by health check responses, e.g. the internet computer
+/// `/api/v2/status` endpoint, indefinite lengths and big numbers are rejected. Requests are encoded
+/// with the shortest arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum CborValue {
+    Unsigned(u64),
+    Negative(u64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<CborValue>),
+    Map(Vec<(CborValue, CborValue)>),
+    Tag(u64, Box<CborValue>),
+    // simple values and floats, false, true, null...
+    Simple(u64),
+}
+
+impl CborValue {
+    /// The value of a text key in a map, tags are looked through
+    pub(crate) fn get(&self, key: &str) -> Option<&CborValue> {
+        match self {
+            CborValue::Map(entries) => entries
+                .iter()
+                .find(|(k, _)| matches!(k, CborValue::Text(text) if text == key))
+                .map(|(_, v)| v),
+            CborValue::Tag(_, value) => value.get(key),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_text(&self) -> Option<&str> {
+        match self {
+            CborValue::Text(text) => Some(text.as_str()),
+            _ => None,
+        }
+    }
+}
+
+// nesting limit, so a malicious body can't exhaust the stack
+const MAX_DEPTH: usize = 32;
+
+/// Decode a single CBOR item filling the whole input, None if it is malformed or unsupported
+pub(crate) fn decode(input: &[u8]) -> Option<CborValue> {
+    let mut pos = 0;
+    let value = decode_item(input, &mut pos, 0)?;
+    if pos == input.len() {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+fn decode_item(input: &[u8], pos: &mut usize, depth: usize) -> Option<CborValue> {
+    if depth > MAX_DEPTH {
+        return None;
+    }
+
+    let initial = *input.get(*pos)?;
+    *pos += 1;
+    let major = initial >> 5;
+    let argument = read_argument(input, pos, initial & 0x1f)?;
+
+    match major {
+        0 => Some(CborValue::Unsigned(argument)),
+        1 => Some(CborValue::Negative(argument)),
+        2 => Some(CborValue::Bytes(read_bytes(input, pos, argument)?.to_vec())),
+        3 => {
+            let text = std::str::from_utf8(read_bytes(input, pos, argument)?).ok()?;
+            Some(CborValue::Text(text.to_string()))
+        }
+        4 => {
+            let mut items = Vec::new();
+            for _ in 0..argument {
+                items.push(decode_item(input, pos, depth + 1)?);
+            }
+            Some(CborValue::Array(items))
+        }
+        5 => {
+            let mut entries = Vec::new();
+            for _ in 0..argument {
+                let key = decode_item(input, pos, depth + 1)?;
+                let value = decode_item(input, pos, depth + 1)?;
+                entries.push((key, value));
+            }
+            Some(CborValue::Map(entries))
+        }
+        6 => Some(CborValue::Tag(argument, Box::new(decode_item(input, pos, depth + 1)?))),
+        _ => Some(CborValue::Simple(argument)),
+    }
+}
+
+/// Encode a CBOR item, the inverse of `decode`
+pub(crate) fn encode(value: &CborValue) -> Vec<u8> {
+    let mut output = Vec::new();
+    encode_item(value, &mut output);
+    output
+}
+
+fn encode_item(value: &CborValue, output: &mut Vec<u8>) {
+    match value {
+        CborValue::Unsigned(value) => write_head(0, *value, output),
+        CborValue::Negative(value) => write_head(1, *value, output),
+        CborValue::Bytes(bytes) => {
+            write_head(2, bytes.len() as u64, output);
+            output.extend_from_slice(bytes);
+        }
+        CborValue::Text(text) => {
+            write_head(3, text.len() as u64, output);
+            output.extend_from_slice(text.as_bytes());
+        }
+        CborValue::Array(items) => {
+            write_head(4, items.len() as u64, output);
+            items.iter().for_each(|item| encode_item(item, output));
+        }
+        CborValue::Map(entries) => {
+            write_head(5, entries.len() as u64, output);
+            for (key, value) in entries.iter() {
+                encode_item(key, output);
+                encode_item(value, output);
+            }
+        }
+        CborValue::Tag(tag, value) => {
+            write_head(6, *tag, output);
+            encode_item(value, output);
+        }
+        CborValue::Simple(value) => write_head(7, *value, output),
+    }
+}
+
+// the initial byte of an item and its argument in the fewest bytes
+fn write_head(major: u8, argument: u64, output: &mut Vec<u8>) {
+    let major = major << 5;
+    match argument {
+        0..=23 => output.push(major | argument as u8),
+        24..=0xff => output.extend_from_slice(&[major | 24, argument as u8]),
+        0x100..=0xffff => {
+            output.push(major | 25);
+            output.extend_from_slice(&(argument as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            output.push(major | 26);
+            output.extend_from_slice(&(argument as u32).to_be_bytes());
+        }
+        _ => {
+            output.push(major | 27);
+            output.extend_from_slice(&argument.to_be_bytes());
+        }
+    }
+}
+
+// the argument of an item, its value, length or tag number
+fn read_argument(input: &[u8], pos: &mut usize, additional: u8) -> Option<u64> {
+    let size = match additional {
+        0..=23 => return Some(additional as u64),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        // reserved, or an indefinite length
+        _ => return None,
+    };
+
+    let bytes = read_bytes(input, pos, size)?;
+    Some(bytes.iter().fold(0u64, |value, byte| (value << 8) | *byte as u64))
+}
+
+fn read_bytes<'a>(input: &'a [u8], pos: &mut usize, len: u64) -> Option<&'a [u8]> {
+    let end = pos.checked_add(usize::try_from(len).ok()?)?;
+    let bytes = input.get(*pos..end)?;
+    *pos = end;
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        // {"a": 1, "b": [2, -3], "c": h'0102', "d": true} under the self-described cbor tag
+        let input = [
+            0xd9, 0xd9, 0xf7, 0xa4, 0x61, b'a', 0x01, 0x61, b'b', 0x82, 0x02, 0x22, 0x61, b'c', 0x42, 0x01, 0x02,
+            0x61, b'd', 0xf5,
+        ];
+        let value = decode(&input).unwrap();
+        assert_eq!(value.get("a"), Some(&CborValue::Unsigned(1)));
+        assert_eq!(
+            value.get("b"),
+            Some(&CborValue::Array(vec![CborValue::Unsigned(2), CborValue::Negative(2)]))
+        );
+        assert_eq!(value.get("c"), Some(&CborValue::Bytes(vec![1, 2])));
+        assert_eq!(value.get("d"), Some(&CborValue::Simple(21)));
+        assert_eq!(value.get("e"), None);
+
+        // 1000 with a 2 bytes argument
+        assert_eq!(decode(&[0x19, 0x03, 0xe8]), Some(CborValue::Unsigned(1000)));
+    }
+
+    #[test]
+    fn test_encode() {
+        assert_eq!(encode(&CborValue::Unsigned(23)), [0x17]);
+        assert_eq!(encode(&CborValue::Unsigned(1000)), [0x19, 0x03, 0xe8]);
+        assert_eq!(encode(&CborValue::Unsigned(u64::MAX)), [0x1b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+
+        let value = CborValue::Tag(55799, Box::new(CborValue::Map(vec![
+            (CborValue::Text("a".to_string()), CborValue::Unsigned(1)),
+            (CborValue::Text("b".to_string()), CborValue::Array(vec![CborValue::Unsigned(2), CborValue::Negative(2)])),
+            (CborValue::Text("c".to_string()), CborValue::Bytes(vec![1, 2])),
+            (CborValue::Text("d".to_string()), CborValue::Simple(21)),
+        ])));
+        let encoded = encode(&value);
+        assert_eq!(
+            encoded,
+            [
+                0xd9, 0xd9, 0xf7, 0xa4, 0x61, b'a', 0x01, 0x61, b'b', 0x82, 0x02, 0x22, 0x61, b'c', 0x42, 0x01, 0x02,
+                0x61, b'd', 0xf5,
+            ]
+        );
+        assert_eq!(decode(&encoded), Some(value));
+    }
+
+    #[test]
+    fn test_decode_malformed() {
+        // truncated map
+        assert!(decode(&[0xa1, 0x61, b'a']).is_none());
+        // trailing bytes
+        assert!(decode(&[0x01, 0x02]).is_none());
+        // indefinite length array
+        assert!(decode(&[0x9f, 0x01, 0xff]).is_none());
+        // a length past the input
+        assert!(decode(&[0x5b, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).is_none());
+        // json is not cbor
+        assert!(decode(br#"{"a":1}"#).is_none());
+        assert!(decode(&[]).is_none());
+        // nesting past the limit
+        let mut nested = vec![0x81; MAX_DEPTH];
+        nested.push(0x01);
+        assert!(decode(&nested).is_some());
+        nested.insert(0, 0x81);
+        assert!(decode(&nested).is_none());
+    }
+}
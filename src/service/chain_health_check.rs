@@ -1,22 +1,40 @@
-use crate::config::ChainState;
+use crate::config::{BlockTag, ChainState, ProbeResult, ResultValueType};
 use async_trait::async_trait;
 use pingora_load_balancing::health_check::HealthCheck;
 use pingora_load_balancing::Backend;
-use pingora::{Custom, Error, Result};
+use pingora::Result;
+use crate::error::ProxyError;
 use reqwest::Client;
 use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
 use serde::{Deserialize, Serialize};
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::collections::HashMap;
-use crate::metrics::set_node_height_gauge;
+use std::ops::RangeInclusive;
+use rand::Rng;
+use crate::metrics::{observe_health_check_latency, set_cert_expiry_days_gauge, set_node_height_gauge};
+use crate::service::number::{height_string, json_height, parse_height};
+use crate::service::{cbor, protobuf};
+use crate::service::cbor::CborValue;
+use crate::service::interval::HealthCheckInterval;
+use crate::service::nodes::ChainNodes;
+use openssl::asn1::Asn1Time;
+use openssl::x509::X509;
 
 type Validator = Arc<dyn Fn(&[u8]) -> Result<u64> + Send + Sync>;
 
+/// Builds the request body of a follow-up request from the response body of the previous request
+type FollowUpRequest = Arc<dyn Fn(&[u8]) -> Result<Vec<u8>> + Send + Sync>;
+
+/// Builds a fresh request body for every probe, e.g. a request expiring shortly after it is built
+type RequestBodyBuilder = Arc<dyn Fn() -> Vec<u8> + Send + Sync>;
+
 #[derive(Clone)]
 pub struct ChainChecker {
     pub validator: Validator,
     pub request_body: Vec<u8>,
+    /// Optional second request built from the response of the first one, the validator reads its response
+    pub follow_up_request: Option<FollowUpRequest>,
 }
 
 lazy_static! {
@@ -49,6 +67,7 @@ pub fn init_chain_checker() {
                "#
             .as_bytes()
             .to_vec(),
+        follow_up_request: None,
     };
     register_chain_checker("ethereum", ethereum_checker);
 
@@ -65,17 +84,247 @@ pub fn init_chain_checker() {
                "#
             .as_bytes()
             .to_vec(),
+        follow_up_request: None,
     };
     register_chain_checker("ripple", ripple_checker);
 
+    // register the ripple server_info checker, also checks the server state
+    let ripple_server_info_checker = ChainChecker {
+        validator: Arc::new(ripple_server_info_validator),
+        request_body: r#"{"method":"server_info","params":[{}]}"#.as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("ripple_server_info", ripple_server_info_checker);
+
     // register the cosmos chain checker
     let cosmos_checker = ChainChecker {
         validator: Arc::new(cosmos_validator),
         request_body: "".as_bytes().to_vec(),
+        follow_up_request: None,
     };
     register_chain_checker("cosmos", cosmos_checker);
+
+    // register the celestia chain checker, the consensus node answers like cosmos
+    let celestia_checker = ChainChecker {
+        validator: Arc::new(cosmos_validator),
+        request_body: "".as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("celestia", celestia_checker);
+
+    // register the osmosis checker, a GET /status of the tendermint rpc
+    let osmosis_checker = ChainChecker {
+        validator: Arc::new(cosmos_status_validator),
+        request_body: "".as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("osmosis", osmosis_checker);
+
+    // register the celestia da node checker, a plain GET /header/network_head
+    let celestia_da_checker = ChainChecker {
+        validator: Arc::new(celestia_da_validator),
+        request_body: "".as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("celestia_da", celestia_da_checker);
+
+    // register the starknet chain checker, json-rpc is the default
+    let starknet_checker = ChainChecker {
+        validator: Arc::new(starknet_validator),
+        request_body: r#"
+                {
+                    "jsonrpc":"2.0",
+                    "method":"starknet_blockNumber",
+                    "id":1
+               }
+               "#
+            .as_bytes()
+            .to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("starknet", starknet_checker);
+
+    // register the starknet feeder gateway checker
+    // the feeder gateway is a plain GET api, e.g. /feeder_gateway/get_block?blockNumber=latest
+    let starknet_feeder_checker = ChainChecker {
+        validator: Arc::new(starknet_feeder_validator),
+        request_body: "".as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("starknet_feeder", starknet_feeder_checker);
+
+    // register the arweave chain checker, a plain GET /info
+    let arweave_checker = ChainChecker {
+        validator: Arc::new(arweave_validator),
+        request_body: "".as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("arweave", arweave_checker);
+
+    // register the bitcoin rest checker, a plain GET /rest/chaininfo.json without rpc auth
+    let bitcoin_rest_checker = ChainChecker {
+        validator: Arc::new(bitcoin_rest_validator),
+        request_body: "".as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("bitcoin_rest", bitcoin_rest_checker);
+
+    // register the kaspa checker, a plain GET /info/blockdag
+    let kaspa_checker = ChainChecker {
+        validator: Arc::new(kaspa_validator),
+        request_body: "".as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("kaspa", kaspa_checker);
+
+    // register the multiversx checker, a plain GET /network/status/4294967295 of the metachain
+    let multiversx_checker = ChainChecker {
+        validator: Arc::new(multiversx_validator),
+        request_body: "".as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("multiversx", multiversx_checker);
+
+    // register the ethereum beacon checker, a plain GET /eth/v1/beacon/headers/head
+    let beacon_checker = ChainChecker {
+        validator: Arc::new(beacon_validator),
+        request_body: "".as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("ethereum_beacon", beacon_checker);
+
+    // register the nano checker
+    let nano_checker = ChainChecker {
+        validator: Arc::new(nano_validator),
+        request_body: r#"{"action":"block_count"}"#.as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("nano", nano_checker);
+
+    // register the neo checker
+    let neo_checker = ChainChecker {
+        validator: Arc::new(neo_validator),
+        request_body: r#"{"jsonrpc":"2.0","method":"getblockcount","params":[],"id":1}"#.as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("neo", neo_checker);
+
+    // register the aptos checker, a plain GET /v1
+    let aptos_checker = ChainChecker {
+        validator: Arc::new(aptos_validator),
+        request_body: "".as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("aptos", aptos_checker);
+
+    // register the tron http checker, a POST /wallet/getnowblock of the latest block
+    let tron_http_checker = ChainChecker {
+        validator: Arc::new(tron_validator),
+        request_body: "".as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("tron_http", tron_http_checker);
+
+    // register the starkex checker, a plain GET /feeder_gateway/get_last_batch_id answering a bare number,
+    // gateways answering another shape are read with HealthCheckResultPath
+    let starkex_checker = ChainChecker {
+        validator: json_pointer_validator("", ResultValueType::Number),
+        request_body: "".as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("starkex", starkex_checker);
+
+    // register the internet computer status checker, a plain GET /api/v2/status answering cbor
+    let icp_status_checker = ChainChecker {
+        validator: Arc::new(icp_status_validator),
+        request_body: "".as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("icp_status", icp_status_checker);
+
+    // register the fuel checker, a graphql query of the latest block height
+    let fuel_checker = ChainChecker {
+        validator: Arc::new(fuel_validator),
+        request_body: r#"{"query":"query { chain { latestBlock { header { height } } } }"}"#.as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("fuel", fuel_checker);
+
+    // register the sui checker, the latest checkpoint is the height
+    let sui_checker = ChainChecker {
+        validator: Arc::new(sui_validator),
+        request_body: r#"{"jsonrpc":"2.0","method":"sui_getLatestCheckpointSequenceNumber","params":[],"id":1}"#
+            .as_bytes()
+            .to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("sui", sui_checker);
+
+    // register the substrate finalized head checker, the finalized head hash is resolved to its block number
+    let substrate_finalized_checker = ChainChecker {
+        validator: Arc::new(substrate_header_validator),
+        request_body: SUBSTRATE_FINALIZED_HEAD_REQUEST.to_vec(),
+        follow_up_request: Some(Arc::new(substrate_header_request)),
+    };
+    register_chain_checker("substrate_finalized", substrate_finalized_checker);
+
+    // register the substrate checker, the height is the current block of the sync state
+    let substrate_checker = ChainChecker {
+        validator: Arc::new(substrate_sync_state_validator),
+        request_body: r#"{"jsonrpc":"2.0","method":"system_syncState","params":[],"id":1}"#.as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("substrate", substrate_checker);
+
+    // register the cosmos grpc checker, an empty GetLatestBlockRequest to the tendermint service
+    let cosmos_grpc_checker = ChainChecker {
+        validator: Arc::new(cosmos_grpc_validator),
+        request_body: protobuf::grpc_frame(&[]),
+        follow_up_request: None,
+    };
+    register_chain_checker("cosmos_grpc", cosmos_grpc_checker);
+
+    // register the zkevm checker, the height is eth_blockNumber, the verified batch is checked with VerificationGap
+    let zkevm_checker = ChainChecker {
+        validator: Arc::new(eth_validator),
+        request_body: r#"{"jsonrpc":"2.0","method":"eth_blockNumber","params":[],"id":1}"#.as_bytes().to_vec(),
+        follow_up_request: None,
+    };
+    register_chain_checker("zkevm", zkevm_checker);
+}
+
+// chain types probed with grpc, over http/2
+const GRPC_CHAIN_TYPES: [&str; 1] = ["cosmos_grpc"];
+
+/// Whether the health check of the chain type is a grpc call
+pub fn is_grpc_chain_type(chain_type: &str) -> bool {
+    GRPC_CHAIN_TYPES.contains(&chain_type)
 }
 
+// request body of the substrate finalized head hash
+const SUBSTRATE_FINALIZED_HEAD_REQUEST: &[u8] =
+    br#"{"jsonrpc":"2.0","method":"chain_getFinalizedHead","params":[],"id":1}"#;
+
+// request body of the latest sui checkpoint, with its timestamp
+pub(crate) const SUI_LATEST_CHECKPOINT_REQUEST: &[u8] =
+    br#"{"jsonrpc":"2.0","method":"sui_getCheckpoints","params":[null,1,true],"id":1}"#;
+
+// request body of the eth net_peerCount probe
+const NET_PEER_COUNT_REQUEST: &[u8] = br#"{"jsonrpc":"2.0","method":"net_peerCount","params":[],"id":1}"#;
+
+// request body of the eth eth_syncing probe
+const ETH_SYNCING_REQUEST: &[u8] = br#"{"jsonrpc":"2.0","method":"eth_syncing","params":[],"id":1}"#;
+
+// request body of the eth eth_gasPrice probe
+const ETH_GAS_PRICE_REQUEST: &[u8] = br#"{"jsonrpc":"2.0","method":"eth_gasPrice","params":[],"id":1}"#;
+
+// request body of the substrate system_health probe
+const SYSTEM_HEALTH_REQUEST: &[u8] = br#"{"jsonrpc":"2.0","method":"system_health","params":[],"id":1}"#;
+
+// request body of the substrate state_getRuntimeVersion probe
+const STATE_GET_RUNTIME_VERSION_REQUEST: &[u8] =
+    br#"{"jsonrpc":"2.0","method":"state_getRuntimeVersion","params":[],"id":1}"#;
+
 /// Define various response validators for different chain, like ethereum, bitcoin, etc.
 /// Eth response and validator
 #[derive(Debug, Serialize, Deserialize)]
@@ -92,7 +341,7 @@ pub(crate) fn eth_validator(body: &[u8]) -> Result<u64> {
     if parsed.is_err() {
         // log the body
         log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
-        return Error::e_explain(Custom("invalid json"), "during http healthcheck");
+        return ProxyError::InvalidJson.explain("during http healthcheck");
     }
 
     let parsed: EthJsonResponse = parsed.unwrap();
@@ -100,20 +349,76 @@ pub(crate) fn eth_validator(body: &[u8]) -> Result<u64> {
     if parsed.jsonrpc != "2.0" {
         // log the body
         log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
-        Error::e_explain(Custom("invalid jsonrpc"), "during http healthcheck")
+        ProxyError::InvalidJsonrpc.explain("during http healthcheck")
     } else {
         // from hex string to u64
         let block_number = u64::from_str_radix(&parsed.result[2..], 16);
         if block_number.is_err() {
             // log the body
             log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
-            return Error::e_explain(Custom("invalid block number"), "during http healthcheck");
+            return ProxyError::InvalidBlockNumber.explain("during http healthcheck");
         }
 
         Ok(block_number.unwrap())
     }
 }
 
+/// Build the eth_getBlockByNumber request of the given block tag, without the transactions
+pub(crate) fn eth_block_by_number_request(block_tag: BlockTag) -> Vec<u8> {
+    format!(
+        r#"{{"jsonrpc":"2.0","method":"eth_getBlockByNumber","params":["{}",false],"id":1}}"#,
+        block_tag.as_str()
+    )
+    .into_bytes()
+}
+
+/// Eth block response and validator
+#[derive(Debug, Serialize, Deserialize)]
+struct EthBlockJsonResponse {
+    /// The key to check in the JSON response
+    jsonrpc: String,
+    id: u64,
+    result: EthBlock,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct EthBlock {
+    /// The key to check in the JSON response
+    number: String,
+}
+
+pub(crate) fn eth_block_by_number_validator(body: &[u8]) -> Result<u64> {
+    // try to parse the JSON response, the result is null if the tag is not supported yet
+    let parsed: Result<EthBlockJsonResponse, serde_json::Error> = serde_json::from_slice(body);
+    if parsed.is_err() {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidJson.explain("during http healthcheck");
+    }
+
+    let parsed = parsed.unwrap();
+    if parsed.jsonrpc != "2.0" {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidJsonrpc.explain("during http healthcheck");
+    }
+
+    // from hex string to u64
+    let block_number = parsed
+        .result
+        .number
+        .strip_prefix("0x")
+        .and_then(|hex| u64::from_str_radix(hex, 16).ok());
+    match block_number {
+        Some(block_number) => Ok(block_number),
+        None => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            ProxyError::InvalidBlockNumber.explain("during http healthcheck")
+        }
+    }
+}
+
 /// ripple response and validator
 #[derive(Debug, Serialize, Deserialize)]
 struct RippleJsonResponse {
@@ -135,7 +440,7 @@ pub(crate) fn ripple_validator(body: &[u8]) -> Result<u64> {
     if parsed.is_err() {
         // log the body
         log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
-        return Error::e_explain(Custom("invalid json"), "during http healthcheck");
+        return ProxyError::InvalidJson.explain("during http healthcheck");
     }
 
     let parsed = parsed.unwrap();
@@ -144,12 +449,64 @@ pub(crate) fn ripple_validator(body: &[u8]) -> Result<u64> {
     if parsed.result.status != "success" {
         // log the body
         log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
-        Error::e_explain(Custom("invalid status"), "during http healthcheck")
+        ProxyError::InvalidStatus.explain("during http healthcheck")
     } else {
         Ok(parsed.result.ledger_index)
     }
 }
 
+/// ripple server_info response and validator
+#[derive(Debug, Deserialize)]
+struct RippleServerInfoResponse {
+    result: RippleServerInfoResult,
+}
+
+#[derive(Debug, Deserialize)]
+struct RippleServerInfoResult {
+    info: RippleServerInfo,
+    status: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RippleServerInfo {
+    server_state: String,
+    #[serde(default)]
+    amendment_blocked: bool,
+    validated_ledger: Option<RippleValidatedLedger>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RippleValidatedLedger {
+    seq: u64,
+}
+
+/// Like ripple_validator, but a node which is amendment blocked or not in the `full` or `proposing`
+/// state is unhealthy, ledger_closed still succeeds on such a node.
+/// The height is the sequence of the last validated ledger.
+pub(crate) fn ripple_server_info_validator(body: &[u8]) -> Result<u64> {
+    let parsed: RippleServerInfoResponse = match serde_json::from_slice(body) {
+        Ok(parsed) => parsed,
+        Err(_) => {
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            return ProxyError::InvalidJson.explain("during http healthcheck");
+        }
+    };
+
+    let result = parsed.result;
+    if result.status != "success" || result.info.amendment_blocked {
+        log::error!("ripple server is not usable: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidStatus.explain("during http healthcheck");
+    }
+
+    match (result.info.server_state.as_str(), result.info.validated_ledger) {
+        ("full" | "proposing", Some(validated_ledger)) => Ok(validated_ledger.seq),
+        (state, _) => {
+            log::warn!("ripple server is not validating, server_state: {}", state);
+            ProxyError::NodeSyncing.explain("during http healthcheck")
+        }
+    }
+}
+
 /// cosmos response and validator
 #[derive(Debug, Serialize, Deserialize)]
 struct CosmosJsonResponse {
@@ -165,7 +522,8 @@ struct CosmosBlock {
 
 #[derive(Debug, Serialize, Deserialize)]
 struct CosmosHeader {
-    /// The key to check in the JSON response
+    /// The key to check in the JSON response, a string or a number
+    #[serde(deserialize_with = "height_string")]
     height: String,
 }
 
@@ -175,266 +533,3203 @@ pub(crate) fn cosmos_validator(body: &[u8]) -> Result<u64> {
     if parsed.is_err() {
         // log the body
         log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
-        return Error::e_explain(Custom("invalid json"), "during http healthcheck");
+        return ProxyError::InvalidJson.explain("during http healthcheck");
     }
 
     let parsed = parsed.unwrap();
 
     // from string to u64
-    let block_number = parsed.block.header.height.parse::<u64>();
-    if block_number.is_err() {
-        // log the body
-        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
-        return Error::e_explain(Custom("invalid block number"), "during http healthcheck");
+    match parse_height(&parsed.block.header.height) {
+        Some(block_number) => Ok(block_number),
+        None => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            ProxyError::InvalidBlockNumber.explain("during http healthcheck")
+        }
     }
+}
 
-    Ok(block_number.unwrap())
+/// cosmos tendermint rpc status response and validator
+#[derive(Debug, Serialize, Deserialize)]
+struct CosmosStatusResponse {
+    result: CosmosStatusResult,
 }
 
-/// Chain health check
-///
-/// This health check checks if it can receive the expected HTTP(s) response from the given backend.
-pub struct ChainHealthCheck {
-    /// Number of successful checks to flip from unhealthy to healthy.
-    pub consecutive_success: usize,
-    /// Number of failed checks to flip from healthy to unhealthy.
-    pub consecutive_failure: usize,
+#[derive(Debug, Serialize, Deserialize)]
+struct CosmosStatusResult {
+    sync_info: CosmosSyncInfo,
+}
 
-    pub chain_state: Arc<Mutex<ChainState>>,
+#[derive(Debug, Serialize, Deserialize)]
+struct CosmosSyncInfo {
+    /// The latest block height, a string or a number
+    #[serde(deserialize_with = "height_string")]
+    latest_block_height: String,
+    /// The rfc3339 time of the latest block
+    #[serde(default)]
+    latest_block_time: Option<String>,
+}
 
-    pub request_method: String,
+pub(crate) fn cosmos_status_validator(body: &[u8]) -> Result<u64> {
+    read_cosmos_status(body, None, SystemTime::now())
+}
 
-    pub request_url: String,
+/// The cosmos status validator also marking a node unhealthy when its latest block is older than the max age
+pub(crate) fn cosmos_status_age_validator(max_age: Duration) -> Validator {
+    Arc::new(move |body: &[u8]| read_cosmos_status(body, Some(max_age), SystemTime::now()))
+}
 
-    pub request_body: Option<Vec<u8>>,
+fn read_cosmos_status(body: &[u8], max_age: Option<Duration>, now: SystemTime) -> Result<u64> {
+    let parsed: Result<CosmosStatusResponse, serde_json::Error> = serde_json::from_slice(body);
+    let sync_info = match parsed {
+        Ok(parsed) => parsed.result.sync_info,
+        Err(_) => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            return ProxyError::InvalidJson.explain("during http healthcheck");
+        }
+    };
 
-    pub request_timeout: Duration,
+    let height = match parse_height(&sync_info.latest_block_height) {
+        Some(height) => height,
+        None => {
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            return ProxyError::InvalidBlockNumber.explain("during http healthcheck");
+        }
+    };
 
-    pub client: Arc<Client>,
+    if let Some(max_age) = max_age {
+        let block_time = match sync_info.latest_block_time.as_deref().and_then(parse_rfc3339) {
+            Some(block_time) => block_time,
+            None => {
+                log::error!("cosmos block {} has no valid time: {:?}", height, sync_info.latest_block_time);
+                return ProxyError::InvalidBlockNumber.explain("during http healthcheck");
+            }
+        };
 
-    /// Optional field to define how to validate the response from the server.
-    ///
-    /// If not set, any response with a `200 OK` is considered a successful check.
-    pub validator: Option<Validator>,
+        // a block time in the future is considered fresh
+        let age = now.duration_since(block_time).unwrap_or_default();
+        if age > max_age {
+            log::error!("cosmos block {} is {:?} old, more than {:?}", height, age, max_age);
+            return ProxyError::StaleLedger.explain("during http healthcheck");
+        }
+    }
 
-    pub host: String,
+    Ok(height)
 }
 
-impl ChainHealthCheck {
-    /// Create a new [ChainHealthCheck] with the following default settings
-    /// * req: a GET/POST to the given path of the given host name
-    /// * request_body: None
-    /// * consecutive_success: 1
-    /// * consecutive_failure: 1
-    /// * validator: `None`, any 200 response is considered successful
-    pub fn new(host: &str, path: &str, method: &str, state: Arc<Mutex<ChainState>>) -> Box<Self> {
-        let request_url = format!("{}{}", host, path);
+/// Parse a rfc3339 timestamp as sent by tendermint, e.g. `2024-05-01T12:00:00.123456789Z`
+fn parse_rfc3339(value: &str) -> Option<SystemTime> {
+    let (date, time) = value.split_once(['T', 't'])?;
+    let date: Vec<i64> = date.split('-').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    let [year, month, day] = date[..] else {
+        return None;
+    };
 
-        Box::new(ChainHealthCheck {
-            consecutive_success: 1,
-            consecutive_failure: 1,
-            chain_state: Arc::clone(&state),
-            request_method: method.to_string(),
-            request_url: request_url.to_string(),
-            request_body: None,
-            request_timeout: Duration::from_secs(60),
-            client: Arc::new(Client::new()),
-            validator: None,
-            host: host.to_string(),
-        })
-    }
+    // the offset is Z or +hh:mm / -hh:mm
+    let (time, offset_secs) = match time.strip_suffix(['Z', 'z']) {
+        Some(time) => (time, 0),
+        None => {
+            let (time, offset) = time.split_at(time.rfind(['+', '-'])?);
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            let offset_secs = hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60;
+            (time, if offset.starts_with('-') { -offset_secs } else { offset_secs })
+        }
+    };
 
-    /// Set the request body to send to the backend
-    pub fn with_request_body(mut self, body: Vec<u8>) -> Box<Self> {
-        self.request_body = Some(body);
-        Box::new(self)
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let time: Vec<i64> = time.split(':').map(|part| part.parse().ok()).collect::<Option<_>>()?;
+    let [hour, minute, second] = time[..] else {
+        return None;
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
     }
 
-    /// Set the response body validator
-    pub fn with_response_body_validator(mut self, validator: Validator) -> Box<Self> {
-        self.validator = Some(validator);
-        Box::new(self)
+    // nanoseconds, digits past the ninth are dropped
+    if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
     }
+    let nanos = format!("{:0<9}", &fraction[..fraction.len().min(9)]).parse::<u32>().ok()?;
+
+    let secs = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset_secs;
+    Some(UNIX_EPOCH + Duration::new(u64::try_from(secs).ok()?, nanos))
 }
 
-#[async_trait]
-impl HealthCheck for ChainHealthCheck {
-    async fn check(&self, _target: &Backend) -> Result<()> {
-        let client = self.client.clone();
-
-        let method_result = reqwest::Method::from_bytes(self.request_method.as_bytes());
-        let method = match method_result {
-            Ok(m) => m,
-            Err(e) => {
-                log::error!(
-                    "invalid request method: {}, error: {}",
-                    self.request_method,
-                    e
-                );
-                return Error::e_explain(Custom("invalid request method"), "reqwest error");
-            }
-        };
+/// Days since the unix epoch of a gregorian calendar date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
 
-        let mut headers = HeaderMap::new();
-        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+/// celestia da node network head response and validator
+#[derive(Debug, Serialize, Deserialize)]
+struct CelestiaHeaderResponse {
+    /// The key to check in the JSON response
+    result: CosmosBlock,
+}
 
-        let request_builder = client
-            .request(method, &self.request_url)
-            .headers(headers)
-            .timeout(self.request_timeout);
+pub(crate) fn celestia_da_validator(body: &[u8]) -> Result<u64> {
+    // try to parse the JSON response
+    let parsed: Result<CelestiaHeaderResponse, serde_json::Error> = serde_json::from_slice(body);
+    if parsed.is_err() {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidJson.explain("during http healthcheck");
+    }
 
-        let request_builder = if let Some(body) = self.request_body.as_ref() {
-            request_builder.body(body.clone())
-        } else {
-            request_builder
-        };
+    let parsed = parsed.unwrap();
 
-        let response = request_builder.send().await;
+    // from string to u64
+    match parse_height(&parsed.result.header.height) {
+        Some(block_number) => Ok(block_number),
+        None => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            ProxyError::InvalidBlockNumber.explain("during http healthcheck")
+        }
+    }
+}
 
-        let response = match response {
-            Ok(r) => r,
-            Err(_e) => {
-                log::error!("failed to send request, error: {}", _e);
-                return Error::e_explain(Custom("failed to send request"), "reqwest error");
-            }
-        };
+/// ethereum beacon header response and validator
+#[derive(Debug, Serialize, Deserialize)]
+struct BeaconHeaderResponse {
+    /// The key to check in the JSON response
+    data: BeaconHeaderData,
+}
 
-        let response_body = response.bytes().await;
-        let response_body = match response_body {
-            Ok(b) => b,
-            Err(_e) => {
-                log::error!("failed to read response body, error: {}", _e);
-                return Error::e_explain(Custom("failed to read response body"), "reqwest error");
-            }
-        };
+#[derive(Debug, Serialize, Deserialize)]
+struct BeaconHeaderData {
+    /// The key to check in the JSON response
+    header: BeaconSignedHeader,
+}
 
-        if let Some(validator) = self.validator.as_ref() {
-            let chain_state_result = validator(&response_body);
-            if chain_state_result.is_err() {
-                log::error!("failed to validate response body");
+#[derive(Debug, Serialize, Deserialize)]
+struct BeaconSignedHeader {
+    /// The key to check in the JSON response
+    message: BeaconHeaderMessage,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BeaconHeaderMessage {
+    /// The key to check in the JSON response, the slot is a decimal string
+    #[serde(deserialize_with = "height_string")]
+    slot: String,
+}
+
+pub(crate) fn beacon_validator(body: &[u8]) -> Result<u64> {
+    // try to parse the JSON response
+    let parsed: Result<BeaconHeaderResponse, serde_json::Error> = serde_json::from_slice(body);
+    if parsed.is_err() {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidJson.explain("during http healthcheck");
+    }
+
+    let parsed = parsed.unwrap();
+
+    // from string to u64
+    match parse_height(&parsed.data.header.message.slot) {
+        Some(slot) => Ok(slot),
+        None => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            ProxyError::InvalidBlockNumber.explain("during http healthcheck")
+        }
+    }
+}
+
+/// starknet json-rpc response and validator
+#[derive(Debug, Serialize, Deserialize)]
+struct StarknetJsonResponse {
+    /// The key to check in the JSON response
+    jsonrpc: String,
+    id: u64,
+    result: u64,
+}
+
+pub(crate) fn starknet_validator(body: &[u8]) -> Result<u64> {
+    // try to parse the JSON response
+    let parsed: Result<StarknetJsonResponse, serde_json::Error> = serde_json::from_slice(body);
+    if parsed.is_err() {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidJson.explain("during http healthcheck");
+    }
+
+    let parsed = parsed.unwrap();
+
+    // check if the JSON response is valid
+    if parsed.jsonrpc != "2.0" {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        ProxyError::InvalidJsonrpc.explain("during http healthcheck")
+    } else {
+        Ok(parsed.result)
+    }
+}
+
+/// starknet feeder gateway response and validator
+#[derive(Debug, Serialize, Deserialize)]
+struct StarknetFeederResponse {
+    /// The key to check in the JSON response
+    block_number: u64,
+}
+
+pub(crate) fn starknet_feeder_validator(body: &[u8]) -> Result<u64> {
+    // try to parse the JSON response
+    let parsed: Result<StarknetFeederResponse, serde_json::Error> = serde_json::from_slice(body);
+    if parsed.is_err() {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidJson.explain("during http healthcheck");
+    }
+
+    Ok(parsed.unwrap().block_number)
+}
+
+/// arweave response and validator
+#[derive(Debug, Serialize, Deserialize)]
+struct ArweaveInfoResponse {
+    /// The key to check in the JSON response
+    height: u64,
+}
+
+pub(crate) fn arweave_validator(body: &[u8]) -> Result<u64> {
+    // try to parse the JSON response
+    let parsed: Result<ArweaveInfoResponse, serde_json::Error> = serde_json::from_slice(body);
+    if parsed.is_err() {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidJson.explain("during http healthcheck");
+    }
+
+    Ok(parsed.unwrap().height)
+}
+
+/// bitcoin rest chaininfo response and validator
+#[derive(Debug, Serialize, Deserialize)]
+struct BitcoinChainInfoResponse {
+    /// The key to check in the JSON response
+    blocks: u64,
+    initialblockdownload: bool,
+}
+
+pub(crate) fn bitcoin_rest_validator(body: &[u8]) -> Result<u64> {
+    // try to parse the JSON response
+    let parsed: Result<BitcoinChainInfoResponse, serde_json::Error> = serde_json::from_slice(body);
+    if parsed.is_err() {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidJson.explain("during http healthcheck");
+    }
+
+    let parsed = parsed.unwrap();
+
+    // a node in initial block download is still catching up, its height is not usable
+    if parsed.initialblockdownload {
+        log::error!("node is in initial block download, blocks: {}", parsed.blocks);
+        ProxyError::NodeSyncing.explain("during http healthcheck")
+    } else {
+        Ok(parsed.blocks)
+    }
+}
+
+/// kaspa blockdag response and validator
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KaspaBlockDagResponse {
+    /// The keys to check in the JSON response, numbers are encoded as strings
+    block_count: Option<String>,
+    virtual_daa_score: Option<String>,
+}
+
+pub(crate) fn kaspa_validator(body: &[u8]) -> Result<u64> {
+    // try to parse the JSON response
+    let parsed: Result<KaspaBlockDagResponse, serde_json::Error> = serde_json::from_slice(body);
+    if parsed.is_err() {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidJson.explain("during http healthcheck");
+    }
+
+    let parsed = parsed.unwrap();
+
+    // the virtual daa score grows steadily with the dag, the block count is a fallback
+    let height = parsed.virtual_daa_score.or(parsed.block_count);
+    match height.and_then(|h| parse_height(&h)) {
+        Some(height) => Ok(height),
+        None => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            ProxyError::InvalidBlockNumber.explain("during http healthcheck")
+        }
+    }
+}
+
+/// multiversx network status response and validator
+#[derive(Debug, Serialize, Deserialize)]
+struct MultiversxStatusResponse {
+    data: Option<MultiversxStatusData>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MultiversxStatusData {
+    status: Option<MultiversxStatus>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MultiversxStatus {
+    /// The nonce of the latest block of the shard
+    erd_nonce: Option<u64>,
+}
+
+pub(crate) fn multiversx_validator(body: &[u8]) -> Result<u64> {
+    // try to parse the JSON response
+    let parsed: Result<MultiversxStatusResponse, serde_json::Error> = serde_json::from_slice(body);
+    if parsed.is_err() {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidJson.explain("during http healthcheck");
+    }
+
+    let parsed = parsed.unwrap();
+
+    // an error response has no data, e.g. {"data":null,"error":"...","code":"internal_issue"}
+    match parsed.data.and_then(|data| data.status).and_then(|status| status.erd_nonce) {
+        Some(nonce) => Ok(nonce),
+        None => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            ProxyError::InvalidBlockNumber.explain("during http healthcheck")
+        }
+    }
+}
+
+// height reported by liveness checks without a block height, the same for every healthy node
+const SYNTHETIC_HEIGHT: u64 = 1;
+
+/// Internet computer /api/v2/status validator, a liveness probe without a block height
+///
+/// The status is a cbor map with the api version and root key, a replica reporting
+/// its health status must be healthy.
+pub(crate) fn icp_status_validator(body: &[u8]) -> Result<u64> {
+    let status = match cbor::decode(body) {
+        Some(status) if status.get("ic_api_version").is_some() => status,
+        _ => {
+            log::error!("failed to decode icp status: {:02x?}", &body[..body.len().min(256)]);
+            return ProxyError::FailedToValidateResponseBody.explain("during http healthcheck");
+        }
+    };
+
+    match status.get("replica_health_status").map(|s| s.as_text()) {
+        None | Some(Some("healthy")) => Ok(SYNTHETIC_HEIGHT),
+        Some(health_status) => {
+            log::error!("icp replica is not healthy: {:?}", health_status);
+            ProxyError::InvalidStatus.explain("during http healthcheck")
+        }
+    }
+}
+
+// how long an internet computer read_state request is valid, the replicas accept up to 5 minutes
+const ICP_INGRESS_EXPIRY: Duration = Duration::from_secs(3 * 60);
+
+/// Internet computer read_state request of the certified `time`, sent by the anonymous principal
+pub(crate) fn icp_read_state_request() -> Vec<u8> {
+    let expiry = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default() + ICP_INGRESS_EXPIRY;
+    icp_read_state_request_at(expiry.as_nanos() as u64)
+}
+
+fn icp_read_state_request_at(ingress_expiry_nanos: u64) -> Vec<u8> {
+    let text = |value: &str| CborValue::Text(value.to_string());
+    let content = CborValue::Map(vec![
+        (text("request_type"), text("read_state")),
+        (text("sender"), CborValue::Bytes(vec![ICP_ANONYMOUS_PRINCIPAL])),
+        (text("paths"), CborValue::Array(vec![CborValue::Array(vec![CborValue::Bytes(b"time".to_vec())])])),
+        (text("ingress_expiry"), CborValue::Unsigned(ingress_expiry_nanos)),
+    ]);
+    let envelope = CborValue::Map(vec![(text("content"), content)]);
+    cbor::encode(&CborValue::Tag(CBOR_SELF_DESCRIBED_TAG, Box::new(envelope)))
+}
+
+// the single byte principal of unauthenticated internet computer requests
+const ICP_ANONYMOUS_PRINCIPAL: u8 = 0x04;
+
+// tag marking a cbor document, sent by the internet computer clients
+const CBOR_SELF_DESCRIBED_TAG: u64 = 55799;
+
+/// Internet computer read_state validator, the height is the certified `time` of the replica in seconds
+///
+/// The response is a cbor map with the certificate, itself cbor with the hash tree holding the time
+/// as leb128 nanoseconds. The certificate signature isn't verified, a probe only compares replicas.
+pub(crate) fn icp_read_state_validator(body: &[u8]) -> Result<u64> {
+    let certificate = match cbor::decode(body).as_ref().and_then(|response| response.get("certificate")) {
+        Some(CborValue::Bytes(certificate)) => cbor::decode(certificate),
+        _ => None,
+    };
+    let time = certificate
+        .as_ref()
+        .and_then(|certificate| certificate.get("tree"))
+        .and_then(|tree| icp_tree_lookup(tree, b"time"))
+        .and_then(|leaf| match leaf {
+            CborValue::Array(node) => match node.as_slice() {
+                [CborValue::Unsigned(3), CborValue::Bytes(value)] => read_leb128(value),
+                _ => None,
+            },
+            _ => None,
+        });
+
+    match time {
+        Some(nanos) => Ok(nanos / 1_000_000_000),
+        None => {
+            log::error!("failed to read icp certified time: {:02x?}", &body[..body.len().min(256)]);
+            ProxyError::InvalidBlockNumber.explain("during http healthcheck")
+        }
+    }
+}
+
+/// The subtree under a label of an internet computer hash tree, looked up through the forks
+fn icp_tree_lookup<'a>(tree: &'a CborValue, label: &[u8]) -> Option<&'a CborValue> {
+    let CborValue::Array(node) = tree else {
+        return None;
+    };
+    match node.as_slice() {
+        // fork
+        [CborValue::Unsigned(1), left, right] => {
+            icp_tree_lookup(left, label).or_else(|| icp_tree_lookup(right, label))
+        }
+        // labeled subtree
+        [CborValue::Unsigned(2), CborValue::Bytes(node_label), subtree] if node_label == label => Some(subtree),
+        _ => None,
+    }
+}
+
+/// Decode an unsigned leb128 number filling the whole input
+fn read_leb128(bytes: &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for (i, byte) in bytes.iter().enumerate() {
+        let shift = 7 * i as u32;
+        if shift >= 64 {
+            return None;
+        }
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return (i + 1 == bytes.len()).then_some(value);
+        }
+    }
+    None
+}
+
+/// fuel graphql latest block response and validator
+#[derive(Debug, Serialize, Deserialize)]
+struct FuelGraphqlResponse {
+    data: Option<FuelChainData>,
+    /// graphql errors, the data may be partial or null when present
+    errors: Option<Vec<serde_json::Value>>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FuelChainData {
+    chain: FuelChain,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FuelChain {
+    latest_block: FuelBlock,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FuelBlock {
+    header: FuelBlockHeader,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct FuelBlockHeader {
+    /// The key to check in the JSON response, a u32 encoded as a string
+    #[serde(deserialize_with = "height_string")]
+    height: String,
+}
+
+pub(crate) fn fuel_validator(body: &[u8]) -> Result<u64> {
+    // try to parse the JSON response
+    let parsed: Result<FuelGraphqlResponse, serde_json::Error> = serde_json::from_slice(body);
+    if parsed.is_err() {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidJson.explain("during http healthcheck");
+    }
+
+    let parsed = parsed.unwrap();
+
+    // a response with errors is unhealthy, even with partial data
+    if parsed.errors.as_ref().is_some_and(|errors| !errors.is_empty()) {
+        log::error!("fuel graphql errors: {}", String::from_utf8_lossy(body));
+        return ProxyError::FailedToValidateResponseBody.explain("during http healthcheck");
+    }
+
+    let height = parsed.data.map(|data| data.chain.latest_block.header.height);
+    match height.and_then(|h| parse_height(&h)) {
+        Some(height) => Ok(height),
+        None => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            ProxyError::InvalidBlockNumber.explain("during http healthcheck")
+        }
+    }
+}
+
+/// nano block_count response and validator
+#[derive(Debug, Serialize, Deserialize)]
+struct NanoBlockCountResponse {
+    /// The keys to check in the JSON response, numbers are encoded as strings
+    count: Option<String>,
+    cemented: Option<String>,
+}
+
+pub(crate) fn nano_validator(body: &[u8]) -> Result<u64> {
+    // try to parse the JSON response
+    let parsed: Result<NanoBlockCountResponse, serde_json::Error> = serde_json::from_slice(body);
+    if parsed.is_err() {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidJson.explain("during http healthcheck");
+    }
+
+    let parsed = parsed.unwrap();
+
+    // cemented blocks are confirmed, the count is a fallback for nodes not reporting it
+    let height = parsed.cemented.or(parsed.count);
+    match height.and_then(|h| parse_height(&h)) {
+        Some(height) => Ok(height),
+        None => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            ProxyError::InvalidBlockNumber.explain("during http healthcheck")
+        }
+    }
+}
+
+/// neo getblockcount response and validator
+#[derive(Debug, Serialize, Deserialize)]
+struct NeoBlockCountResponse {
+    /// The key to check in the JSON response, the number of blocks including the genesis block
+    result: u64,
+}
+
+pub(crate) fn neo_validator(body: &[u8]) -> Result<u64> {
+    // try to parse the JSON response
+    let parsed: Result<NeoBlockCountResponse, serde_json::Error> = serde_json::from_slice(body);
+    if parsed.is_err() {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidJson.explain("during http healthcheck");
+    }
+
+    // the tip height is the count minus the genesis block, a count of 0 has no tip
+    match parsed.unwrap().result.checked_sub(1) {
+        Some(height) => Ok(height),
+        None => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            ProxyError::InvalidBlockNumber.explain("during http healthcheck")
+        }
+    }
+}
+
+/// aptos fullnode ledger info response and validator
+#[derive(Debug, Serialize, Deserialize)]
+struct AptosLedgerInfo {
+    /// The keys to check in the JSON response, numbers are encoded as strings
+    #[serde(deserialize_with = "height_string")]
+    block_height: String,
+    /// Ledger timestamp in microseconds since the unix epoch
+    ledger_timestamp: String,
+}
+
+pub(crate) fn aptos_validator(body: &[u8]) -> Result<u64> {
+    read_aptos_ledger(body, None, SystemTime::now())
+}
+
+/// Aptos validator also rejecting a ledger timestamp older than `max_age`
+pub(crate) fn aptos_ledger_age_validator(max_age: Duration) -> Validator {
+    Arc::new(move |body: &[u8]| read_aptos_ledger(body, Some(max_age), SystemTime::now()))
+}
+
+fn read_aptos_ledger(body: &[u8], max_age: Option<Duration>, now: SystemTime) -> Result<u64> {
+    // try to parse the JSON response
+    let parsed: Result<AptosLedgerInfo, serde_json::Error> = serde_json::from_slice(body);
+    if parsed.is_err() {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidJson.explain("during http healthcheck");
+    }
+
+    let parsed = parsed.unwrap();
+
+    // from string to u64
+    let block_height = parse_height(&parsed.block_height);
+    if block_height.is_none() {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidBlockNumber.explain("during http healthcheck");
+    }
+
+    if let Some(max_age) = max_age {
+        let ledger_timestamp = match parsed.ledger_timestamp.parse::<u64>() {
+            Ok(micros) => UNIX_EPOCH + Duration::from_micros(micros),
+            Err(_) => {
+                log::error!("failed to parse ledger timestamp: {}", parsed.ledger_timestamp);
+                return ProxyError::InvalidBlockNumber.explain("during http healthcheck");
+            }
+        };
+
+        // a ledger timestamp in the future is considered fresh
+        let age = now.duration_since(ledger_timestamp).unwrap_or_default();
+        if age > max_age {
+            log::error!("aptos ledger is {:?} old, more than {:?}", age, max_age);
+            return ProxyError::StaleLedger.explain("during http healthcheck");
+        }
+    }
+
+    Ok(block_height.unwrap())
+}
+
+/// tron wallet/getnowblock response and validator
+#[derive(Debug, Serialize, Deserialize)]
+struct TronBlockResponse {
+    block_header: TronBlockHeader,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TronBlockHeader {
+    raw_data: TronBlockRawData,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TronBlockRawData {
+    number: u64,
+    // block time in milliseconds
+    #[serde(default)]
+    timestamp: Option<u64>,
+}
+
+pub(crate) fn tron_validator(body: &[u8]) -> Result<u64> {
+    read_tron_block(body, None, SystemTime::now())
+}
+
+/// Validator of the tron latest block which also fails when the block is older than the max age,
+/// a stalled node keeps reporting the same height
+pub(crate) fn tron_block_age_validator(max_age: Duration) -> Validator {
+    Arc::new(move |body: &[u8]| read_tron_block(body, Some(max_age), SystemTime::now()))
+}
+
+fn read_tron_block(body: &[u8], max_age: Option<Duration>, now: SystemTime) -> Result<u64> {
+    let parsed: Result<TronBlockResponse, serde_json::Error> = serde_json::from_slice(body);
+    let raw_data = match parsed {
+        Ok(parsed) => parsed.block_header.raw_data,
+        Err(_) => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            return ProxyError::InvalidJson.explain("during http healthcheck");
+        }
+    };
+
+    if let Some(max_age) = max_age {
+        let block_time = match raw_data.timestamp {
+            Some(millis) => UNIX_EPOCH + Duration::from_millis(millis),
+            None => {
+                log::error!("tron block {} has no timestamp", raw_data.number);
+                return ProxyError::InvalidBlockNumber.explain("during http healthcheck");
+            }
+        };
+
+        // a block time in the future is considered fresh
+        let age = now.duration_since(block_time).unwrap_or_default();
+        if age > max_age {
+            log::error!("tron block {} is {:?} old, more than {:?}", raw_data.number, age, max_age);
+            return ProxyError::StaleLedger.explain("during http healthcheck");
+        }
+    }
+
+    Ok(raw_data.number)
+}
+
+/// sui latest checkpoint sequence number response and validator
+#[derive(Debug, Serialize, Deserialize)]
+struct SuiCheckpointNumberResponse {
+    /// The key to check in the JSON response, the sequence number encoded as a string
+    #[serde(deserialize_with = "height_string")]
+    result: String,
+}
+
+pub(crate) fn sui_validator(body: &[u8]) -> Result<u64> {
+    // try to parse the JSON response
+    let parsed: Result<SuiCheckpointNumberResponse, serde_json::Error> = serde_json::from_slice(body);
+    if parsed.is_err() {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidJson.explain("during http healthcheck");
+    }
+
+    match parse_height(&parsed.unwrap().result) {
+        Some(height) => Ok(height),
+        None => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            ProxyError::InvalidBlockNumber.explain("during http healthcheck")
+        }
+    }
+}
+
+/// sui_getCheckpoints response, the latest checkpoint first
+#[derive(Debug, Serialize, Deserialize)]
+struct SuiCheckpointsResponse {
+    result: SuiCheckpointPage,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SuiCheckpointPage {
+    data: Vec<SuiCheckpoint>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SuiCheckpoint {
+    /// The keys to check in the JSON response, numbers are encoded as strings
+    sequence_number: String,
+    /// Checkpoint timestamp in milliseconds since the unix epoch
+    timestamp_ms: String,
+}
+
+/// Sui validator of the latest checkpoint rejecting a checkpoint timestamp older than `max_age`
+pub(crate) fn sui_checkpoint_age_validator(max_age: Duration) -> Validator {
+    Arc::new(move |body: &[u8]| read_sui_checkpoint(body, max_age, SystemTime::now()))
+}
+
+fn read_sui_checkpoint(body: &[u8], max_age: Duration, now: SystemTime) -> Result<u64> {
+    // try to parse the JSON response
+    let parsed: Result<SuiCheckpointsResponse, serde_json::Error> = serde_json::from_slice(body);
+    let checkpoint = match parsed.map(|parsed| parsed.result.data.into_iter().next()) {
+        Ok(Some(checkpoint)) => checkpoint,
+        _ => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            return ProxyError::InvalidJson.explain("during http healthcheck");
+        }
+    };
+
+    let sequence_number = parse_height(&checkpoint.sequence_number);
+    let timestamp = checkpoint.timestamp_ms.parse::<u64>();
+    let (sequence_number, timestamp) = match (sequence_number, timestamp) {
+        (Some(sequence_number), Ok(millis)) => (sequence_number, UNIX_EPOCH + Duration::from_millis(millis)),
+        _ => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            return ProxyError::InvalidBlockNumber.explain("during http healthcheck");
+        }
+    };
+
+    // a checkpoint timestamp in the future is considered fresh
+    let age = now.duration_since(timestamp).unwrap_or_default();
+    if age > max_age {
+        log::error!("sui checkpoint {} is {:?} old, more than {:?}", sequence_number, age, max_age);
+        return ProxyError::StaleLedger.explain("during http healthcheck");
+    }
+
+    Ok(sequence_number)
+}
+
+/// substrate chain_getFinalizedHead response, the block hash
+#[derive(Debug, Serialize, Deserialize)]
+struct SubstrateHashResponse {
+    result: String,
+}
+
+/// Build the chain_getHeader request of the block hash answered by chain_getFinalizedHead
+pub(crate) fn substrate_header_request(body: &[u8]) -> Result<Vec<u8>> {
+    // try to parse the JSON response
+    let parsed: Result<SubstrateHashResponse, serde_json::Error> = serde_json::from_slice(body);
+    let hash = match parsed {
+        Ok(parsed) => parsed.result,
+        Err(_) => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            return ProxyError::InvalidJson.explain("during http healthcheck");
+        }
+    };
+
+    // the hash is sent back to the node, only accept a hex hash
+    let is_hex_hash = hash
+        .strip_prefix("0x")
+        .is_some_and(|hex| !hex.is_empty() && hex.chars().all(|c| c.is_ascii_hexdigit()));
+    if !is_hex_hash {
+        log::error!("invalid finalized head hash: {}", hash);
+        return ProxyError::InvalidJson.explain("during http healthcheck");
+    }
+
+    Ok(format!(r#"{{"jsonrpc":"2.0","method":"chain_getHeader","params":["{hash}"],"id":1}}"#).into_bytes())
+}
+
+/// substrate chain_getHeader response and validator, the result is null for an unknown block
+#[derive(Debug, Serialize, Deserialize)]
+struct SubstrateHeaderResponse {
+    result: Option<SubstrateHeader>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SubstrateHeader {
+    /// The key to check in the JSON response, a hex quantity
+    number: String,
+}
+
+pub(crate) fn substrate_header_validator(body: &[u8]) -> Result<u64> {
+    // try to parse the JSON response
+    let parsed: Result<SubstrateHeaderResponse, serde_json::Error> = serde_json::from_slice(body);
+    let header = match parsed {
+        Ok(SubstrateHeaderResponse { result: Some(header) }) => header,
+        _ => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            return ProxyError::InvalidJson.explain("during http healthcheck");
+        }
+    };
+
+    // from hex string to u64
+    let number = header.number.trim_start_matches("0x");
+    match u64::from_str_radix(number, 16) {
+        Ok(block_number) => Ok(block_number),
+        Err(_) => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            ProxyError::InvalidBlockNumber.explain("during http healthcheck")
+        }
+    }
+}
+
+/// substrate system_syncState response and validator
+#[derive(Debug, Serialize, Deserialize)]
+struct SubstrateSyncStateResponse {
+    result: Option<SubstrateSyncState>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SubstrateSyncState {
+    current_block: u64,
+}
+
+pub(crate) fn substrate_sync_state_validator(body: &[u8]) -> Result<u64> {
+    // try to parse the JSON response
+    let parsed: Result<SubstrateSyncStateResponse, serde_json::Error> = serde_json::from_slice(body);
+    match parsed {
+        Ok(SubstrateSyncStateResponse { result: Some(sync_state) }) => Ok(sync_state.current_block),
+        _ => {
+            // log the body
+            log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+            ProxyError::InvalidJson.explain("during http healthcheck")
+        }
+    }
+}
+
+/// substrate system_health response
+#[derive(Debug, Serialize, Deserialize)]
+struct SystemHealthResponse {
+    result: Option<SystemHealth>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SystemHealth {
+    peers: u64,
+    is_syncing: bool,
+}
+
+/// Validator of the substrate system_health response, fewer peers than the minimum or a syncing node fails
+pub(crate) fn system_health_validator(min_peers: u64) -> Validator {
+    Arc::new(move |body: &[u8]| {
+        let parsed: Result<SystemHealthResponse, serde_json::Error> = serde_json::from_slice(body);
+        let health = match parsed {
+            Ok(SystemHealthResponse { result: Some(health) }) => health,
+            _ => {
+                // log the body
+                log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+                return ProxyError::InvalidJson.explain("during http healthcheck");
+            }
+        };
+
+        if health.peers < min_peers {
+            log::error!("{} peers, less than the minimum {}", health.peers, min_peers);
+            return ProxyError::InsufficientPeers.explain("validator error");
+        }
+        if health.is_syncing {
+            log::error!("node is syncing: {}", String::from_utf8_lossy(body));
+            return ProxyError::NodeSyncing.explain("validator error");
+        }
+        Ok(health.peers)
+    })
+}
+
+/// Validator of a cosmos `cosmos.base.tendermint.v1beta1.Service/GetLatestBlock` grpc response,
+/// the height is read from `sdk_block` (field 3) and from the deprecated `block` (field 2) on older nodes
+pub(crate) fn cosmos_grpc_validator(body: &[u8]) -> Result<u64> {
+    let message = match protobuf::grpc_message(body) {
+        Some(message) => message,
+        None => {
+            log::error!("failed to read grpc message: {:02x?}", &body[..body.len().min(256)]);
+            return ProxyError::FailedToValidateResponseBody.explain("during http healthcheck");
+        }
+    };
+
+    // block.header (1).height (3)
+    let height = [3, 2].iter().find_map(|block| {
+        let header = protobuf::message_at(message, &[*block, 1])?;
+        match protobuf::field(header, 3)? {
+            protobuf::ProtoValue::Varint(height) => Some(height),
+            _ => None,
+        }
+    });
+
+    match height {
+        Some(height) => Ok(height),
+        None => {
+            log::error!("failed to read block height: {:02x?}", &message[..message.len().min(256)]);
+            ProxyError::InvalidBlockNumber.explain("during http healthcheck")
+        }
+    }
+}
+
+/// Generic validator reading the block height at a json pointer, e.g. `/result/sync_info/latest_block_height`,
+/// so chains without a dedicated validator can be supported from the config only
+pub(crate) fn json_pointer_validator(path: &str, value_type: ResultValueType) -> Validator {
+    let path = path.to_string();
+    Arc::new(move |body: &[u8]| read_json_pointer(body, &path, value_type))
+}
+
+pub(crate) fn read_json_pointer(body: &[u8], path: &str, value_type: ResultValueType) -> Result<u64> {
+    // try to parse the JSON response
+    let parsed: Result<serde_json::Value, serde_json::Error> = serde_json::from_slice(body);
+    if parsed.is_err() {
+        // log the body
+        log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+        return ProxyError::InvalidJson.explain("during http healthcheck");
+    }
+
+    let parsed = parsed.unwrap();
+    let block_number = match (parsed.pointer(path), value_type) {
+        (Some(value @ serde_json::Value::Number(_)), ResultValueType::Number) => json_height(value),
+        (Some(serde_json::Value::String(s)), ResultValueType::Decimal) => parse_height(s),
+        (Some(serde_json::Value::String(s)), ResultValueType::Hex) => s
+            .strip_prefix("0x")
+            .and_then(|hex| u64::from_str_radix(hex, 16).ok()),
+        _ => None,
+    };
+
+    match block_number {
+        Some(block_number) => Ok(block_number),
+        None => {
+            // log the body
+            log::error!("failed to read {} at {}: {}", path, value_type.as_str(), String::from_utf8_lossy(body));
+            ProxyError::InvalidBlockNumber.explain("during http healthcheck")
+        }
+    }
+}
+
+/// Validator comparing the value at a json pointer with an expected value, e.g. `/result` to be `false` for
+/// eth_syncing, the reported number is always 0
+pub(crate) fn json_pointer_expect_validator(path: &str, expected: serde_json::Value) -> Validator {
+    let path = path.to_string();
+    Arc::new(move |body: &[u8]| {
+        let parsed: serde_json::Value = match serde_json::from_slice(body) {
+            Ok(parsed) => parsed,
+            Err(_) => {
+                // log the body
+                log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+                return ProxyError::InvalidJson.explain("during http healthcheck");
+            }
+        };
+
+        if parsed.pointer(&path) != Some(&expected) {
+            log::error!("unexpected value at {}, expected {}: {}", path, expected, String::from_utf8_lossy(body));
+            return ProxyError::FailedToValidateResponseBody.explain("validator error");
+        }
+        Ok(0)
+    })
+}
+
+/// Validator of the net_peerCount response, fewer peers than the minimum fails
+pub(crate) fn min_peer_count_validator(min_peer_count: u64) -> Validator {
+    Arc::new(move |body: &[u8]| {
+        // net_peerCount answers a hex quantity like eth_blockNumber
+        let peer_count = eth_validator(body)?;
+        if peer_count < min_peer_count {
+            log::error!("{} peers, less than the minimum {}", peer_count, min_peer_count);
+            return ProxyError::InsufficientPeers.explain("validator error");
+        }
+        Ok(peer_count)
+    })
+}
+
+#[derive(Deserialize, Debug)]
+struct EthSyncingResponse {
+    result: Option<EthSyncing>,
+}
+
+/// The eth_syncing result, false once synced or the sync progress object
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum EthSyncing {
+    Synced(bool),
+    #[serde(rename_all = "camelCase")]
+    Progress {
+        current_block: String,
+        highest_block: String,
+    },
+}
+
+/// Validator of the eth_syncing response, the gap between the current and highest block of a syncing node
+/// is reported and fails over the maximum, a synced node has no gap
+pub(crate) fn eth_syncing_validator(max_syncing_gap: u64) -> Validator {
+    Arc::new(move |body: &[u8]| {
+        let parsed: Result<EthSyncingResponse, serde_json::Error> = serde_json::from_slice(body);
+        let (current_block, highest_block) = match parsed {
+            Ok(EthSyncingResponse { result: Some(EthSyncing::Synced(false)) }) => return Ok(0),
+            Ok(EthSyncingResponse { result: Some(EthSyncing::Progress { current_block, highest_block }) }) => {
+                (current_block, highest_block)
+            }
+            _ => {
+                // log the body
+                log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+                return ProxyError::InvalidJson.explain("during http healthcheck");
+            }
+        };
+
+        // from hex strings to u64
+        let parse = |block: &str| u64::from_str_radix(block.trim_start_matches("0x"), 16).ok();
+        let gap = match (parse(&current_block), parse(&highest_block)) {
+            (Some(current_block), Some(highest_block)) => highest_block.saturating_sub(current_block),
+            _ => {
+                log::error!("failed to parse syncing blocks: {}", String::from_utf8_lossy(body));
+                return ProxyError::InvalidBlockNumber.explain("during http healthcheck");
+            }
+        };
+
+        if gap > max_syncing_gap {
+            log::error!("node syncing {} blocks behind, more than the maximum {}", gap, max_syncing_gap);
+            return ProxyError::NodeSyncing.explain("validator error");
+        }
+        log::warn!("node syncing {} blocks behind", gap);
+        Ok(gap)
+    })
+}
+
+/// Build the jsonrpc batch of `eth_blockNumber` (id 1) and the verified state method (id 2)
+pub(crate) fn verification_gap_request(method: &str, params: &[serde_json::Value]) -> Vec<u8> {
+    serde_json::json!([
+        {"jsonrpc": "2.0", "method": "eth_blockNumber", "params": [], "id": 1},
+        {"jsonrpc": "2.0", "method": method, "params": params, "id": 2},
+    ])
+    .to_string()
+    .into_bytes()
+}
+
+/// Validator of the verification gap batch response, the latest block must be at most the max gap ahead of
+/// the verified batch or block, answered as a number or as a block object with its `number`
+pub(crate) fn verification_gap_validator(max_gap: u64) -> Validator {
+    Arc::new(move |body: &[u8]| {
+        let responses: Vec<serde_json::Value> = match serde_json::from_slice(body) {
+            Ok(responses) => responses,
+            Err(_) => {
+                // log the body
+                log::error!("failed to parse json: {}", String::from_utf8_lossy(body));
+                return ProxyError::InvalidJson.explain("during http healthcheck");
+            }
+        };
+        // batch responses may come in any order
+        let height_of = |id: u64| {
+            let result = responses.iter().find(|response| response["id"] == id)?.get("result")?;
+            json_height(result).or_else(|| result.get("number").and_then(json_height))
+        };
+        let (latest, verified) = match (height_of(1), height_of(2)) {
+            (Some(latest), Some(verified)) => (latest, verified),
+            _ => {
+                log::error!("failed to read verification gap blocks: {}", String::from_utf8_lossy(body));
+                return ProxyError::InvalidBlockNumber.explain("during http healthcheck");
+            }
+        };
+
+        let gap = latest.saturating_sub(verified);
+        if gap > max_gap {
+            log::error!("latest block {} is {} ahead of verified {}, more than {}", latest, gap, verified, max_gap);
+            return ProxyError::VerificationLagging.explain("validator error");
+        }
+        Ok(verified)
+    })
+}
+
+/// Validator of the eth_gasPrice response, a zero gas price is reported by broken nodes
+pub(crate) fn gas_price_validator(body: &[u8]) -> Result<u64> {
+    // eth_gasPrice answers a hex quantity like eth_blockNumber
+    let gas_price = eth_validator(body)?;
+    if gas_price == 0 {
+        log::error!("gas price is 0: {}", String::from_utf8_lossy(body));
+        return ProxyError::FailedToValidateResponseBody.explain("validator error");
+    }
+    Ok(gas_price)
+}
+
+/// Secondary probe of a chain health check
+///
+/// Secondary probes are evaluated in order after the primary probe and all of them must pass for the node
+/// to be healthy, the first failure short-circuits the rest. The block height only comes from the primary probe.
+#[derive(Clone)]
+pub struct SecondaryProbe {
+    pub request_method: String,
+
+    pub request_url: String,
+
+    pub request_body: Option<Vec<u8>>,
+
+    /// Optional response validator, its error fails the check as is.
+    ///
+    /// If not set, any `2xx` response passes.
+    pub validator: Option<Validator>,
+}
+
+impl SecondaryProbe {
+    pub fn new(host: &str, path: &str, method: &str) -> Self {
+        SecondaryProbe {
+            request_method: method.to_string(),
+            request_url: format!("{}{}", host, path),
+            request_body: None,
+            validator: None,
+        }
+    }
+
+    /// Set the request body to send to the backend
+    pub fn with_request_body(mut self, body: Vec<u8>) -> Self {
+        self.request_body = Some(body);
+        self
+    }
+
+    /// Set the response body validator
+    pub fn with_response_body_validator(mut self, validator: Validator) -> Self {
+        self.validator = Some(validator);
+        self
+    }
+}
+
+/// Chain health check
+///
+/// This health check checks if it can receive the expected HTTP(s) response from the given backend.
+pub struct ChainHealthCheck {
+    /// Number of successful checks to flip from unhealthy to healthy.
+    pub consecutive_success: usize,
+    /// Number of failed checks to flip from healthy to unhealthy.
+    pub consecutive_failure: usize,
+
+    pub chain_state: Arc<Mutex<ChainState>>,
+
+    pub request_method: String,
+
+    pub request_url: String,
+
+    pub request_body: Option<Vec<u8>>,
+
+    pub request_timeout: Duration,
+
+    /// Optional interval of the health checks, the request timeout is its default capped to the interval if set.
+    pub interval: Option<HealthCheckInterval>,
+
+    pub client: Arc<Client>,
+
+    /// Settings the client was built with.
+    pub client_options: ClientOptions,
+
+    /// Optional field to define how to validate the response from the server.
+    ///
+    /// If not set, any response with a `200 OK` is considered a successful check.
+    pub validator: Option<Validator>,
+
+    pub host: String,
+
+    /// Number of extra attempts made when the request fails to get a response.
+    pub retries: usize,
+
+    /// Base backoff between retries, doubled on every attempt and jittered.
+    pub retry_backoff: Duration,
+
+    /// Optional response header carrying the block height, the body is not parsed if set.
+    pub height_header: Option<String>,
+
+    /// Statuses the primary response must have along with a valid body, any status is accepted if empty.
+    pub expected_status: Vec<RangeInclusive<u16>>,
+
+    /// Optional second request built from the primary response, the validator reads its response instead.
+    pub follow_up_request: Option<FollowUpRequest>,
+
+    /// Probes which must also pass after the primary one, in order.
+    pub secondary_probes: Vec<SecondaryProbe>,
+
+    /// Days before the tls certificate expiry from which a warning is logged.
+    pub cert_expiry_warn_days: Option<i64>,
+
+    /// Days before the tls certificate expiry from which the check fails.
+    pub cert_expiry_min_days: Option<i64>,
+
+    /// Whether the requests are grpc calls, sent over http/2.
+    pub grpc: bool,
+
+    /// Optional builder of the request body of every probe, it replaces the request body.
+    pub request_body_builder: Option<RequestBodyBuilder>,
+
+    /// Whether the requests are cbor encoded.
+    pub cbor: bool,
+
+    /// Optional nodes still in the chain config, the node is no longer probed once a config reload removes it.
+    pub chain_nodes: Option<ChainNodes>,
+}
+
+impl ChainHealthCheck {
+    /// Create a new [ChainHealthCheck] with the following default settings
+    /// * req: a GET/POST to the given path of the given host name
+    /// * request_body: None
+    /// * consecutive_success: 1
+    /// * consecutive_failure: 1
+    /// * validator: `None`, any 200 response is considered successful
+    pub fn new(host: &str, path: &str, method: &str, state: Arc<Mutex<ChainState>>) -> Box<Self> {
+        let request_url = format!("{}{}", host, path);
+
+        Box::new(ChainHealthCheck {
+            consecutive_success: 1,
+            consecutive_failure: 1,
+            chain_state: Arc::clone(&state),
+            request_method: method.to_string(),
+            request_url: request_url.to_string(),
+            request_body: None,
+            request_timeout: Duration::from_secs(60),
+            interval: None,
+            client: Arc::new(build_client(&ClientOptions::default()).unwrap_or_default()),
+            client_options: ClientOptions::default(),
+            validator: None,
+            host: host.to_string(),
+            retries: 0,
+            retry_backoff: Duration::from_millis(0),
+            height_header: None,
+            expected_status: Vec::new(),
+            follow_up_request: None,
+            secondary_probes: Vec::new(),
+            cert_expiry_warn_days: None,
+            cert_expiry_min_days: None,
+            grpc: false,
+            request_body_builder: None,
+            cbor: false,
+            chain_nodes: None,
+        })
+    }
+
+    /// Set the request body to send to the backend
+    pub fn with_request_body(mut self, body: Vec<u8>) -> Box<Self> {
+        self.request_body = Some(body);
+        Box::new(self)
+    }
+
+    /// Set the response body validator
+    pub fn with_response_body_validator(mut self, validator: Validator) -> Box<Self> {
+        self.validator = Some(validator);
+        Box::new(self)
+    }
+
+    /// Set the timeout of every health check request
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Box<Self> {
+        self.request_timeout = request_timeout;
+        Box::new(self)
+    }
+
+    /// Use the default request timeout capped to the interval, following its changes, instead of a fixed one
+    pub fn with_interval(mut self, interval: HealthCheckInterval) -> Box<Self> {
+        self.interval = Some(interval);
+        Box::new(self)
+    }
+
+    /// Stop probing the node once it is removed from the nodes of the chain
+    pub fn with_chain_nodes(mut self, chain_nodes: ChainNodes) -> Box<Self> {
+        self.chain_nodes = Some(chain_nodes);
+        Box::new(self)
+    }
+
+    /// Set the retry budget, how many extra attempts and the base backoff between them
+    pub fn with_retries(mut self, retries: usize, backoff: Duration) -> Box<Self> {
+        self.retries = retries;
+        self.retry_backoff = backoff;
+        Box::new(self)
+    }
+
+    /// Set how many consecutive results flip the health of the node, at least 1
+    pub fn with_thresholds(mut self, consecutive_success: usize, consecutive_failure: usize) -> Box<Self> {
+        self.consecutive_success = consecutive_success.max(1);
+        self.consecutive_failure = consecutive_failure.max(1);
+        Box::new(self)
+    }
+
+    /// Read the block height from the given response header instead of the body
+    pub fn with_height_header(mut self, header_name: &str) -> Box<Self> {
+        self.height_header = Some(header_name.to_string());
+        Box::new(self)
+    }
+
+    /// Require the primary response status to be in one of the ranges, the body must still be valid
+    pub fn with_expected_status(mut self, expected_status: Vec<RangeInclusive<u16>>) -> Box<Self> {
+        self.expected_status = expected_status;
+        Box::new(self)
+    }
+
+    /// Send a second request built from the primary response, to the same url and with the same method,
+    /// and validate its response, e.g. a block hash resolved to its header
+    pub fn with_follow_up_request(mut self, follow_up_request: FollowUpRequest) -> Box<Self> {
+        self.follow_up_request = Some(follow_up_request);
+        Box::new(self)
+    }
+
+    /// Also probe `net_peerCount` and require at least the given number of peers,
+    /// an isolated node may serve stale data while its block number looks fine
+    pub fn with_min_peer_count(self, min_peer_count: u64) -> Box<Self> {
+        let probe = SecondaryProbe {
+            request_method: "POST".to_string(),
+            request_url: self.request_url.clone(),
+            request_body: Some(NET_PEER_COUNT_REQUEST.to_vec()),
+            validator: Some(min_peer_count_validator(min_peer_count)),
+        };
+        self.with_secondary_probe(probe)
+    }
+
+    /// Also probe `eth_syncing` and require the node to be synced or syncing at most the given number of blocks
+    /// behind its `highestBlock`, a node catching up still answers its block number
+    pub fn with_max_syncing_gap(self, max_syncing_gap: u64) -> Box<Self> {
+        let probe = SecondaryProbe {
+            request_method: "POST".to_string(),
+            request_url: self.request_url.clone(),
+            request_body: Some(ETH_SYNCING_REQUEST.to_vec()),
+            validator: Some(eth_syncing_validator(max_syncing_gap)),
+        };
+        self.with_secondary_probe(probe)
+    }
+
+    /// Also probe `eth_gasPrice` and require a non-zero gas price, a broken node may still report blocks
+    pub fn with_gas_price_check(self) -> Box<Self> {
+        let probe = SecondaryProbe {
+            request_method: "POST".to_string(),
+            request_url: self.request_url.clone(),
+            request_body: Some(ETH_GAS_PRICE_REQUEST.to_vec()),
+            validator: Some(Arc::new(gas_price_validator)),
+        };
+        self.with_secondary_probe(probe)
+    }
+
+    /// Also probe `state_getRuntimeVersion` and require the given `specName`, a parachain endpoint
+    /// pointing at the wrong runtime may still report a plausible sync state
+    pub fn with_expected_spec_name(self, spec_name: &str) -> Box<Self> {
+        let probe = SecondaryProbe {
+            request_method: "POST".to_string(),
+            request_url: self.request_url.clone(),
+            request_body: Some(STATE_GET_RUNTIME_VERSION_REQUEST.to_vec()),
+            validator: Some(json_pointer_expect_validator(
+                "/result/specName",
+                serde_json::Value::String(spec_name.to_string()),
+            )),
+        };
+        self.with_secondary_probe(probe)
+    }
+
+    /// Also probe `system_health` and require at least the given number of peers and the node not syncing,
+    /// e.g. on Vara and other Gear chains, a node may answer its sync state while isolated or catching up
+    pub fn with_system_health(self, min_peers: u64) -> Box<Self> {
+        let probe = SecondaryProbe {
+            request_method: "POST".to_string(),
+            request_url: self.request_url.clone(),
+            request_body: Some(SYSTEM_HEALTH_REQUEST.to_vec()),
+            validator: Some(system_health_validator(min_peers)),
+        };
+        self.with_secondary_probe(probe)
+    }
+
+    /// Also probe the verified batch of a zk rollup node along with `eth_blockNumber` in one jsonrpc batch,
+    /// and require the latest block to be at most the given gap ahead of it, e.g. `zkevm_verifiedBatchNumber`
+    /// or `eth_getBlockByNumber` of the finalized block on Linea
+    pub fn with_verification_gap(self, method: &str, params: &[serde_json::Value], max_gap: u64) -> Box<Self> {
+        let probe = SecondaryProbe {
+            request_method: "POST".to_string(),
+            request_url: self.request_url.clone(),
+            request_body: Some(verification_gap_request(method, params)),
+            validator: Some(verification_gap_validator(max_gap)),
+        };
+        self.with_secondary_probe(probe)
+    }
+
+    /// Add a probe which must also pass for the node to be healthy
+    pub fn with_secondary_probe(mut self, probe: SecondaryProbe) -> Box<Self> {
+        self.secondary_probes.push(probe);
+        Box::new(self)
+    }
+
+    /// Send the health check requests as grpc calls, over http/2 with prior knowledge so plaintext
+    /// nodes are reached without an upgrade, set before the upstream proxy
+    pub fn with_grpc(mut self) -> Box<Self> {
+        self.grpc = true;
+        let options = ClientOptions { http2_only: true, ..self.client_options.clone() };
+        if let Err(e) = self.rebuild_client(options) {
+            log::error!("failed to build grpc client for {}, error: {}", self.host, e);
+        }
+        Box::new(self)
+    }
+
+    /// Build the request body of every probe when it is sent, the body must be fresh,
+    /// and send it as cbor if `cbor` is set
+    pub fn with_request_body_builder(mut self, builder: RequestBodyBuilder, cbor: bool) -> Box<Self> {
+        self.request_body_builder = Some(builder);
+        self.cbor = cbor;
+        Box::new(self)
+    }
+
+    /// Send the health check requests through the given http or socks5 proxy,
+    /// the default client is kept if the proxy url is invalid
+    pub fn with_upstream_proxy(mut self, upstream_proxy: &str) -> Box<Self> {
+        let options = ClientOptions { upstream_proxy: Some(upstream_proxy.to_string()), ..self.client_options.clone() };
+        if let Err(e) = self.rebuild_client(options) {
+            log::error!("invalid upstream proxy for {}, error: {}", self.host, e);
+        }
+        Box::new(self)
+    }
+
+    /// Reach the node by the given tls server name, as the proxy does, the requests are sent to the
+    /// addresses of the node host under that name, and accept any certificate of the node if asked to
+    pub fn with_tls(mut self, server_name: Option<&str>, accept_invalid_certs: bool) -> Box<Self> {
+        let server_name = match server_name {
+            Some(server_name) => match resolve_host(&self.host) {
+                Ok(addrs) => Some((server_name.to_string(), addrs)),
+                Err(e) => {
+                    log::error!("failed to resolve {} for its tls server name, error: {}", self.host, e);
+                    None
+                }
+            },
+            None => None,
+        };
+        let options = ClientOptions { server_name, accept_invalid_certs, ..self.client_options.clone() };
+        if let Err(e) = self.rebuild_client(options) {
+            log::error!("failed to build tls client for {}, error: {}", self.host, e);
+        }
+        Box::new(self)
+    }
+
+    // the client is kept with its settings when the new ones are invalid
+    fn rebuild_client(&mut self, options: ClientOptions) -> reqwest::Result<()> {
+        self.client = Arc::new(build_client(&options)?);
+        self.client_options = options;
+        Ok(())
+    }
+
+    /// Warn and fail when the tls certificate of the node expires within the given days
+    pub fn with_cert_expiry(mut self, warn_days: Option<u64>, min_days: Option<u64>) -> Box<Self> {
+        self.cert_expiry_warn_days = warn_days.map(|days| days as i64);
+        self.cert_expiry_min_days = min_days.map(|days| days as i64);
+        Box::new(self)
+    }
+
+    /// Report the days until the tls certificate of the node expires,
+    /// fail if it expires sooner than allowed, plain http responses are skipped
+    fn check_cert_expiry(&self, response: &reqwest::Response) -> Result<()> {
+        let certificate = response
+            .extensions()
+            .get::<reqwest::tls::TlsInfo>()
+            .and_then(|tls_info| tls_info.peer_certificate());
+        let not_after = match certificate.and_then(cert_not_after) {
+            Some(not_after) => not_after,
+            None => return Ok(()),
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        let days = cert_expiry_days(not_after, now);
+        let chain_name = self.chain_state.lock().unwrap().chain_name.clone();
+        set_cert_expiry_days_gauge(&chain_name, &self.host, days);
+
+        if self.cert_expiry_min_days.is_some_and(|min_days| days < min_days) {
+            log::error!("tls certificate of {} expires in {} days", self.host, days);
+            return ProxyError::CertificateExpiring.explain("during http healthcheck");
+        }
+        if self.cert_expiry_warn_days.is_some_and(|warn_days| days < warn_days) {
+            log::warn!("tls certificate of {} expires in {} days", self.host, days);
+        }
+        Ok(())
+    }
+
+    /// Update the chain state and metrics with the block number of this host
+    fn update_block_number(&self, block_number: u64) {
+        let mut state = self.chain_state.lock().unwrap();
+        state.update_block_number(&self.host, block_number);
+
+        // metrics
+        set_node_height_gauge(&state.chain_name, &self.host, block_number);
+    }
+
+    /// Probe the backend once, return the block height if one is reported
+    pub(crate) async fn probe(&self) -> Result<Option<u64>> {
+        let block_number = self.probe_primary().await?;
+
+        for probe in self.secondary_probes.iter() {
+            self.probe_secondary(probe).await?;
+        }
+
+        Ok(block_number)
+    }
+
+    /// The primary probe, the block height is read from its response
+    async fn probe_primary(&self) -> Result<Option<u64>> {
+        let built_body = self.request_body_builder.as_ref().map(|build| build());
+        let request_body = built_body.as_deref().or(self.request_body.as_deref());
+        let method = parse_method(&self.request_method, request_body)?;
+
+        let mut attempt = 0;
+        let response = loop {
+            match self.send_request(&self.request_url, method.clone(), request_body).await {
+                Ok(r) => break r,
+                Err(_e) if attempt < self.retries => {
+                    let backoff = retry_backoff(self.retry_backoff, attempt as u32);
+                    log::warn!(
+                        "failed to send request to {}, retry in {:?}, error: {}",
+                        self.host,
+                        backoff,
+                        _e
+                    );
+                    attempt += 1;
+                    tokio::time::sleep(backoff).await;
+                }
+                Err(_e) => {
+                    log::error!("failed to send request, error: {}", _e);
+                    return ProxyError::FailedToSendRequest.explain("reqwest error");
+                }
+            }
+        };
+
+        self.check_cert_expiry(&response)?;
+
+        // the status and the body must both pass, a valid body with an unexpected status is a failed check
+        let status = response.status().as_u16();
+        if !self.expected_status.is_empty() && !self.expected_status.iter().any(|range| range.contains(&status)) {
+            log::error!("{} answered unexpected status code: {}", self.host, status);
+            return ProxyError::UnexpectedStatus.explain("during http healthcheck");
+        }
+
+        // the block height is carried by a response header, skip the body parsing
+        if let Some(header_name) = self.height_header.as_ref() {
+            let block_number = response
+                .headers()
+                .get(header_name.as_str())
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_height);
+
+            return match block_number {
+                Some(block_number) => Ok(Some(block_number)),
+                None => {
+                    log::error!("failed to read height from response header: {}", header_name);
+                    ProxyError::InvalidHeightHeader.explain("validator error")
+                }
+            };
+        }
+
+        let response_body = response.bytes().await;
+        let response_body = match response_body {
+            Ok(b) => b,
+            Err(_e) => {
+                log::error!("failed to read response body, error: {}", _e);
+                return ProxyError::FailedToReadResponseBody.explain("reqwest error");
+            }
+        };
+
+        // the validated response is the one of the follow-up request
+        let response_body = match self.follow_up_request.as_ref() {
+            Some(follow_up_request) => {
+                let request_body = follow_up_request(&response_body)?;
+                self.probe_follow_up(method, &request_body).await?
+            }
+            None => response_body,
+        };
+
+        if let Some(validator) = self.validator.as_ref() {
+            let chain_state_result = validator(&response_body);
+            if chain_state_result.is_err() {
+                log::error!("failed to validate response body");
+
+                return ProxyError::FailedToValidateResponseBody.explain("validator error");
+            }
+
+            return Ok(Some(chain_state_result?));
+        }
+
+        Ok(None)
+    }
+
+    /// The follow-up request of the primary probe, return its response body
+    async fn probe_follow_up(&self, method: reqwest::Method, request_body: &[u8]) -> Result<bytes::Bytes> {
+        let response = match self.send_request(&self.request_url, method, Some(request_body)).await {
+            Ok(r) => r,
+            Err(_e) => {
+                log::error!("failed to send follow-up request to {}, error: {}", self.host, _e);
+                return ProxyError::FailedToSendRequest.explain("reqwest error");
+            }
+        };
+
+        match response.bytes().await {
+            Ok(b) => Ok(b),
+            Err(_e) => {
+                log::error!("failed to read follow-up response body, error: {}", _e);
+                ProxyError::FailedToReadResponseBody.explain("reqwest error")
+            }
+        }
+    }
+
+    /// A secondary probe, the validator error is returned as is
+    async fn probe_secondary(&self, probe: &SecondaryProbe) -> Result<()> {
+        let method = parse_method(&probe.request_method, probe.request_body.as_deref())?;
+
+        let response = match self.send_request(&probe.request_url, method, probe.request_body.as_deref()).await {
+            Ok(r) => r,
+            Err(_e) => {
+                log::error!("failed to send secondary probe request to {}, error: {}", self.host, _e);
+                return ProxyError::FailedToSendRequest.explain("reqwest error");
+            }
+        };
+
+        let validator = match probe.validator.as_ref() {
+            Some(validator) => validator,
+            None => {
+                // only check the status code
+                if !response.status().is_success() {
+                    log::error!(
+                        "secondary probe of {} failed, status code: {}",
+                        self.host,
+                        response.status().as_u16()
+                    );
+                    return ProxyError::UpstreamError.explain("reqwest error");
+                }
+                return Ok(());
+            }
+        };
+
+        let response_body = match response.bytes().await {
+            Ok(b) => b,
+            Err(_e) => {
+                log::error!("failed to read secondary probe response body, error: {}", _e);
+                return ProxyError::FailedToReadResponseBody.explain("reqwest error");
+            }
+        };
+
+        validator(&response_body)?;
+        Ok(())
+    }
+
+    /// Keep the probe result in the per host history of the chain state
+    fn record_probe(&self, result: &Result<Option<u64>>) {
+        let (success, height, reason) = match result {
+            Ok(height) => (true, *height, None),
+            Err(e) => (false, None, Some(e.etype().as_str().to_string())),
+        };
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+
+        let mut state = self.chain_state.lock().unwrap();
+        state.record_probe(&self.host, ProbeResult { timestamp, success, height, reason });
+    }
+
+    /// Send a health check request once
+    async fn send_request(
+        &self,
+        url: &str,
+        method: reqwest::Method,
+        body: Option<&[u8]>,
+    ) -> reqwest::Result<reqwest::Response> {
+        let mut headers = HeaderMap::new();
+        if self.grpc {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/grpc"));
+            headers.insert("te", HeaderValue::from_static("trailers"));
+        } else if self.cbor {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/cbor"));
+        } else {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        }
+
+        let request_builder = self.client
+            .request(method, self.server_name_url(url))
+            .headers(headers)
+            .timeout(self.interval.as_ref().map_or(self.request_timeout, |i| i.default_request_timeout()));
+
+        let request_builder = if let Some(body) = body {
+            request_builder.body(body.to_vec())
+        } else {
+            request_builder
+        };
+
+        request_builder.send().await
+    }
+
+    // a url of the node host is sent to its tls server name instead, it resolves to the node addresses
+    fn server_name_url(&self, url: &str) -> String {
+        let server_name = match self.client_options.server_name.as_ref() {
+            Some((server_name, _)) => server_name,
+            None => return url.to_string(),
+        };
+        let (mut parsed, node) = match (reqwest::Url::parse(url), reqwest::Url::parse(&self.host)) {
+            (Ok(parsed), Ok(node)) => (parsed, node),
+            _ => return url.to_string(),
+        };
+        if parsed.host_str() != node.host_str() || parsed.set_host(Some(server_name)).is_err() {
+            return url.to_string();
+        }
+        parsed.to_string()
+    }
+}
+
+/// Settings of a health check client
+#[derive(Clone, Debug, Default)]
+pub struct ClientOptions {
+    // http or socks5 proxy url every request is sent through
+    pub upstream_proxy: Option<String>,
+    // send the requests over http/2 only, for grpc
+    pub http2_only: bool,
+    // tls server name the node is reached by, with the addresses it resolves to
+    pub server_name: Option<(String, Vec<std::net::SocketAddr>)>,
+    // accept any certificate of the node
+    pub accept_invalid_certs: bool,
+}
+
+/// Build a health check client keeping the peer certificate of tls responses
+pub(crate) fn build_client(options: &ClientOptions) -> reqwest::Result<Client> {
+    let builder = Client::builder()
+        .tls_info(true)
+        .danger_accept_invalid_certs(options.accept_invalid_certs);
+    let builder = if options.http2_only { builder.http2_prior_knowledge() } else { builder };
+    let builder = match options.upstream_proxy.as_deref() {
+        Some(upstream_proxy) => builder.proxy(reqwest::Proxy::all(upstream_proxy)?),
+        None => builder,
+    };
+    let builder = match options.server_name.as_ref() {
+        Some((server_name, addrs)) => builder.resolve_to_addrs(server_name, addrs),
+        None => builder,
+    };
+    builder.build()
+}
+
+/// Addresses the host of a node url resolves to
+fn resolve_host(url: &str) -> std::io::Result<Vec<std::net::SocketAddr>> {
+    use std::net::ToSocketAddrs;
+
+    let url = reqwest::Url::parse(url).map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+    let host = url.host_str().unwrap_or_default().trim_start_matches('[').trim_end_matches(']');
+    let port = url.port_or_known_default().unwrap_or_default();
+    Ok((host, port).to_socket_addrs()?.collect())
+}
+
+/// Expiry of a DER encoded certificate in unix seconds
+pub(crate) fn cert_not_after(der: &[u8]) -> Option<i64> {
+    let certificate = X509::from_der(der).ok()?;
+    let epoch = Asn1Time::from_unix(0).ok()?;
+    let diff = epoch.diff(certificate.not_after()).ok()?;
+    Some(diff.days as i64 * 86400 + diff.secs as i64)
+}
+
+/// Whole days from now until the expiry, negative once expired
+pub(crate) fn cert_expiry_days(not_after: i64, now: i64) -> i64 {
+    (not_after - now).div_euclid(86400)
+}
+
+// health check method resolved from the request body
+pub(crate) const AUTO_METHOD: &str = "auto";
+
+/// Parse the http method of a health check request,
+/// the `auto` method is POST when the request has a body and GET otherwise
+pub(crate) fn parse_method(method: &str, body: Option<&[u8]>) -> Result<reqwest::Method> {
+    if method.eq_ignore_ascii_case(AUTO_METHOD) {
+        return Ok(match body {
+            Some(body) if !body.is_empty() => reqwest::Method::POST,
+            _ => reqwest::Method::GET,
+        });
+    }
+
+    match reqwest::Method::from_bytes(method.as_bytes()) {
+        Ok(m) => Ok(m),
+        Err(e) => {
+            log::error!("invalid request method: {}, error: {}", method, e);
+            ProxyError::InvalidRequestMethod.explain("reqwest error")
+        }
+    }
+}
+
+/// Compute the backoff before the given retry attempt (starting from 0).
+///
+/// The base backoff is doubled on every attempt, and half of it is randomized so that
+/// many nodes recovering at the same time don't probe in lockstep.
+pub(crate) fn retry_backoff(base: Duration, attempt: u32) -> Duration {
+    let backoff = base.saturating_mul(2u32.saturating_pow(attempt.min(16)));
+    let half = backoff / 2;
+    let jitter_ms = half.as_millis() as u64;
+    if jitter_ms == 0 {
+        return backoff;
+    }
+
+    half + Duration::from_millis(rand::thread_rng().gen_range(0..=jitter_ms))
+}
+
+#[async_trait]
+impl HealthCheck for ChainHealthCheck {
+    async fn check(&self, _target: &Backend) -> Result<()> {
+        // a node removed by a config reload fails without being probed, nor its height or metrics recorded
+        if self.chain_nodes.as_ref().is_some_and(|nodes| !nodes.contains(&self.host)) {
+            return ProxyError::NodeRemoved.explain("during http healthcheck");
+        }
+
+        let start = Instant::now();
+        let result = self.probe().await;
+        let chain_name = self.chain_state.lock().unwrap().chain_name.clone();
+        observe_health_check_latency(&chain_name, &self.host, start.elapsed());
+        self.record_probe(&result);
+
+        if let Some(block_number) = result? {
+            self.update_block_number(block_number);
+        }
+
+        Ok(())
+    }
+
+    fn health_threshold(&self, success: bool) -> usize {
+        if success {
+            self.consecutive_success
+        } else {
+            self.consecutive_failure
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use once_cell::sync::OnceCell;
+    use pingora::protocols::l4::socket::SocketAddr;
+    use crate::test_util::{mock_response, spawn_mock_handler, spawn_mock_server};
+
+    static INIT: OnceCell<()> = OnceCell::new();
+
+    // todo: move this to a common place
+    pub fn initialize_logger() {
+        INIT.get_or_init(|| {
+            if let Err(e) = env_logger::builder().is_test(true).try_init() {
+                eprintln!("Logger already initialized: {}", e);
+            }
+        });
+    }
+
+    // spawn a mock jsonrpc server answering the body of the first method found in the request
+    async fn spawn_mock_jsonrpc_server(bodies: Vec<(&'static str, &'static str)>) -> std::net::SocketAddr {
+        spawn_mock_handler(move |request| {
+            match bodies.iter().find(|(method, _)| request.contains(&format!("\"{method}\""))) {
+                Some((_, body)) => mock_response("200 OK", &[], body),
+                None => mock_response("404 Not Found", &[], ""),
+            }
+        })
+        .await
+    }
+
+    #[tokio::test]
+    async fn test_https_check_get() {
+        initialize_logger();
+
+        // create a health check that connects to httpbin.org over HTTPS
+        let chain_health_check = ChainHealthCheck::new(
+            "https://httpbin.org",
+            "/get",
+            "GET",
+            Arc::new(Mutex::new(ChainState::new("test"))),
+        );
+        let backend = Backend {
+            addr: SocketAddr::Inet("23.23.165.157:443".parse().unwrap()),
+            weight: 1,
+        };
+
+        assert!(chain_health_check.check(&backend).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_removed_node_check() {
+        let chain_state = Arc::new(Mutex::new(ChainState::new("test")));
+        let chain_nodes = ChainNodes::new(["http://127.0.0.1:1".to_string()].into());
+        let chain_health_check = ChainHealthCheck::new("http://127.0.0.1:1", "/", "POST", chain_state.clone())
+            .with_chain_nodes(chain_nodes.clone());
+        let backend = Backend {
+            addr: SocketAddr::Inet("127.0.0.1:1".parse().unwrap()),
+            weight: 1,
+        };
+
+        // the unreachable node is probed while in the chain
+        let e = chain_health_check.check(&backend).await.unwrap_err();
+        assert_ne!(ProxyError::from_error(&e), Some(ProxyError::NodeRemoved));
+        assert_eq!(chain_state.lock().unwrap().probe_history.len(), 1);
+
+        // once removed it is no longer probed nor recorded
+        chain_nodes.set(Default::default());
+        let e = chain_health_check.check(&backend).await.unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::NodeRemoved));
+        let probes = chain_state.lock().unwrap().probe_history.get("http://127.0.0.1:1").unwrap().results().len();
+        assert_eq!(probes, 1);
+    }
+
+    #[tokio::test]
+    async fn test_https_check_post() {
+        initialize_logger();
+
+        // create a health check that connects to httpbin.org over HTTPS
+        let chain_health_check = ChainHealthCheck::new(
+            "https://httpbin.org",
+            "/post",
+            "POST",
+            Arc::new(Mutex::new(ChainState::new("test"))),
+        );
+        let http_check = chain_health_check.with_request_body(
+            r#"
+               {
+                    "key":"value"
+               }
+               "#
+            .as_bytes()
+            .to_vec(),
+        );
+        let backend = Backend {
+            addr: SocketAddr::Inet("23.23.165.157:443".parse().unwrap()),
+            weight: 1,
+        };
+
+        assert!(http_check.check(&backend).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_optimism_check() {
+        initialize_logger();
+        log::info!("running optimism check");
+        let http_check = ChainHealthCheck::new(
+            "https://practical-green-butterfly.optimism.quiknode.pro",
+            "/d02f8d49bde8ccbbcec3c9a8962646db998ade83",
+            "POST",
+            Arc::new(Mutex::new(ChainState::new("test"))),
+        );
+        let http_check = http_check.with_response_body_validator(Arc::new(eth_validator));
+        let http_check = http_check.with_request_body(
+            r#"
+                {
+                    "jsonrpc":"2.0",
+                    "method":"eth_blockNumber",
+                    "id":1
+               }
+               "#
+            .as_bytes()
+            .to_vec(),
+        );
+
+        let backend = Backend {
+            addr: SocketAddr::Inet("158.178.243.130:443".parse().unwrap()),
+            weight: 1,
+        };
+
+        assert!(http_check.check(&backend).await.is_ok());
+    }
+
+    #[test]
+    fn test_starknet_validator() {
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":123}"#;
+        assert_eq!(starknet_validator(body).unwrap(), 123);
+    }
+
+    #[test]
+    fn test_starknet_feeder_validator() {
+        let body = br#"{"block_hash":"0x1","block_number":123,"status":"ACCEPTED_ON_L2","transactions":[]}"#;
+        assert_eq!(starknet_feeder_validator(body).unwrap(), 123);
+
+        let body = br#"{"block_hash":"0x1","status":"ACCEPTED_ON_L2"}"#;
+        assert!(starknet_feeder_validator(body).is_err());
+    }
+
+    #[test]
+    fn test_parse_method() {
+        let body = br#"{"jsonrpc":"2.0","method":"eth_blockNumber","id":1}"#;
+        assert_eq!(parse_method("auto", Some(body)).unwrap(), reqwest::Method::POST);
+        assert_eq!(parse_method("AUTO", Some(body)).unwrap(), reqwest::Method::POST);
+        assert_eq!(parse_method("auto", Some(b"")).unwrap(), reqwest::Method::GET);
+        assert_eq!(parse_method("auto", None).unwrap(), reqwest::Method::GET);
+
+        // a configured method is used as is
+        assert_eq!(parse_method("GET", Some(body)).unwrap(), reqwest::Method::GET);
+        assert!(parse_method("GE T", None).is_err());
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let base = Duration::from_millis(100);
+        for attempt in 0..4 {
+            let full = base * 2u32.pow(attempt);
+            let backoff = retry_backoff(base, attempt);
+            assert!(backoff >= full / 2);
+            assert!(backoff <= full);
+        }
+
+        assert_eq!(retry_backoff(Duration::from_millis(0), 3), Duration::from_millis(0));
+    }
+
+    #[tokio::test]
+    async fn test_check_retries() {
+        initialize_logger();
+
+        // a mock server that accepts connections and closes them straight away
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let attempts = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = attempts.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                drop(stream);
+            }
+        });
+
+        let http_check = ChainHealthCheck::new(
+            &format!("http://{addr}"),
+            "/",
+            "POST",
+            Arc::new(Mutex::new(ChainState::new("test"))),
+        );
+        let http_check = http_check.with_retries(2, Duration::from_millis(1));
+        let backend = Backend {
+            addr: SocketAddr::Inet(addr),
+            weight: 1,
+        };
+
+        assert!(http_check.check(&backend).await.is_err());
+        assert_eq!(attempts.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_min_peer_count_check() {
+        initialize_logger();
+
+        // the mock server answers 0x5 to both eth_blockNumber and net_peerCount
+        let body = r#"{"jsonrpc":"2.0","id":1,"result":"0x5"}"#;
+        let addr = spawn_mock_server(mock_response("200 OK", &[], body)).await;
+        let backend = Backend {
+            addr: SocketAddr::Inet(addr),
+            weight: 1,
+        };
+
+        // sufficient peers
+        let chain_state = Arc::new(Mutex::new(ChainState::new("test")));
+        let host = format!("http://{addr}");
+        let http_check = ChainHealthCheck::new(&host, "/", "POST", chain_state.clone())
+            .with_response_body_validator(Arc::new(eth_validator));
+        let http_check = http_check.with_min_peer_count(3);
+        assert!(http_check.check(&backend).await.is_ok());
+        assert_eq!(chain_state.lock().unwrap().get_block_numbers().get(&host), Some(&5));
+
+        // insufficient peers, the block number is not used
+        let chain_state = Arc::new(Mutex::new(ChainState::new("test")));
+        let http_check = ChainHealthCheck::new(&host, "/", "POST", chain_state.clone())
+            .with_response_body_validator(Arc::new(eth_validator));
+        let http_check = http_check.with_min_peer_count(10);
+        let err = http_check.check(&backend).await.unwrap_err();
+        assert_eq!(ProxyError::from_error(&err), Some(ProxyError::InsufficientPeers));
+        assert!(chain_state.lock().unwrap().get_block_numbers().get(&host).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_gas_price_check() {
+        initialize_logger();
+
+        // the mock servers answer the same quantity to both eth_blockNumber and eth_gasPrice
+        let body = r#"{"jsonrpc":"2.0","id":1,"result":"0x3b9aca00"}"#;
+        let addr = spawn_mock_server(mock_response("200 OK", &[], body)).await;
+        let backend = Backend {
+            addr: SocketAddr::Inet(addr),
+            weight: 1,
+        };
+
+        // a normal gas price
+        let chain_state = Arc::new(Mutex::new(ChainState::new("gnosis")));
+        let host = format!("http://{addr}");
+        let http_check = ChainHealthCheck::new(&host, "/", "POST", chain_state.clone())
+            .with_response_body_validator(Arc::new(eth_validator))
+            .with_gas_price_check();
+        assert!(http_check.check(&backend).await.is_ok());
+        assert_eq!(chain_state.lock().unwrap().get_block_numbers().get(&host), Some(&1000000000));
+
+        // a zero gas price, the node is unhealthy
+        let body = r#"{"jsonrpc":"2.0","id":1,"result":"0x0"}"#;
+        let addr = spawn_mock_server(mock_response("200 OK", &[], body)).await;
+        let backend = Backend {
+            addr: SocketAddr::Inet(addr),
+            weight: 1,
+        };
+        let chain_state = Arc::new(Mutex::new(ChainState::new("gnosis")));
+        let host = format!("http://{addr}");
+        let http_check = ChainHealthCheck::new(&host, "/", "POST", chain_state.clone())
+            .with_response_body_validator(Arc::new(eth_validator))
+            .with_gas_price_check();
+        let err = http_check.check(&backend).await.unwrap_err();
+        assert_eq!(ProxyError::from_error(&err), Some(ProxyError::FailedToValidateResponseBody));
+
+        assert!(gas_price_validator(br#"{"jsonrpc":"2.0","id":1,"result":"0x1"}"#).is_ok());
+        assert!(gas_price_validator(br#"{"jsonrpc":"2.0","id":1,"result":"0x0"}"#).is_err());
+    }
+
+    #[test]
+    fn test_eth_syncing_validator() {
+        let validator = eth_syncing_validator(64);
+
+        // a synced node
+        assert_eq!(validator(br#"{"jsonrpc":"2.0","id":1,"result":false}"#).unwrap(), 0);
+
+        // a besu or geth node catching up, within and over the gap
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":{"startingBlock":"0x0","currentBlock":"0x1312d00","highestBlock":"0x1312d20"}}"#;
+        assert_eq!(validator(body).unwrap(), 32);
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":{"startingBlock":"0x0","currentBlock":"0x1312c00","highestBlock":"0x1312d20"}}"#;
+        let e = validator(body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::NodeSyncing));
+
+        // a highest block not known yet is no gap
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":{"currentBlock":"0x10","highestBlock":"0x0"}}"#;
+        assert_eq!(validator(body).unwrap(), 0);
+
+        // malformed blocks and errors
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":{"currentBlock":"0xzz","highestBlock":"0x10"}}"#;
+        let e = validator(body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InvalidBlockNumber));
+        let body = br#"{"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"Method not found"}}"#;
+        let e = validator(body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InvalidJson));
+    }
+
+    #[test]
+    fn test_verification_gap_validator() {
+        init_chain_checker();
+        assert!(get_chain_checker("zkevm").is_some());
+        let request: serde_json::Value =
+            serde_json::from_slice(&verification_gap_request("zkevm_verifiedBatchNumber", &[])).unwrap();
+        assert_eq!(request[1]["method"], "zkevm_verifiedBatchNumber");
+        let validator = verification_gap_validator(100);
+
+        // verified within the gap, the responses in any order
+        let body = br#"[{"jsonrpc":"2.0","id":2,"result":"0x3e8"},{"jsonrpc":"2.0","id":1,"result":"0x41a"}]"#;
+        assert_eq!(validator(body).unwrap(), 1000);
+
+        // a finalized block object
+        let body = br#"[{"jsonrpc":"2.0","id":1,"result":"0x41a"},{"jsonrpc":"2.0","id":2,"result":{"number":"0x3e8"}}]"#;
+        assert_eq!(validator(body).unwrap(), 1000);
+
+        // verification lagging past the gap
+        let body = br#"[{"jsonrpc":"2.0","id":1,"result":"0x7d0"},{"jsonrpc":"2.0","id":2,"result":"0x3e8"}]"#;
+        let e = validator(body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::VerificationLagging));
+
+        // a node not supporting the method, or not batching
+        let body = br#"[{"jsonrpc":"2.0","id":1,"result":"0x41a"},{"jsonrpc":"2.0","id":2,"error":{"code":-32601}}]"#;
+        let e = validator(body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InvalidBlockNumber));
+        let e = validator(br#"{"jsonrpc":"2.0","id":1,"result":"0x41a"}"#).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InvalidJson));
+    }
 
-                return Error::e_explain(
-                    Custom("failed to validate response body"),
-                    "validator error",
-                );
-            }
+    #[tokio::test]
+    async fn test_expected_spec_name() {
+        initialize_logger();
+
+        // the mock server answers the same body to the primary probe and state_getRuntimeVersion
+        let body = r#"{"jsonrpc":"2.0","id":1,"result":{"specName":"moonbeam","specVersion":3000,"number":"0x10"}}"#;
+        let addr = spawn_mock_server(mock_response("200 OK", &[], body)).await;
+        let backend = Backend {
+            addr: SocketAddr::Inet(addr),
+            weight: 1,
+        };
+        let host = format!("http://{addr}");
+
+        // matching spec name
+        let chain_state = Arc::new(Mutex::new(ChainState::new("test")));
+        let http_check = ChainHealthCheck::new(&host, "/", "POST", chain_state.clone())
+            .with_response_body_validator(json_pointer_validator("/result/number", ResultValueType::Hex));
+        let http_check = http_check.with_expected_spec_name("moonbeam");
+        assert!(http_check.check(&backend).await.is_ok());
+        assert_eq!(chain_state.lock().unwrap().get_block_numbers().get(&host), Some(&16));
+
+        // a different runtime, the block number is not used
+        let chain_state = Arc::new(Mutex::new(ChainState::new("test")));
+        let http_check = ChainHealthCheck::new(&host, "/", "POST", chain_state.clone())
+            .with_response_body_validator(json_pointer_validator("/result/number", ResultValueType::Hex));
+        let http_check = http_check.with_expected_spec_name("astar");
+        let err = http_check.check(&backend).await.unwrap_err();
+        assert_eq!(ProxyError::from_error(&err), Some(ProxyError::FailedToValidateResponseBody));
+        assert!(chain_state.lock().unwrap().get_block_numbers().get(&host).is_none());
+    }
+
+    #[test]
+    fn test_substrate_sync_state_validator() {
+        init_chain_checker();
+        let validator = get_chain_checker("substrate").unwrap().validator;
+
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":{"startingBlock":0,"currentBlock":18421337,"highestBlock":18421337}}"#;
+        assert_eq!(validator(body).unwrap(), 18421337);
+
+        let body = br#"{"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"Method not found"}}"#;
+        assert!(validator(body).is_err());
+    }
+
+    #[test]
+    fn test_system_health_validator() {
+        let validator = system_health_validator(3);
+
+        // a healthy node
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":{"peers":25,"isSyncing":false,"shouldHavePeers":true}}"#;
+        assert_eq!(validator(body).unwrap(), 25);
+
+        // an isolated node
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":{"peers":0,"isSyncing":false,"shouldHavePeers":true}}"#;
+        let e = validator(body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InsufficientPeers));
+
+        // a node catching up
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":{"peers":25,"isSyncing":true,"shouldHavePeers":true}}"#;
+        let e = validator(body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::NodeSyncing));
+
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":null}"#;
+        let e = validator(body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InvalidJson));
+    }
+
+    #[tokio::test]
+    async fn test_system_health_check() {
+        initialize_logger();
+
+        // both calls are stubbed, the height comes from the sync state
+        let sync_state = r#"{"jsonrpc":"2.0","id":1,"result":{"startingBlock":0,"currentBlock":4096,"highestBlock":4096}}"#;
+        let addr = spawn_mock_jsonrpc_server(vec![
+            ("system_syncState", sync_state),
+            ("system_health", r#"{"jsonrpc":"2.0","id":1,"result":{"peers":12,"isSyncing":false}}"#),
+        ])
+        .await;
+        let backend = Backend {
+            addr: SocketAddr::Inet(addr),
+            weight: 1,
+        };
+        let chain_state = Arc::new(Mutex::new(ChainState::new("vara")));
+        let host = format!("http://{addr}");
+        let http_check = ChainHealthCheck::new(&host, "/", "POST", chain_state.clone())
+            .with_response_body_validator(Arc::new(substrate_sync_state_validator))
+            .with_request_body(br#"{"jsonrpc":"2.0","method":"system_syncState","params":[],"id":1}"#.to_vec())
+            .with_system_health(3);
+        assert!(http_check.check(&backend).await.is_ok());
+        assert_eq!(chain_state.lock().unwrap().get_block_numbers().get(&host), Some(&4096));
+
+        // a node without peers is unhealthy even though its sync state looks fine
+        let addr = spawn_mock_jsonrpc_server(vec![
+            ("system_syncState", sync_state),
+            ("system_health", r#"{"jsonrpc":"2.0","id":1,"result":{"peers":0,"isSyncing":false}}"#),
+        ])
+        .await;
+        let backend = Backend {
+            addr: SocketAddr::Inet(addr),
+            weight: 1,
+        };
+        let chain_state = Arc::new(Mutex::new(ChainState::new("vara")));
+        let host = format!("http://{addr}");
+        let http_check = ChainHealthCheck::new(&host, "/", "POST", chain_state.clone())
+            .with_response_body_validator(Arc::new(substrate_sync_state_validator))
+            .with_request_body(br#"{"jsonrpc":"2.0","method":"system_syncState","params":[],"id":1}"#.to_vec())
+            .with_system_health(3);
+        let err = http_check.check(&backend).await.unwrap_err();
+        assert_eq!(ProxyError::from_error(&err), Some(ProxyError::InsufficientPeers));
+        assert!(chain_state.lock().unwrap().get_block_numbers().get(&host).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_secondary_probes() {
+        initialize_logger();
+
+        let primary = spawn_mock_server(mock_response("200 OK", &[], r#"{"jsonrpc":"2.0","id":1,"result":"0x10"}"#)).await;
+        let synced = spawn_mock_server(mock_response("200 OK", &[], r#"{"jsonrpc":"2.0","id":1,"result":false}"#)).await;
+        let syncing = spawn_mock_server(mock_response(
+            "200 OK",
+            &[],
+            r#"{"jsonrpc":"2.0","id":1,"result":{"currentBlock":"0x10","highestBlock":"0x20"}}"#,
+        )).await;
+        let backend = Backend {
+            addr: SocketAddr::Inet(primary),
+            weight: 1,
+        };
+        let host = format!("http://{primary}");
+        let eth_syncing = |addr: std::net::SocketAddr| {
+            SecondaryProbe::new(&format!("http://{addr}"), "/", "POST")
+                .with_request_body(br#"{"jsonrpc":"2.0","method":"eth_syncing","params":[],"id":1}"#.to_vec())
+                .with_response_body_validator(json_pointer_expect_validator("/result", serde_json::Value::Bool(false)))
+        };
+
+        // all probes pass, the height comes from the primary probe
+        let chain_state = Arc::new(Mutex::new(ChainState::new("test")));
+        let http_check = ChainHealthCheck::new(&host, "/", "POST", chain_state.clone())
+            .with_response_body_validator(Arc::new(eth_validator))
+            .with_secondary_probe(SecondaryProbe::new(&format!("http://{synced}"), "/status", "GET"))
+            .with_secondary_probe(eth_syncing(synced));
+        assert!(http_check.check(&backend).await.is_ok());
+        assert_eq!(chain_state.lock().unwrap().get_block_numbers().get(&host), Some(&16));
+
+        // the second probe fails, the node is unhealthy and the height is not used
+        let chain_state = Arc::new(Mutex::new(ChainState::new("test")));
+        let http_check = ChainHealthCheck::new(&host, "/", "POST", chain_state.clone())
+            .with_response_body_validator(Arc::new(eth_validator))
+            .with_secondary_probe(SecondaryProbe::new(&format!("http://{synced}"), "/status", "GET"))
+            .with_secondary_probe(eth_syncing(syncing));
+        let err = http_check.check(&backend).await.unwrap_err();
+        assert_eq!(ProxyError::from_error(&err), Some(ProxyError::FailedToValidateResponseBody));
+        assert!(chain_state.lock().unwrap().get_block_numbers().get(&host).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_upstream_proxy() {
+        initialize_logger();
+
+        // the proxy answers for a node which can't be reached directly
+        let proxy = spawn_mock_server(mock_response("200 OK", &[], r#"{"jsonrpc":"2.0","id":1,"result":"0x20"}"#)).await;
+        let host = "http://node.invalid:8545";
+        let backend = Backend {
+            addr: SocketAddr::Inet("127.0.0.1:8545".parse().unwrap()),
+            weight: 1,
+        };
+
+        let chain_state = Arc::new(Mutex::new(ChainState::new("test")));
+        let http_check = ChainHealthCheck::new(host, "/", "POST", chain_state.clone())
+            .with_response_body_validator(Arc::new(eth_validator))
+            .with_upstream_proxy(&format!("http://{proxy}"));
+        assert!(http_check.check(&backend).await.is_ok());
+        assert_eq!(chain_state.lock().unwrap().get_block_numbers().get(host), Some(&32));
+
+        // without the proxy the node is unreachable
+        let http_check = ChainHealthCheck::new(host, "/", "POST", chain_state.clone())
+            .with_response_body_validator(Arc::new(eth_validator));
+        assert!(http_check.check(&backend).await.is_err());
 
-            // update the chain state
-            let chain_state_result = chain_state_result?;
+        let options = |upstream_proxy: &str| ClientOptions {
+            upstream_proxy: Some(upstream_proxy.to_string()),
+            ..Default::default()
+        };
+        assert!(build_client(&options("socks5://127.0.0.1:1080")).is_ok());
+        assert!(build_client(&options("ftp://127.0.0.1:21")).is_err());
+    }
 
-            {
-                let mut state = self.chain_state.lock().unwrap();
-                state.update_block_number(&self.host, chain_state_result);
+    #[tokio::test]
+    async fn test_tls_server_name() {
+        initialize_logger();
 
-                // metrics
-                set_node_height_gauge(&state.chain_name, &self.host, chain_state_result);
+        // the node is reached at its address under the server name
+        let node = spawn_mock_handler(|request: &str| {
+            match request.to_ascii_lowercase().contains("host: eth.example.invalid:") {
+                true => mock_response("200 OK", &[], r#"{"jsonrpc":"2.0","id":1,"result":"0x20"}"#),
+                false => mock_response("404 Not Found", &[], ""),
             }
+        }).await;
+        let host = format!("http://{node}");
+        let backend = Backend {
+            addr: SocketAddr::Inet(node.to_string().parse().unwrap()),
+            weight: 1,
+        };
+
+        let chain_state = Arc::new(Mutex::new(ChainState::new("test")));
+        let http_check = ChainHealthCheck::new(&host, "/", "POST", chain_state.clone())
+            .with_response_body_validator(Arc::new(eth_validator))
+            .with_tls(Some("eth.example.invalid"), false);
+        assert!(http_check.check(&backend).await.is_ok());
+        assert_eq!(chain_state.lock().unwrap().get_block_numbers().get(&host), Some(&32));
+        assert_eq!(http_check.server_name_url(&format!("{host}/status")),
+                   format!("http://eth.example.invalid:{}/status", node.port()));
+        assert_eq!(http_check.server_name_url("http://10.0.0.2:8545/"), "http://10.0.0.2:8545/");
+
+        // the address host is sent without it
+        let http_check = ChainHealthCheck::new(&host, "/", "POST", chain_state.clone())
+            .with_response_body_validator(Arc::new(eth_validator))
+            .with_tls(None, false);
+        assert!(http_check.check(&backend).await.is_err());
+    }
+
+    #[test]
+    fn test_cert_expiry_days() {
+        use openssl::ec::{EcGroup, EcKey};
+        use openssl::hash::MessageDigest;
+        use openssl::nid::Nid;
+        use openssl::pkey::PKey;
+
+        // a self-signed certificate valid until 2030-01-01T00:00:00Z
+        let not_after = 1_893_456_000;
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut builder = X509::builder().unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::from_unix(1_700_000_000).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::from_unix(not_after).unwrap()).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        let der = builder.build().to_der().unwrap();
+
+        assert_eq!(cert_not_after(&der), Some(not_after));
+        assert!(cert_not_after(b"not a certificate").is_none());
+
+        // 30 days and a half before the expiry
+        let now = not_after - 30 * 86400 - 43200;
+        assert_eq!(cert_expiry_days(not_after, now), 30);
+        assert_eq!(cert_expiry_days(not_after, not_after - 1), 0);
+        // expired since yesterday
+        assert_eq!(cert_expiry_days(not_after, not_after + 86400), -1);
+    }
+
+    #[test]
+    fn test_celestia_da_validator() {
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":{"header":{"version":{"block":"11","app":"2"},"chain_id":"celestia","height":"2456789","time":"2024-08-01T10:00:00Z"},"commit":{},"validator_set":{}}}"#;
+        assert_eq!(celestia_da_validator(body).unwrap(), 2456789);
+
+        let body = br#"{"result":{"header":{"height":"abc"}}}"#;
+        assert!(celestia_da_validator(body).is_err());
+
+        // the consensus node response is not a da header
+        let body = br#"{"block":{"header":{"height":"2456789"}}}"#;
+        assert!(celestia_da_validator(body).is_err());
+        assert_eq!(cosmos_validator(body).unwrap(), 2456789);
+    }
+
+    #[test]
+    fn test_float_heights() {
+        // heights serialized as floats, in scientific notation, as json numbers or hex
+        for (height, expected) in [
+            (r#""2456789""#, 2456789),
+            (r#""2456789.0""#, 2456789),
+            (r#""2.456789e6""#, 2456789),
+            (r#"2.456789e6"#, 2456789),
+            (r#"2456789"#, 2456789),
+            (r#""0x257cd5""#, 2456789),
+        ] {
+            let body = format!(r#"{{"block":{{"header":{{"height":{height}}}}}}}"#);
+            assert_eq!(cosmos_validator(body.as_bytes()).unwrap(), expected, "height {height}");
         }
 
-        Ok(())
+        let body = br#"{"block":{"header":{"height":"-2.4e6"}}}"#;
+        let err = cosmos_validator(body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&err), Some(ProxyError::InvalidBlockNumber));
+
+        let body = br#"{"data":{"header":{"message":{"slot":9.876543e6}}}}"#;
+        assert_eq!(beacon_validator(body).unwrap(), 9876543);
+        let body = br#"{"block_height":"2.15738459E8","ledger_timestamp":"1722500000000000"}"#;
+        assert_eq!(aptos_validator(body).unwrap(), 215738459);
+
+        let body = br#"{"result":{"height":1.5e3,"decimal":"1.5e3"}}"#;
+        assert_eq!(read_json_pointer(body, "/result/height", ResultValueType::Number).unwrap(), 1500);
+        assert_eq!(read_json_pointer(body, "/result/decimal", ResultValueType::Decimal).unwrap(), 1500);
     }
 
-    fn health_threshold(&self, success: bool) -> usize {
-        if success {
-            self.consecutive_success
-        } else {
-            self.consecutive_failure
+    #[test]
+    fn test_eth_block_by_number_request() {
+        for (block_tag, name) in [
+            (BlockTag::Latest, "latest"),
+            (BlockTag::Safe, "safe"),
+            (BlockTag::Finalized, "finalized"),
+        ] {
+            let body: serde_json::Value = serde_json::from_slice(&eth_block_by_number_request(block_tag)).unwrap();
+            assert_eq!(body["method"], "eth_getBlockByNumber");
+            assert_eq!(body["params"], serde_json::json!([name, false]));
         }
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use once_cell::sync::OnceCell;
-    use pingora::protocols::l4::socket::SocketAddr;
+    #[test]
+    fn test_eth_block_by_number_validator() {
+        // latest, safe and finalized heads of the same chain
+        for (number, expected) in [("0x1406f40", 21000000), ("0x1406f20", 20999968), ("0x1406f00", 20999936)] {
+            let body = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"result":{{"number":"{number}","hash":"0xabc","parentHash":"0xdef","transactions":[]}}}}"#
+            );
+            assert_eq!(eth_block_by_number_validator(body.as_bytes()).unwrap(), expected);
+        }
 
-    static INIT: OnceCell<()> = OnceCell::new();
+        // the tag is not supported by the node
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":null}"#;
+        assert!(eth_block_by_number_validator(body).is_err());
 
-    // todo: move this to a common place
-    pub fn initialize_logger() {
-        INIT.get_or_init(|| {
-            if let Err(e) = env_logger::builder().is_test(true).try_init() {
-                eprintln!("Logger already initialized: {}", e);
-            }
-        });
+        let body = br#"{"jsonrpc":"2.0","id":1,"error":{"code":-32602,"message":"invalid block tag"}}"#;
+        assert!(eth_block_by_number_validator(body).is_err());
+    }
+
+    #[test]
+    fn test_beacon_validator() {
+        let body = br#"{"execution_optimistic":false,"finalized":false,"data":{"root":"0x9a2fefd2fdb57f74993c7780ea5b9030d2897b615b89f808011ca5aebed54eaf","canonical":true,"header":{"message":{"slot":"9876543","proposer_index":"1","parent_root":"0x1a2b","state_root":"0x3c4d","body_root":"0x5e6f"},"signature":"0x1b66ac1fb663c9bc59509846d6ec05345bd908eda73e670af888da41af171505"}}}"#;
+        assert_eq!(beacon_validator(body).unwrap(), 9876543);
+
+        // missing slot
+        let body = br#"{"data":{"root":"0x9a2f","canonical":true,"header":{"message":{"proposer_index":"1"}}}}"#;
+        assert!(beacon_validator(body).is_err());
+
+        let body = br#"{"data":{"header":{"message":{"slot":"not a number"}}}}"#;
+        assert!(beacon_validator(body).is_err());
+    }
+
+    #[test]
+    fn test_aptos_validator() {
+        let body = br#"{"chain_id":1,"epoch":"8542","ledger_version":"1040862451","oldest_ledger_version":"0","ledger_timestamp":"1722500000000000","node_role":"full_node","oldest_block_height":"0","block_height":"215738459","git_hash":"abc"}"#;
+        assert_eq!(aptos_validator(body).unwrap(), 215738459);
+
+        let body = br#"{"ledger_version":"1040862451","ledger_timestamp":"1722500000000000"}"#;
+        assert!(aptos_validator(body).is_err());
+    }
+
+    #[test]
+    fn test_aptos_ledger_age() {
+        let body = br#"{"ledger_version":"1040862451","ledger_timestamp":"1722500000000000","block_height":"215738459"}"#;
+        let ledger_time = UNIX_EPOCH + Duration::from_secs(1722500000);
+        let max_age = Some(Duration::from_secs(30));
+
+        // fresh ledger
+        let now = ledger_time + Duration::from_secs(2);
+        assert_eq!(read_aptos_ledger(body, max_age, now).unwrap(), 215738459);
+
+        // stale ledger, the version is still reported
+        let now = ledger_time + Duration::from_secs(120);
+        let e = read_aptos_ledger(body, max_age, now).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::StaleLedger));
+
+        // the staleness is not checked without a threshold
+        assert!(read_aptos_ledger(body, None, now).is_ok());
+    }
+
+    #[test]
+    fn test_tron_block_age() {
+        let body = br#"{"blockID":"0000000003b1f2a6","block_header":{"raw_data":{"number":61993638,"txTrieRoot":"00","witness_address":"41","parentHash":"00","version":30,"timestamp":1722500000000}}}"#;
+        let block_time = UNIX_EPOCH + Duration::from_millis(1722500000000);
+        let max_age = Some(Duration::from_secs(30));
+
+        init_chain_checker();
+        let validator = get_chain_checker("tron_http").unwrap().validator;
+        assert_eq!(validator(body).unwrap(), 61993638);
+
+        // fresh block
+        let now = block_time + Duration::from_secs(3);
+        assert_eq!(read_tron_block(body, max_age, now).unwrap(), 61993638);
+
+        // stale block, the height is still reported
+        let now = block_time + Duration::from_secs(120);
+        let e = read_tron_block(body, max_age, now).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::StaleLedger));
+        assert!(read_tron_block(body, None, now).is_ok());
+
+        // the staleness can't be told without a timestamp
+        let body = br#"{"block_header":{"raw_data":{"number":61993638}}}"#;
+        assert!(read_tron_block(body, None, now).is_ok());
+        let e = read_tron_block(body, max_age, now).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InvalidBlockNumber));
+        assert!(validator(br#"{"Error":"class java.lang.NullPointerException"}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_rfc3339() {
+        assert_eq!(parse_rfc3339("1970-01-01T00:00:00Z"), Some(UNIX_EPOCH));
+        let expected = UNIX_EPOCH + Duration::new(1714564800, 123456789);
+        assert_eq!(parse_rfc3339("2024-05-01T12:00:00.123456789Z"), Some(expected));
+        assert_eq!(parse_rfc3339("2024-05-01T12:00:00.1234567891Z"), Some(expected));
+        assert_eq!(parse_rfc3339("2024-05-01T14:00:00.123456789+02:00"), Some(expected));
+        assert_eq!(parse_rfc3339("2024-02-29T00:00:00Z"), Some(UNIX_EPOCH + Duration::from_secs(1709164800)));
+
+        for value in ["", "2024-05-01", "2024-13-01T00:00:00Z", "2024-05-01T12:00Z", "2024-05-01T12:00:00.x1Z"] {
+            assert_eq!(parse_rfc3339(value), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn test_osmosis_block_age() {
+        let body = br#"{"jsonrpc":"2.0","id":-1,"result":{"node_info":{"network":"osmosis-1"},"sync_info":{"latest_block_height":"21034567","latest_block_time":"2024-05-01T12:00:00.123456789Z","catching_up":false}}}"#;
+        let block_time = parse_rfc3339("2024-05-01T12:00:00.123456789Z").unwrap();
+        // osmosis chains check the block time by default
+        let max_age = Some(Duration::from_secs(crate::config::default_max_ledger_age_secs("osmosis").unwrap()));
+
+        init_chain_checker();
+        let validator = get_chain_checker("osmosis").unwrap().validator;
+        assert_eq!(validator(body).unwrap(), 21034567);
+
+        // fresh block
+        let now = block_time + Duration::from_secs(5);
+        assert_eq!(read_cosmos_status(body, max_age, now).unwrap(), 21034567);
+
+        // stale block, the height is still reported
+        let now = block_time + Duration::from_secs(600);
+        let e = read_cosmos_status(body, max_age, now).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::StaleLedger));
+        assert!(read_cosmos_status(body, None, now).is_ok());
+
+        // the staleness can't be told without a block time
+        let body = br#"{"jsonrpc":"2.0","id":-1,"result":{"sync_info":{"latest_block_height":"21034567"}}}"#;
+        assert!(read_cosmos_status(body, None, now).is_ok());
+        let e = read_cosmos_status(body, max_age, now).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InvalidBlockNumber));
+        assert!(validator(br#"{"jsonrpc":"2.0","id":-1,"error":{"code":-32603}}"#).is_err());
+    }
+
+    #[test]
+    fn test_sui_validator() {
+        init_chain_checker();
+        let validator = get_chain_checker("sui").unwrap().validator;
+
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":"48251023"}"#;
+        assert_eq!(validator(body).unwrap(), 48251023);
+
+        let body = br#"{"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"Method not found"}}"#;
+        assert!(validator(body).is_err());
+    }
+
+    #[test]
+    fn test_sui_checkpoint_age() {
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":{"data":[{"epoch":"470","sequenceNumber":"48251023","digest":"5Zd1","timestampMs":"1722500000123","networkTotalTransactions":"2706000000"}],"nextCursor":"48251022","hasNextPage":true}}"#;
+        let checkpoint_time = UNIX_EPOCH + Duration::from_millis(1722500000123);
+        let max_age = Duration::from_secs(30);
+
+        // fresh checkpoint
+        let now = checkpoint_time + Duration::from_secs(1);
+        assert_eq!(read_sui_checkpoint(body, max_age, now).unwrap(), 48251023);
+
+        // stale checkpoint, the sequence number is still reported
+        let now = checkpoint_time + Duration::from_secs(90);
+        let e = read_sui_checkpoint(body, max_age, now).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::StaleLedger));
+
+        // no checkpoint
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":{"data":[],"nextCursor":null,"hasNextPage":false}}"#;
+        let e = read_sui_checkpoint(body, max_age, now).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InvalidJson));
+    }
+
+    #[test]
+    fn test_substrate_finalized_head() {
+        let hash = "0x8f5a1f3b3f8b6c2b2d7a0c4e0b6f1e2d3c4b5a69788796a5b4c3d2e1f0a1b2c3";
+        let request = substrate_header_request(format!(r#"{{"jsonrpc":"2.0","id":1,"result":"{hash}"}}"#).as_bytes());
+        let request: serde_json::Value = serde_json::from_slice(&request.unwrap()).unwrap();
+        assert_eq!(request["method"], "chain_getHeader");
+        assert_eq!(request["params"][0], hash);
+
+        // the hash is sent back to the node, it must be a hex hash
+        assert!(substrate_header_request(br#"{"jsonrpc":"2.0","id":1,"result":"0x12\"]}"}"#).is_err());
+        assert!(substrate_header_request(br#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000}}"#).is_err());
+
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":{"parentHash":"0x01","number":"0x4a3b2c","stateRoot":"0x02"}}"#;
+        assert_eq!(substrate_header_validator(body).unwrap(), 0x4a3b2c);
+        // an unknown block
+        assert!(substrate_header_validator(br#"{"jsonrpc":"2.0","id":1,"result":null}"#).is_err());
+        assert!(substrate_header_validator(br#"{"jsonrpc":"2.0","id":1,"result":{"number":"0xzz"}}"#).is_err());
     }
 
     #[tokio::test]
-    async fn test_https_check_get() {
+    async fn test_substrate_finalized_head_check() {
         initialize_logger();
 
-        // create a health check that connects to httpbin.org over HTTPS
-        let chain_health_check = ChainHealthCheck::new(
-            "https://httpbin.org",
-            "/get",
-            "GET",
-            Arc::new(Mutex::new(ChainState::new("test"))),
-        );
+        // both calls are stubbed, the finalized head hash is resolved to the header of its block
+        let addr = spawn_mock_jsonrpc_server(vec![
+            ("chain_getFinalizedHead", r#"{"jsonrpc":"2.0","id":1,"result":"0xabcdef"}"#),
+            ("chain_getHeader", r#"{"jsonrpc":"2.0","id":1,"result":{"number":"0x1e240"}}"#),
+        ])
+        .await;
         let backend = Backend {
-            addr: SocketAddr::Inet("23.23.165.157:443".parse().unwrap()),
+            addr: SocketAddr::Inet(addr),
             weight: 1,
         };
 
-        assert!(chain_health_check.check(&backend).await.is_ok());
+        let chain_state = Arc::new(Mutex::new(ChainState::new("bittensor")));
+        let host = format!("http://{addr}");
+        let http_check = ChainHealthCheck::new(&host, "/", "POST", chain_state.clone())
+            .with_response_body_validator(Arc::new(substrate_header_validator))
+            .with_request_body(SUBSTRATE_FINALIZED_HEAD_REQUEST.to_vec())
+            .with_follow_up_request(Arc::new(substrate_header_request));
+        assert!(http_check.check(&backend).await.is_ok());
+        assert_eq!(chain_state.lock().unwrap().get_block_numbers().get(&host), Some(&123456));
+
+        // the node doesn't know the header of its finalized head
+        let addr = spawn_mock_jsonrpc_server(vec![
+            ("chain_getFinalizedHead", r#"{"jsonrpc":"2.0","id":1,"result":"0xabcdef"}"#),
+            ("chain_getHeader", r#"{"jsonrpc":"2.0","id":1,"result":null}"#),
+        ])
+        .await;
+        let backend = Backend {
+            addr: SocketAddr::Inet(addr),
+            weight: 1,
+        };
+        let chain_state = Arc::new(Mutex::new(ChainState::new("bittensor")));
+        let host = format!("http://{addr}");
+        let http_check = ChainHealthCheck::new(&host, "/", "POST", chain_state.clone())
+            .with_response_body_validator(Arc::new(substrate_header_validator))
+            .with_request_body(SUBSTRATE_FINALIZED_HEAD_REQUEST.to_vec())
+            .with_follow_up_request(Arc::new(substrate_header_request));
+        assert!(http_check.check(&backend).await.is_err());
+        assert!(chain_state.lock().unwrap().get_block_numbers().get(&host).is_none());
+    }
+
+    // spawn a mock grpc server over plaintext http/2, answering the message to calls of the path
+    async fn spawn_mock_grpc_server(path: &'static str, message: Vec<u8>) -> std::net::SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let message = message.clone();
+                tokio::spawn(async move {
+                    let mut connection = match h2::server::handshake(stream).await {
+                        Ok(connection) => connection,
+                        Err(_) => return,
+                    };
+                    while let Some(Ok((request, mut respond))) = connection.accept().await {
+                        let is_grpc = request
+                            .headers()
+                            .get(CONTENT_TYPE)
+                            .is_some_and(|content_type| content_type == "application/grpc");
+                        // an unknown method answers the unimplemented status without a message
+                        let (grpc_status, body) = match request.uri().path() == path && is_grpc {
+                            true => ("0", protobuf::grpc_frame(&message)),
+                            false => ("12", Vec::new()),
+                        };
+
+                        let response = http::Response::builder()
+                            .status(200)
+                            .header(CONTENT_TYPE, "application/grpc")
+                            .body(())
+                            .unwrap();
+                        let mut stream = match respond.send_response(response, false) {
+                            Ok(stream) => stream,
+                            Err(_) => return,
+                        };
+                        let _ = stream.send_data(bytes::Bytes::from(body), false);
+                        let mut trailers = HeaderMap::new();
+                        trailers.insert("grpc-status", HeaderValue::from_static(grpc_status));
+                        let _ = stream.send_trailers(trailers);
+                    }
+                });
+            }
+        });
+        addr
+    }
+
+    // protobuf encoding of a varint
+    fn proto_varint(mut value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        while value >= 0x80 {
+            bytes.push((value as u8) | 0x80);
+            value >>= 7;
+        }
+        bytes.push(value as u8);
+        bytes
+    }
+
+    // protobuf encoding of a length delimited field
+    fn proto_message(number: u64, message: &[u8]) -> Vec<u8> {
+        [proto_varint((number << 3) | 2), proto_varint(message.len() as u64), message.to_vec()].concat()
+    }
+
+    // a GetLatestBlockResponse with the height in the header of the given block field
+    fn latest_block_response(block_field: u64, height: u64) -> Vec<u8> {
+        let header = [proto_message(2, b"cosmoshub-4"), proto_varint(3 << 3), proto_varint(height)].concat();
+        let block = proto_message(1, &header);
+        [proto_message(1, &proto_message(1, b"hash")), proto_message(block_field, &block)].concat()
+    }
+
+    #[test]
+    fn test_cosmos_grpc_validator() {
+        // sdk_block of recent nodes, block of older ones
+        let body = protobuf::grpc_frame(&latest_block_response(3, 21_000_000));
+        assert_eq!(cosmos_grpc_validator(&body).unwrap(), 21_000_000);
+        let body = protobuf::grpc_frame(&latest_block_response(2, 300));
+        assert_eq!(cosmos_grpc_validator(&body).unwrap(), 300);
+
+        // no block, or not a grpc frame
+        let body = protobuf::grpc_frame(&proto_message(1, &proto_message(1, b"hash")));
+        let e = cosmos_grpc_validator(&body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InvalidBlockNumber));
+        let e = cosmos_grpc_validator(br#"{"height":"300"}"#).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::FailedToValidateResponseBody));
     }
 
     #[tokio::test]
-    async fn test_https_check_post() {
+    async fn test_cosmos_grpc_check() {
         initialize_logger();
+        init_chain_checker();
+        assert!(is_grpc_chain_type("cosmos_grpc"));
+        assert!(!is_grpc_chain_type("cosmos"));
 
-        // create a health check that connects to httpbin.org over HTTPS
-        let chain_health_check = ChainHealthCheck::new(
-            "https://httpbin.org",
-            "/post",
-            "POST",
-            Arc::new(Mutex::new(ChainState::new("test"))),
-        );
-        let http_check = chain_health_check.with_request_body(
-            r#"
-               {
-                    "key":"value"
-               }
-               "#
-            .as_bytes()
-            .to_vec(),
-        );
+        let path = "/cosmos.base.tendermint.v1beta1.Service/GetLatestBlock";
+        let addr = spawn_mock_grpc_server(path, latest_block_response(3, 21_000_000)).await;
         let backend = Backend {
-            addr: SocketAddr::Inet("23.23.165.157:443".parse().unwrap()),
+            addr: SocketAddr::Inet(addr),
             weight: 1,
         };
 
-        assert!(http_check.check(&backend).await.is_ok());
+        let checker = get_chain_checker("cosmos_grpc").unwrap();
+        let chain_state = Arc::new(Mutex::new(ChainState::new("cosmos")));
+        let host = format!("http://{addr}");
+        let grpc_check = ChainHealthCheck::new(&host, path, "POST", chain_state.clone())
+            .with_grpc()
+            .with_response_body_validator(checker.validator.clone())
+            .with_request_body(checker.request_body.clone());
+        assert!(grpc_check.check(&backend).await.is_ok());
+        assert_eq!(chain_state.lock().unwrap().get_block_numbers().get(&host), Some(&21_000_000));
+
+        // a node without the tendermint service
+        let chain_state = Arc::new(Mutex::new(ChainState::new("cosmos")));
+        let status_path = "/cosmos.base.node.v1beta1.Service/Status";
+        let grpc_check = ChainHealthCheck::new(&host, status_path, "POST", chain_state.clone())
+            .with_grpc()
+            .with_response_body_validator(checker.validator)
+            .with_request_body(checker.request_body);
+        assert!(grpc_check.check(&backend).await.is_err());
+        assert!(chain_state.lock().unwrap().get_block_numbers().get(&host).is_none());
+    }
+
+    #[test]
+    fn test_ripple_server_info_validator() {
+        let body = br#"{"result":{"info":{"build_version":"2.2.3","complete_ledgers":"32570-90000120","server_state":"full","validated_ledger":{"age":2,"base_fee_xrp":1e-05,"hash":"4C0B7A2E","seq":90000120}},"status":"success"}}"#;
+        assert_eq!(ripple_server_info_validator(body).unwrap(), 90000120);
+
+        let body = br#"{"result":{"info":{"server_state":"proposing","validated_ledger":{"seq":90000121}},"status":"success"}}"#;
+        assert_eq!(ripple_server_info_validator(body).unwrap(), 90000121);
+
+        // a non-validating node still answers with its last validated ledger
+        let body = br#"{"result":{"info":{"server_state":"syncing","validated_ledger":{"seq":89999000}},"status":"success"}}"#;
+        let e = ripple_server_info_validator(body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::NodeSyncing));
+
+        let body = br#"{"result":{"info":{"server_state":"connected"},"status":"success"}}"#;
+        assert!(ripple_server_info_validator(body).is_err());
+
+        let body = br#"{"result":{"info":{"amendment_blocked":true,"server_state":"full","validated_ledger":{"seq":90000120}},"status":"success"}}"#;
+        let e = ripple_server_info_validator(body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InvalidStatus));
+    }
+
+    #[test]
+    fn test_neo_validator() {
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":5123456}"#;
+        assert_eq!(neo_validator(body).unwrap(), 5123455);
+
+        // no tip without blocks
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":0}"#;
+        let e = neo_validator(body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InvalidBlockNumber));
+
+        let body = br#"{"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"Method not found"}}"#;
+        assert!(neo_validator(body).is_err());
+    }
+
+    #[test]
+    fn test_starkex_validator() {
+        init_chain_checker();
+        let validator = get_chain_checker("starkex").unwrap().validator;
+
+        let body = b"74893";
+        assert_eq!(validator(body).unwrap(), 74893);
+
+        // a gateway error object
+        let body = br#"{"code":"StarkErrorCode.MALFORMED_REQUEST","message":"Unknown endpoint"}"#;
+        let e = validator(body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InvalidBlockNumber));
+
+        // an Immutable X style response read from its configured field
+        let validator = json_pointer_validator("/result/batch_id", ResultValueType::Decimal);
+        let body = br#"{"result":{"batch_id":"74893","timestamp":"2024-08-01T10:00:00Z"}}"#;
+        assert_eq!(validator(body).unwrap(), 74893);
+    }
+
+    #[test]
+    fn test_icp_status_validator() {
+        // cbor encoded text, shorter than 256 bytes
+        fn text(value: &str) -> Vec<u8> {
+            let mut text = match value.len() {
+                len @ 0..=23 => vec![0x60 | len as u8],
+                len => vec![0x78, len as u8],
+            };
+            text.extend_from_slice(value.as_bytes());
+            text
+        }
+        // cbor encoded map entry of a text key to a text value
+        fn entry(key: &str, value: &str) -> Vec<u8> {
+            [text(key), text(value)].concat()
+        }
+        // self-described tag, then a map of the given number of entries
+        fn status(entries: &[Vec<u8>]) -> Vec<u8> {
+            let mut status = vec![0xd9, 0xd9, 0xf7, 0xa0 | entries.len() as u8];
+            entries.iter().for_each(|e| status.extend_from_slice(e));
+            status
+        }
+
+        // the root key is a byte string
+        let root_key = [text("root_key"), vec![0x43, 0x30, 0x81, 0x82]].concat();
+
+        let body = status(&[entry("ic_api_version", "0.18.0"), root_key.clone(), entry("replica_health_status", "healthy")]);
+        assert_eq!(icp_status_validator(&body).unwrap(), SYNTHETIC_HEIGHT);
+
+        // boundary nodes don't report the replica health
+        let body = status(&[entry("ic_api_version", "0.18.0"), root_key.clone()]);
+        assert_eq!(icp_status_validator(&body).unwrap(), SYNTHETIC_HEIGHT);
+
+        let body = status(&[entry("ic_api_version", "0.18.0"), entry("replica_health_status", "waiting_for_certified_state")]);
+        let e = icp_status_validator(&body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InvalidStatus));
+
+        // not a status
+        let body = status(&[root_key]);
+        assert!(icp_status_validator(&body).is_err());
+        assert!(icp_status_validator(b"<html>502 Bad Gateway</html>").is_err());
+    }
+
+    #[test]
+    fn test_icp_read_state() {
+        let bytes = |value: &[u8]| CborValue::Bytes(value.to_vec());
+        let text = |value: &str| CborValue::Text(value.to_string());
+        fn leb128(mut value: u64) -> Vec<u8> {
+            let mut encoded = Vec::new();
+            loop {
+                let byte = (value & 0x7f) as u8;
+                value >>= 7;
+                if value == 0 {
+                    encoded.push(byte);
+                    return encoded;
+                }
+                encoded.push(byte | 0x80);
+            }
+        }
+        // a read_state response certifying the given hash tree
+        let response = |tree: CborValue| {
+            let certificate = CborValue::Map(vec![(text("tree"), tree), (text("signature"), bytes(&[0xaa; 48]))]);
+            let response = CborValue::Map(vec![(text("certificate"), CborValue::Bytes(cbor::encode(&certificate)))]);
+            cbor::encode(&CborValue::Tag(CBOR_SELF_DESCRIBED_TAG, Box::new(response)))
+        };
+        let node = |parts: Vec<CborValue>| CborValue::Array(parts);
+
+        // the time label is found through the forks, next to the pruned subtrees
+        let time_nanos = 1_722_500_000_123_456_789u64;
+        let time = node(vec![CborValue::Unsigned(3), bytes(&leb128(time_nanos))]);
+        let tree = node(vec![
+            CborValue::Unsigned(1),
+            node(vec![CborValue::Unsigned(4), bytes(&[0u8; 32])]),
+            node(vec![CborValue::Unsigned(2), bytes(b"time"), time]),
+        ]);
+        assert_eq!(icp_read_state_validator(&response(tree)).unwrap(), 1_722_500_000);
+
+        // a certificate without the time
+        let tree = node(vec![CborValue::Unsigned(2), bytes(b"subnet"), node(vec![CborValue::Unsigned(0)])]);
+        let e = icp_read_state_validator(&response(tree)).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InvalidBlockNumber));
+        // a rejection, or not cbor
+        let rejection = cbor::encode(&CborValue::Map(vec![(text("reject_code"), CborValue::Unsigned(4))]));
+        assert!(icp_read_state_validator(&rejection).is_err());
+        assert!(icp_read_state_validator(b"<html>502 Bad Gateway</html>").is_err());
+
+        assert_eq!(read_leb128(&leb128(u64::MAX)), Some(u64::MAX));
+        assert_eq!(read_leb128(&[0x80]), None);
+        assert_eq!(read_leb128(&[0x01, 0x02]), None);
+
+        // the request asks the anonymous principal for the time
+        let request = cbor::decode(&icp_read_state_request_at(time_nanos)).unwrap();
+        let content = request.get("content").unwrap();
+        assert_eq!(content.get("request_type").and_then(|t| t.as_text()), Some("read_state"));
+        assert_eq!(content.get("sender"), Some(&bytes(&[ICP_ANONYMOUS_PRINCIPAL])));
+        assert_eq!(content.get("paths"), Some(&node(vec![node(vec![bytes(b"time")])])));
+        assert_eq!(content.get("ingress_expiry"), Some(&CborValue::Unsigned(time_nanos)));
+    }
+
+    #[test]
+    fn test_fuel_validator() {
+        let body = br#"{"data":{"chain":{"latestBlock":{"header":{"height":"11962544"}}}}}"#;
+        assert_eq!(fuel_validator(body).unwrap(), 11962544);
+
+        // graphql errors are unhealthy, with or without data
+        let body = br#"{"data":null,"errors":[{"message":"Unknown field \"latestBlock\"","locations":[{"line":1,"column":17}]}]}"#;
+        let e = fuel_validator(body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::FailedToValidateResponseBody));
+
+        let body = br#"{"data":{"chain":{"latestBlock":{"header":{"height":"11962544"}}}},"errors":[{"message":"timeout"}]}"#;
+        assert!(fuel_validator(body).is_err());
+
+        // the shared height parsing accepts 0x hex
+        let body = br#"{"data":{"chain":{"latestBlock":{"header":{"height":"0x10"}}}}}"#;
+        assert_eq!(fuel_validator(body).unwrap(), 16);
+
+        let body = br#"{"data":{"chain":{"latestBlock":{"header":{"height":"abc"}}}}}"#;
+        let e = fuel_validator(body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InvalidBlockNumber));
+
+        let body = br#"{"data":{"chain":null}}"#;
+        let e = fuel_validator(body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InvalidJson));
+    }
+
+    #[test]
+    fn test_nano_validator() {
+        let body = br#"{"count":"198452637","unchecked":"12","cemented":"198452600"}"#;
+        assert_eq!(nano_validator(body).unwrap(), 198452600);
+
+        // fall back to the count
+        let body = br#"{"count":"198452637","unchecked":"12"}"#;
+        assert_eq!(nano_validator(body).unwrap(), 198452637);
+
+        let body = br#"{"count":"-1","cemented":"abc"}"#;
+        assert!(nano_validator(body).is_err());
+
+        // rpc errors are answered with an error key
+        let body = br#"{"error":"Unknown command"}"#;
+        assert!(nano_validator(body).is_err());
+    }
+
+    #[test]
+    fn test_multiversx_validator() {
+        let body = br#"{"data":{"status":{"erd_current_round":21034800,"erd_epoch_number":1456,"erd_highest_final_nonce":21034560,"erd_nonce":21034567,"erd_nonce_at_epoch_start":21020000,"erd_nonces_passed_in_current_epoch":14567,"erd_round_at_epoch_start":21020200,"erd_rounds_passed_in_current_epoch":14600,"erd_rounds_per_epoch":14400}},"error":"","code":"successful"}"#;
+        init_chain_checker();
+        let validator = get_chain_checker("multiversx").unwrap().validator;
+        assert_eq!(validator(body).unwrap(), 21034567);
+
+        // missing fields of the nested status
+        for body in [
+            r#"{"data":null,"error":"shard not found","code":"internal_issue"}"#,
+            r#"{"data":{},"code":"successful"}"#,
+            r#"{"data":{"status":{"erd_current_round":21034800}},"code":"successful"}"#,
+        ] {
+            let e = multiversx_validator(body.as_bytes()).unwrap_err();
+            assert_eq!(ProxyError::from_error(&e), Some(ProxyError::InvalidBlockNumber), "{body}");
+        }
+        assert!(multiversx_validator(b"not json").is_err());
+    }
+
+    #[test]
+    fn test_kaspa_validator() {
+        let body = br#"{"networkName":"kaspa-mainnet","blockCount":"1083567","headerCount":"1083567","tipHashes":["a1b2c3"],"difficulty":4.5e15,"pastMedianTime":"1722500000000","virtualParentHashes":["a1b2c3"],"pruningPointHash":"d4e5f6","virtualDaaScore":"87654321"}"#;
+        assert_eq!(kaspa_validator(body).unwrap(), 87654321);
+
+        // fall back to the block count
+        let body = br#"{"networkName":"kaspa-mainnet","blockCount":"1083567"}"#;
+        assert_eq!(kaspa_validator(body).unwrap(), 1083567);
+
+        let body = br#"{"networkName":"kaspa-mainnet","virtualDaaScore":"not a number"}"#;
+        assert!(kaspa_validator(body).is_err());
+
+        let body = br#"{"networkName":"kaspa-mainnet"}"#;
+        assert!(kaspa_validator(body).is_err());
+    }
+
+    #[test]
+    fn test_arweave_validator() {
+        let body = br#"{"network":"arweave.N.1","version":5,"release":69,"height":1456789,"current":"abc","blocks":1456790,"peers":64}"#;
+        assert_eq!(arweave_validator(body).unwrap(), 1456789);
+
+        let body = br#"{"network":"arweave.N.1","version":5,"release":69,"blocks":1456790}"#;
+        assert!(arweave_validator(body).is_err());
+    }
+
+    #[test]
+    fn test_bitcoin_rest_validator() {
+        let body = br#"{"chain":"main","blocks":856789,"headers":856789,"bestblockhash":"00000000000000000002a1b2","difficulty":83148355189239.77,"mediantime":1722500000,"verificationprogress":0.9999987,"initialblockdownload":false,"chainwork":"00000000000000000000000000000000000000008a1b2c3d","size_on_disk":650000000000,"pruned":false,"warnings":""}"#;
+        assert_eq!(bitcoin_rest_validator(body).unwrap(), 856789);
+
+        // still in initial block download
+        let body = br#"{"chain":"main","blocks":120000,"headers":856789,"initialblockdownload":true}"#;
+        let err = bitcoin_rest_validator(body).unwrap_err();
+        assert_eq!(ProxyError::from_error(&err), Some(ProxyError::NodeSyncing));
+
+        let body = br#"{"chain":"main","headers":856789}"#;
+        assert!(bitcoin_rest_validator(body).is_err());
+    }
+
+    #[test]
+    fn test_json_pointer_validator() {
+        // cosmos status, decimal string
+        let body = br#"{"jsonrpc":"2.0","id":-1,"result":{"node_info":{"network":"cosmoshub-4"},"sync_info":{"latest_block_height":"21034567","catching_up":false}}}"#;
+        let validator = json_pointer_validator("/result/sync_info/latest_block_height", ResultValueType::Decimal);
+        assert_eq!(validator(body).unwrap(), 21034567);
+
+        // evm jsonrpc, hex string
+        let body = br#"{"jsonrpc":"2.0","id":1,"result":"0x1406f40"}"#;
+        let validator = json_pointer_validator("/result", ResultValueType::Hex);
+        assert_eq!(validator(body).unwrap(), 21000000);
+
+        // nested number inside an array
+        let body = br#"{"data":{"blocks":[{"header":{"height":4567}}]}}"#;
+        let validator = json_pointer_validator("/data/blocks/0/header/height", ResultValueType::Number);
+        assert_eq!(validator(body).unwrap(), 4567);
+
+        // wrong type, missing path and invalid json
+        let body = br#"{"result":{"height":"4567"}}"#;
+        assert!(read_json_pointer(body, "/result/height", ResultValueType::Number).is_err());
+        assert!(read_json_pointer(body, "/result/height", ResultValueType::Hex).is_err());
+        assert!(read_json_pointer(body, "/result/missing", ResultValueType::Decimal).is_err());
+        assert!(read_json_pointer(b"not json", "/result", ResultValueType::Decimal).is_err());
     }
 
     #[tokio::test]
-    async fn test_optimism_check() {
+    async fn test_height_header_check() {
         initialize_logger();
-        log::info!("running optimism check");
-        let http_check = ChainHealthCheck::new(
-            "https://practical-green-butterfly.optimism.quiknode.pro",
-            "/d02f8d49bde8ccbbcec3c9a8962646db998ade83",
-            "POST",
-            Arc::new(Mutex::new(ChainState::new("test"))),
-        );
-        let http_check = http_check.with_response_body_validator(Arc::new(eth_validator));
-        let http_check = http_check.with_request_body(
-            r#"
-                {
-                    "jsonrpc":"2.0",
-                    "method":"eth_blockNumber",
-                    "id":1
-               }
-               "#
-            .as_bytes()
-            .to_vec(),
-        );
 
+        let addr = spawn_mock_server(mock_response("200 OK", &[("X-Blockheight", "12345")], "not json")).await;
+        let chain_state = Arc::new(Mutex::new(ChainState::new("test")));
+        let host = format!("http://{addr}");
+        let http_check = ChainHealthCheck::new(&host, "/", "GET", chain_state.clone())
+            .with_response_body_validator(Arc::new(eth_validator));
+        let http_check = http_check.with_height_header("X-Blockheight");
         let backend = Backend {
-            addr: SocketAddr::Inet("158.178.243.130:443".parse().unwrap()),
+            addr: SocketAddr::Inet(addr),
             weight: 1,
         };
 
         assert!(http_check.check(&backend).await.is_ok());
+        assert_eq!(chain_state.lock().unwrap().get_block_numbers().get(&host), Some(&12345));
+
+        // missing header is a failed check
+        let addr = spawn_mock_server(mock_response("200 OK", &[], "{}")).await;
+        let http_check = ChainHealthCheck::new(&format!("http://{addr}"), "/", "GET", chain_state.clone())
+            .with_height_header("X-Blockheight");
+        assert!(http_check.check(&backend).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_expected_status_check() {
+        initialize_logger();
+
+        let valid_body = r#"{"jsonrpc":"2.0","id":1,"result":"0x10"}"#;
+        let invalid_body = r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000,"message":"rate limited"}}"#;
+        let chain_state = Arc::new(Mutex::new(ChainState::new("test")));
+
+        // both the status and the body must pass
+        for (status, body, expected) in [
+            ("200 OK", valid_body, None),
+            ("200 OK", invalid_body, Some(ProxyError::FailedToValidateResponseBody)),
+            ("503 Service Unavailable", valid_body, Some(ProxyError::UnexpectedStatus)),
+            ("503 Service Unavailable", invalid_body, Some(ProxyError::UnexpectedStatus)),
+        ] {
+            let addr = spawn_mock_server(mock_response(status, &[], body)).await;
+            let http_check = ChainHealthCheck::new(&format!("http://{addr}"), "/", "POST", chain_state.clone())
+                .with_response_body_validator(Arc::new(eth_validator))
+                .with_expected_status(vec![200..=200]);
+            match http_check.probe().await {
+                Ok(block_number) => {
+                    assert_eq!(expected, None, "{status} {body}");
+                    assert_eq!(block_number, Some(16));
+                }
+                Err(e) => assert_eq!(ProxyError::from_error(&e), expected, "{status} {body}"),
+            }
+        }
+
+        // without expected statuses only the body is validated
+        let addr = spawn_mock_server(mock_response("503 Service Unavailable", &[], valid_body)).await;
+        let http_check = ChainHealthCheck::new(&format!("http://{addr}"), "/", "POST", chain_state.clone())
+            .with_response_body_validator(Arc::new(eth_validator));
+        assert_eq!(http_check.probe().await.unwrap(), Some(16));
     }
 }
@@ -1,13 +1,17 @@
-use std::time::Duration;
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
 use std::sync::{Arc, Mutex};
-use reqwest::{Client, Method, header::{HeaderMap, HeaderValue, CONTENT_TYPE}};
+use reqwest::{Client, header::{HeaderMap, HeaderValue, CONTENT_TYPE}, redirect::Policy};
 use async_trait::async_trait;
 use pingora_load_balancing::health_check::HealthCheck;
 use pingora_load_balancing::Backend;
-use pingora::{Custom, Error, Result};
+use pingora::Result;
+use crate::error::ProxyError;
 
 use crate::config::NodeState;
-use crate::metrics::set_node_health_gauge;
+use crate::service::chain_health_check::parse_method;
+use crate::service::interval::HealthCheckInterval;
+use crate::metrics::{observe_health_check_latency, set_node_health_gauge};
 
 pub struct CommonHealthCheck {
     consecutive_success: usize,
@@ -17,8 +21,44 @@ pub struct CommonHealthCheck {
     request_url: String,
     request_body: Option<Vec<u8>>,
     request_timeout: Duration,
+    // interval of the health checks, the request timeout is its default capped to the interval if set
+    interval: Option<HealthCheckInterval>,
     client: Arc<Client>,
     host: String,
+    // non-2xx statuses treated as healthy
+    healthy_status_codes: Vec<u16>,
+    // ranges of statuses treated as healthy, e.g. 300..=399
+    healthy_status_ranges: Vec<RangeInclusive<u16>>,
+    // statuses keeping the current health status, e.g. 429 busy but alive
+    ignore_status_codes: Vec<u16>,
+}
+
+/// How a health check response status is treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum StatusClass {
+    Healthy,
+    // the node is busy but alive, its health status is kept
+    Ignored,
+    Unhealthy,
+}
+
+/// Classify a health check response status, the configured codes take precedence over the 2xx rule
+pub(crate) fn classify_status(
+    status: u16,
+    healthy_status_codes: &[u16],
+    healthy_status_ranges: &[RangeInclusive<u16>],
+    ignore_status_codes: &[u16],
+) -> StatusClass {
+    if ignore_status_codes.contains(&status) {
+        StatusClass::Ignored
+    } else if healthy_status_codes.contains(&status)
+        || healthy_status_ranges.iter().any(|range| range.contains(&status))
+        || (200..300).contains(&status)
+    {
+        StatusClass::Healthy
+    } else {
+        StatusClass::Unhealthy
+    }
 }
 
 impl CommonHealthCheck {
@@ -33,8 +73,12 @@ impl CommonHealthCheck {
             request_url: request_url.to_string(),
             request_body: None,
             request_timeout: Duration::from_secs(60),
+            interval: None,
             client: Arc::new(Client::new()),
             host: host.to_string(),
+            healthy_status_codes: Vec::new(),
+            healthy_status_ranges: Vec::new(),
+            ignore_status_codes: Vec::new(),
         })
     }
 
@@ -43,6 +87,37 @@ impl CommonHealthCheck {
         Box::new(self)
     }
 
+    /// Set the timeout of every health check request
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Box<Self> {
+        self.request_timeout = request_timeout;
+        Box::new(self)
+    }
+
+    /// Use the default request timeout capped to the interval, following its changes, instead of a fixed one
+    pub fn with_interval(mut self, interval: HealthCheckInterval) -> Box<Self> {
+        self.interval = Some(interval);
+        Box::new(self)
+    }
+
+    /// Set the non-2xx statuses treated as healthy and the statuses keeping the current health status
+    pub fn with_status_codes(mut self, healthy_status_codes: Vec<u16>, ignore_status_codes: Vec<u16>) -> Box<Self> {
+        self.healthy_status_codes = healthy_status_codes;
+        self.ignore_status_codes = ignore_status_codes;
+        Box::new(self)
+    }
+
+    /// Set the ranges of statuses treated as healthy, redirects are not followed when they include 3xx statuses
+    pub fn with_status_ranges(mut self, healthy_status_ranges: Vec<RangeInclusive<u16>>) -> Box<Self> {
+        let accepts_redirects = healthy_status_ranges
+            .iter()
+            .any(|range| *range.start() <= 399 && *range.end() >= 300);
+        if accepts_redirects {
+            self.client = Arc::new(Client::builder().redirect(Policy::none()).build().unwrap());
+        }
+        self.healthy_status_ranges = healthy_status_ranges;
+        Box::new(self)
+    }
+
     fn update_health_status(&self, host: &str, is_healthy: bool) {
         let mut state = self.node_state.lock().unwrap();
         state.update_health_status(host, is_healthy);
@@ -50,27 +125,13 @@ impl CommonHealthCheck {
         // update metrics
         set_node_health_gauge(&*state.node_name, host, is_healthy);
     }
-}
 
-#[async_trait]
-impl HealthCheck for CommonHealthCheck {
-    async fn check(&self, _target: &Backend) -> Result<()> {
+    /// Probe the backend once, only the status code is checked,
+    /// an ignored status answers Ok(StatusClass::Ignored)
+    pub(crate) async fn probe(&self) -> Result<StatusClass> {
         let client = self.client.clone();
 
-        let method_result = Method::from_bytes(self.request_method.as_bytes());
-        let method = match method_result {
-            Ok(m) => m,
-            Err(e) => {
-                log::error!(
-                    "invalid request method: {}, error: {}",
-                    self.request_method,
-                    e
-                );
-                self.update_health_status(&self.host, false);
-
-                return Error::e_explain(Custom("invalid request method"), "reqwest error");
-            }
-        };
+        let method = parse_method(&self.request_method, self.request_body.as_deref())?;
 
         let mut headers = HeaderMap::new();
         headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
@@ -78,7 +139,7 @@ impl HealthCheck for CommonHealthCheck {
         let request_builder = client
             .request(method, &self.request_url)
             .headers(headers)
-            .timeout(self.request_timeout);
+            .timeout(self.interval.as_ref().map_or(self.request_timeout, |i| i.default_request_timeout()));
 
         let request_builder = if let Some(body) = self.request_body.as_ref() {
             request_builder.body(body.clone())
@@ -91,26 +152,54 @@ impl HealthCheck for CommonHealthCheck {
             Ok(r) => r,
             Err(_e) => {
                 log::error!("failed to send request, error: {}", _e);
-                self.update_health_status(&self.host, false);
 
-                return Error::e_explain(Custom("failed to send request"), "reqwest error");
+                return ProxyError::FailedToSendRequest.explain("reqwest error");
             }
         };
 
         // only check the status code
-        if !response.status().is_success() {
-            log::error!(
-                "request failed, status code: {}",
-                response.status().as_u16()
-            );
-            self.update_health_status(&self.host, false);
-
-            return Error::e_explain(Custom("request failed"), "reqwest error");
+        let status = response.status().as_u16();
+        match classify_status(
+            status,
+            &self.healthy_status_codes,
+            &self.healthy_status_ranges,
+            &self.ignore_status_codes,
+        ) {
+            StatusClass::Unhealthy => {
+                log::error!("request failed, status code: {}", status);
+
+                ProxyError::UpstreamError.explain("reqwest error")
+            }
+            StatusClass::Ignored => {
+                log::warn!("{} answered status code {}, health status kept", self.host, status);
+
+                Ok(StatusClass::Ignored)
+            }
+            StatusClass::Healthy => Ok(StatusClass::Healthy),
         }
+    }
+}
 
-        self.update_health_status(&self.host, true);
+#[async_trait]
+impl HealthCheck for CommonHealthCheck {
+    async fn check(&self, _target: &Backend) -> Result<()> {
+        let start = Instant::now();
+        let result = self.probe().await;
+        let node_name = self.node_state.lock().unwrap().node_name.clone();
+        observe_health_check_latency(&node_name, &self.host, start.elapsed());
 
-        Ok(())
+        match result {
+            // busy but alive, keep the current health status
+            Ok(StatusClass::Ignored) => Ok(()),
+            Ok(_) => {
+                self.update_health_status(&self.host, true);
+                Ok(())
+            }
+            Err(e) => {
+                self.update_health_status(&self.host, false);
+                Err(e)
+            }
+        }
     }
 
     fn health_threshold(&self, success: bool) -> usize {
@@ -121,3 +210,89 @@ impl HealthCheck for CommonHealthCheck {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingora::protocols::l4::socket::SocketAddr;
+    use crate::test_util::{mock_response, spawn_mock_server};
+
+    // run a check against a server answering the status, starting from a healthy node
+    async fn check_status(status: &'static str) -> (Result<()>, Option<bool>) {
+        check_status_with_ranges(status, Vec::new()).await
+    }
+
+    async fn check_status_with_ranges(
+        status: &'static str,
+        healthy_status_ranges: Vec<RangeInclusive<u16>>,
+    ) -> (Result<()>, Option<bool>) {
+        // every response redirects to the server itself
+        let addr = spawn_mock_server(mock_response(status, &[("location", "/moved")], "")).await;
+        let host = format!("http://{addr}");
+        let node_state = Arc::new(Mutex::new(NodeState::new("test")));
+        node_state.lock().unwrap().update_health_status(&host, true);
+
+        let health_check = CommonHealthCheck::new(&host, "/", "GET", node_state.clone())
+            .with_status_codes(Vec::new(), vec![429])
+            .with_status_ranges(healthy_status_ranges);
+        let backend = Backend {
+            addr: SocketAddr::Inet(addr),
+            weight: 1,
+        };
+
+        let result = health_check.check(&backend).await;
+        let health_status = node_state.lock().unwrap().health_status.get(&host).copied();
+        (result, health_status)
+    }
+
+    #[tokio::test]
+    async fn test_status_codes() {
+        let (result, health_status) = check_status("200 OK").await;
+        assert!(result.is_ok());
+        assert_eq!(health_status, Some(true));
+
+        // busy but alive, the node stays healthy
+        let (result, health_status) = check_status("429 Too Many Requests").await;
+        assert!(result.is_ok());
+        assert_eq!(health_status, Some(true));
+
+        let (result, health_status) = check_status("500 Internal Server Error").await;
+        assert!(result.is_err());
+        assert_eq!(health_status, Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_status_ranges() {
+        let permissive = || vec![200..=299, 300..=399];
+
+        let (result, health_status) = check_status_with_ranges("204 No Content", permissive()).await;
+        assert!(result.is_ok());
+        assert_eq!(health_status, Some(true));
+
+        // the redirect itself is healthy, it isn't followed
+        let (result, health_status) = check_status_with_ranges("301 Moved Permanently", permissive()).await;
+        assert!(result.is_ok());
+        assert_eq!(health_status, Some(true));
+
+        // followed without a 3xx range, the redirect loop fails
+        let (result, health_status) = check_status("301 Moved Permanently").await;
+        assert!(result.is_err());
+        assert_eq!(health_status, Some(false));
+
+        let (result, health_status) = check_status_with_ranges("500 Internal Server Error", permissive()).await;
+        assert!(result.is_err());
+        assert_eq!(health_status, Some(false));
+    }
+
+    #[test]
+    fn test_classify_status() {
+        assert_eq!(classify_status(204, &[], &[], &[]), StatusClass::Healthy);
+        assert_eq!(classify_status(429, &[], &[], &[]), StatusClass::Unhealthy);
+        assert_eq!(classify_status(429, &[], &[], &[429]), StatusClass::Ignored);
+        assert_eq!(classify_status(404, &[404], &[], &[]), StatusClass::Healthy);
+        assert_eq!(classify_status(500, &[404], &[], &[429]), StatusClass::Unhealthy);
+        assert_eq!(classify_status(301, &[], &[], &[]), StatusClass::Unhealthy);
+        assert_eq!(classify_status(301, &[], &[300..=399], &[]), StatusClass::Healthy);
+        assert_eq!(classify_status(429, &[], &[400..=499], &[429]), StatusClass::Ignored);
+    }
+}
@@ -0,0 +1,141 @@
+use async_trait::async_trait;
+use pingora::protocols::l4::socket::SocketAddr;
+use pingora::{Error, ErrorType, Result};
+use pingora_load_balancing::discovery::ServiceDiscovery;
+use pingora_load_balancing::Backend;
+use std::collections::{BTreeSet, HashMap};
+use std::net::SocketAddr as InetSocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// Resolve a "host:port" address to socket addresses
+#[async_trait]
+pub trait Resolver: Send + Sync {
+    async fn resolve(&self, addr: &str) -> std::io::Result<Vec<InetSocketAddr>>;
+}
+
+/// Resolver using the system dns configuration
+pub struct SystemResolver;
+
+#[async_trait]
+impl Resolver for SystemResolver {
+    async fn resolve(&self, addr: &str) -> std::io::Result<Vec<InetSocketAddr>> {
+        Ok(tokio::net::lookup_host(addr).await?.collect())
+    }
+}
+
+/// Dns discovery
+///
+/// Re-resolves the hostname of a node every time the cluster is updated, so a provider rotating
+/// its ips is followed without a restart. A node is a single backend: the current address is kept
+/// as long as it is still resolved, otherwise the lowest resolved address is used.
+pub struct DnsDiscovery {
+    addr: String,
+    resolver: Arc<dyn Resolver>,
+    // the address currently in use
+    current: Mutex<Option<InetSocketAddr>>,
+}
+
+impl DnsDiscovery {
+    pub fn new(addr: &str, resolver: Arc<dyn Resolver>) -> Box<Self> {
+        Box::new(DnsDiscovery {
+            addr: addr.to_string(),
+            resolver,
+            current: Mutex::new(None),
+        })
+    }
+}
+
+#[async_trait]
+impl ServiceDiscovery for DnsDiscovery {
+    async fn discover(&self) -> Result<(BTreeSet<Backend>, HashMap<u64, bool>)> {
+        let resolved = self.resolver.resolve(&self.addr).await;
+
+        let mut current = self.current.lock().unwrap();
+        let addr = match resolved {
+            Ok(addrs) => match *current {
+                Some(addr) if addrs.contains(&addr) => Some(addr),
+                _ => addrs.into_iter().min(),
+            },
+            Err(e) => {
+                // keep the last known address while dns is unavailable
+                log::error!("failed to resolve {}: {e}", self.addr);
+                *current
+            }
+        };
+
+        if addr != *current {
+            log::info!("{} resolved to {:?}, was {:?}", self.addr, addr, *current);
+            *current = addr;
+        }
+
+        let addr = match addr {
+            Some(addr) => addr,
+            None => return Error::e_explain(ErrorType::ConnectNoRoute, "no address resolved"),
+        };
+        let backend = Backend {
+            addr: SocketAddr::Inet(addr),
+            weight: 1,
+        };
+
+        Ok((BTreeSet::from([backend]), HashMap::new()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingora_load_balancing::selection::RoundRobin;
+    use pingora_load_balancing::{Backends, LoadBalancer};
+
+    // a resolver answering whatever addresses are set
+    struct StubResolver {
+        addrs: Mutex<Vec<InetSocketAddr>>,
+    }
+
+    #[async_trait]
+    impl Resolver for StubResolver {
+        async fn resolve(&self, _addr: &str) -> std::io::Result<Vec<InetSocketAddr>> {
+            let addrs = self.addrs.lock().unwrap().clone();
+            if addrs.is_empty() {
+                return Err(std::io::Error::new(std::io::ErrorKind::NotFound, "no record"));
+            }
+            Ok(addrs)
+        }
+    }
+
+    fn backend_addrs(cluster: &LoadBalancer<RoundRobin>) -> Vec<InetSocketAddr> {
+        cluster
+            .backends()
+            .get_backend()
+            .iter()
+            .filter_map(|b| b.addr.as_inet().cloned())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_dns_re_resolution() {
+        let resolver = Arc::new(StubResolver {
+            addrs: Mutex::new(vec!["10.0.0.1:443".parse().unwrap()]),
+        });
+        let discovery = DnsDiscovery::new("rpc.example.com:443", resolver.clone());
+        let cluster: LoadBalancer<RoundRobin> = LoadBalancer::from_backends(Backends::new(discovery));
+
+        cluster.update().await.unwrap();
+        assert_eq!(backend_addrs(&cluster), vec!["10.0.0.1:443".parse().unwrap()]);
+
+        // the provider rotates its ip
+        *resolver.addrs.lock().unwrap() = vec!["10.0.0.3:443".parse().unwrap(), "10.0.0.2:443".parse().unwrap()];
+        cluster.update().await.unwrap();
+        assert_eq!(backend_addrs(&cluster), vec!["10.0.0.2:443".parse().unwrap()]);
+
+        // the current address is kept while it is still resolved
+        *resolver.addrs.lock().unwrap() = vec!["10.0.0.1:443".parse().unwrap(), "10.0.0.2:443".parse().unwrap()];
+        cluster.update().await.unwrap();
+        assert_eq!(backend_addrs(&cluster), vec!["10.0.0.2:443".parse().unwrap()]);
+
+        // dns failure keeps the last known address
+        resolver.addrs.lock().unwrap().clear();
+        cluster.update().await.unwrap();
+        assert_eq!(backend_addrs(&cluster), vec!["10.0.0.2:443".parse().unwrap()]);
+    }
+}
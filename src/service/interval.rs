@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use crate::config::effective_health_check_timeout_ms;
+
+lazy_static! {
+    // health check intervals of the running chains and commons by kind and name, updated when the config is reloaded
+    static ref HEALTH_CHECK_INTERVALS: Mutex<HashMap<(IntervalKind, String), HealthCheckInterval>> =
+        Mutex::new(HashMap::new());
+}
+
+/// The shortest interval the health checks run at, a zero interval would probe without pause
+pub const MIN_HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Kind of the config section a health check interval belongs to, a chain and a common may share a name
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum IntervalKind {
+    Chain,
+    Common,
+}
+
+/// Health check interval
+///
+/// The interval shared by the health check services of a chain, a change is picked up by the running services
+/// so the clusters and the probe history of their nodes are kept.
+#[derive(Clone, Debug)]
+pub struct HealthCheckInterval {
+    sender: Arc<watch::Sender<Duration>>,
+}
+
+impl HealthCheckInterval {
+    pub fn new(interval: Duration) -> Self {
+        HealthCheckInterval { sender: Arc::new(watch::Sender::new(interval)) }
+    }
+
+    pub fn get(&self) -> Duration {
+        *self.sender.borrow()
+    }
+
+    /// The interval the health checks run at, at least [MIN_HEALTH_CHECK_INTERVAL]
+    pub fn period(&self) -> Duration {
+        self.get().max(MIN_HEALTH_CHECK_INTERVAL)
+    }
+
+    /// The timeout of the health check requests when none is configured, the default capped to the interval
+    pub fn default_request_timeout(&self) -> Duration {
+        Duration::from_millis(effective_health_check_timeout_ms(None, self.get().as_secs()))
+    }
+
+    /// Change the interval, returns whether it changed
+    pub fn set(&self, interval: Duration) -> bool {
+        self.sender.send_if_modified(|current| {
+            if *current == interval {
+                return false;
+            }
+            *current = interval;
+            true
+        })
+    }
+
+    /// Watch the changes of the interval
+    pub fn subscribe(&self) -> watch::Receiver<Duration> {
+        self.sender.subscribe()
+    }
+}
+
+/// Register the health check interval of a chain or common, replacing the previous one of the same kind and name
+pub fn register_health_check_interval(kind: IntervalKind, name: &str, interval: HealthCheckInterval) {
+    HEALTH_CHECK_INTERVALS.lock().unwrap().insert((kind, name.to_string()), interval);
+}
+
+/// Change the health check interval of a running chain or common, unknown ones are ignored
+pub fn set_health_check_interval(kind: IntervalKind, name: &str, interval: Duration) {
+    let intervals = HEALTH_CHECK_INTERVALS.lock().unwrap();
+    if let Some(current) = intervals.get(&(kind, name.to_string())) {
+        let previous = current.get();
+        if current.set(interval) {
+            log::info!("{name} health check interval changed from {previous:?} to {interval:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DEFAULT_HEALTH_CHECK_TIMEOUT_MS;
+
+    #[tokio::test]
+    async fn test_health_check_interval() {
+        let interval = HealthCheckInterval::new(Duration::from_secs(30));
+        register_health_check_interval(IntervalKind::Chain, "interval_test", interval.clone());
+        let mut changes = interval.subscribe();
+
+        // an unchanged interval doesn't notify the services
+        set_health_check_interval(IntervalKind::Chain, "interval_test", Duration::from_secs(30));
+        assert!(!changes.has_changed().unwrap());
+
+        set_health_check_interval(IntervalKind::Chain, "interval_test", Duration::from_secs(5));
+        assert!(changes.has_changed().unwrap());
+        assert_eq!(*changes.borrow_and_update(), Duration::from_secs(5));
+        assert_eq!(interval.get(), Duration::from_secs(5));
+
+        // a common of the same name has its own interval
+        let common_interval = HealthCheckInterval::new(Duration::from_secs(30));
+        register_health_check_interval(IntervalKind::Common, "interval_test", common_interval.clone());
+        set_health_check_interval(IntervalKind::Common, "interval_test", Duration::from_secs(10));
+        assert_eq!(common_interval.get(), Duration::from_secs(10));
+        assert_eq!(interval.get(), Duration::from_secs(5));
+
+        // the default request timeout follows the interval
+        assert_eq!(interval.default_request_timeout(), Duration::from_secs(5));
+        set_health_check_interval(IntervalKind::Chain, "interval_test", Duration::from_secs(600));
+        assert_eq!(interval.default_request_timeout(), Duration::from_millis(DEFAULT_HEALTH_CHECK_TIMEOUT_MS));
+
+        // a zero interval runs the health checks every second
+        set_health_check_interval(IntervalKind::Chain, "interval_test", Duration::ZERO);
+        assert_eq!(interval.period(), MIN_HEALTH_CHECK_INTERVAL);
+
+        // unknown chains are ignored
+        set_health_check_interval(IntervalKind::Chain, "unknown", Duration::from_secs(1));
+    }
+}
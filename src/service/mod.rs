@@ -1,3 +1,13 @@
 pub mod chain_health_check;
 pub mod proxy;
+pub mod admin;
 mod common_health_check;
+mod cbor;
+mod protobuf;
+mod number;
+mod discovery;
+pub mod interval;
+pub mod nodes;
+pub mod probe;
+pub mod otlp;
+pub mod reload;
@@ -0,0 +1,66 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+
+lazy_static! {
+    // node addresses of the running chains by name, updated when the config is reloaded
+    static ref CHAIN_NODES: Mutex<HashMap<String, ChainNodes>> = Mutex::new(HashMap::new());
+}
+
+/// Chain nodes
+///
+/// The node addresses of a running chain still in its config, shared by its health check and state pruning
+/// services, a node removed by a config reload is no longer probed and its state is pruned.
+#[derive(Clone, Debug, Default)]
+pub struct ChainNodes {
+    nodes: Arc<RwLock<HashSet<String>>>,
+}
+
+impl ChainNodes {
+    pub fn new(nodes: HashSet<String>) -> Self {
+        ChainNodes { nodes: Arc::new(RwLock::new(nodes)) }
+    }
+
+    pub fn contains(&self, node: &str) -> bool {
+        self.nodes.read().unwrap().contains(node)
+    }
+
+    pub fn get(&self) -> Vec<String> {
+        self.nodes.read().unwrap().iter().cloned().collect()
+    }
+
+    pub fn set(&self, nodes: HashSet<String>) {
+        *self.nodes.write().unwrap() = nodes;
+    }
+}
+
+/// Register the nodes of a chain, replacing the previous ones of the same name
+pub fn register_chain_nodes(name: &str, nodes: ChainNodes) {
+    CHAIN_NODES.lock().unwrap().insert(name.to_string(), nodes);
+}
+
+/// Change the nodes of a running chain, unknown chains are ignored
+pub fn set_chain_nodes(name: &str, nodes: HashSet<String>) {
+    if let Some(current) = CHAIN_NODES.lock().unwrap().get(name) {
+        current.set(nodes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chain_nodes() {
+        let nodes = ChainNodes::new(HashSet::from(["https://node1.com".to_string(), "https://node2.com".to_string()]));
+        register_chain_nodes("nodes_test", nodes.clone());
+        assert!(nodes.contains("https://node2.com"));
+
+        set_chain_nodes("nodes_test", HashSet::from(["https://node1.com".to_string()]));
+        assert!(nodes.contains("https://node1.com"));
+        assert!(!nodes.contains("https://node2.com"));
+        assert_eq!(nodes.get(), vec!["https://node1.com".to_string()]);
+
+        // unknown chains are ignored
+        set_chain_nodes("unknown", HashSet::new());
+    }
+}
@@ -0,0 +1,105 @@
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+
+/// Tolerant block height parser shared by the validators
+///
+/// Accepts a decimal integer, a `0x` prefixed hex integer, or a float in plain or scientific notation
+/// (`1.23e6`) as some apis serialize large numbers, truncated to an integer. Floats past 2^53 lose precision.
+/// A negative, infinite or NaN height, or one past u64, is malformed.
+pub(crate) fn parse_height(value: &str) -> Option<u64> {
+    let value = value.trim();
+    if let Some(hex) = value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        return u64::from_str_radix(hex, 16).ok();
+    }
+    if let Ok(height) = value.parse::<u64>() {
+        return Some(height);
+    }
+    float_height(value.parse::<f64>().ok()?)
+}
+
+/// The height of a json value, a number or a string as accepted by `parse_height`
+pub(crate) fn json_height(value: &Value) -> Option<u64> {
+    match value {
+        Value::Number(n) => n.as_u64().or_else(|| n.as_f64().and_then(float_height)),
+        Value::String(s) => parse_height(s),
+        _ => None,
+    }
+}
+
+/// Deserialize a height field sent as a string or a json number to its text, parsed with `parse_height`
+pub(crate) fn height_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::String(s) => Ok(s),
+        Value::Number(n) => Ok(n.to_string()),
+        other => Err(D::Error::custom(format!("invalid height: {other}"))),
+    }
+}
+
+fn float_height(value: f64) -> Option<u64> {
+    // u64::MAX rounds up to 2^64 as a float, which is out of range
+    (value.is_finite() && value >= 0.0 && value < u64::MAX as f64).then(|| value.trunc() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_height() {
+        // integers
+        assert_eq!(parse_height("1234567"), Some(1234567));
+        assert_eq!(parse_height(" 42 "), Some(42));
+        assert_eq!(parse_height("18446744073709551615"), Some(u64::MAX));
+
+        // floats are truncated
+        assert_eq!(parse_height("1230000.0"), Some(1230000));
+        assert_eq!(parse_height("99.9"), Some(99));
+
+        // scientific notation
+        assert_eq!(parse_height("1.23e6"), Some(1230000));
+        assert_eq!(parse_height("1.5E+3"), Some(1500));
+        assert_eq!(parse_height("5e0"), Some(5));
+
+        // hex
+        assert_eq!(parse_height("0x12d687"), Some(1234567));
+        assert_eq!(parse_height("0X10"), Some(16));
+
+        // malformed
+        assert_eq!(parse_height(""), None);
+        assert_eq!(parse_height("abc"), None);
+        assert_eq!(parse_height("0x"), None);
+        assert_eq!(parse_height("-1"), None);
+        assert_eq!(parse_height("-1.5e3"), None);
+        assert_eq!(parse_height("NaN"), None);
+        assert_eq!(parse_height("inf"), None);
+        assert_eq!(parse_height("1e30"), None);
+    }
+
+    #[test]
+    fn test_json_height() {
+        assert_eq!(json_height(&serde_json::json!(1234567)), Some(1234567));
+        assert_eq!(json_height(&serde_json::json!(1.23e6)), Some(1230000));
+        assert_eq!(json_height(&serde_json::json!("1.23e6")), Some(1230000));
+        assert_eq!(json_height(&serde_json::json!("0x10")), Some(16));
+        assert_eq!(json_height(&serde_json::json!(-5)), None);
+        assert_eq!(json_height(&serde_json::json!(null)), None);
+    }
+
+    #[test]
+    fn test_height_string() {
+        #[derive(Deserialize)]
+        struct Header {
+            #[serde(deserialize_with = "height_string")]
+            height: String,
+        }
+
+        let header: Header = serde_json::from_str(r#"{"height": "100"}"#).unwrap();
+        assert_eq!(parse_height(&header.height), Some(100));
+        let header: Header = serde_json::from_str(r#"{"height": 1.23e6}"#).unwrap();
+        assert_eq!(parse_height(&header.height), Some(1230000));
+        let header: Header = serde_json::from_str(r#"{"height": 100}"#).unwrap();
+        assert_eq!(header.height, "100");
+        assert!(serde_json::from_str::<Header>(r#"{"height": true}"#).is_err());
+    }
+}
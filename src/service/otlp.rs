@@ -0,0 +1,196 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use pingora::server::ShutdownWatch;
+use pingora::services::background::BackgroundService;
+use prometheus::proto::{MetricFamily, MetricType};
+use serde_json::{json, Value};
+
+use crate::config::redact_url;
+use crate::metrics;
+
+// timeout of a single push to the collector
+const OTLP_PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// aggregation temporality of the counters, they are never reset
+const AGGREGATION_TEMPORALITY_CUMULATIVE: u8 = 2;
+
+/// Otlp exporter
+///
+/// Pushes the current metric values to an OTLP/HTTP collector every interval, encoded as OTLP/JSON,
+/// in addition to the prometheus service which keeps serving the same values.
+pub struct OtlpExporter {
+    endpoint: String,
+    system: String,
+    interval: Duration,
+    client: reqwest::Client,
+    // start of the cumulative counters
+    start_time_nanos: u64,
+}
+
+impl OtlpExporter {
+    pub fn new(endpoint: &str, system: &str, interval: Duration) -> Self {
+        OtlpExporter {
+            endpoint: endpoint.to_string(),
+            system: system.to_string(),
+            interval,
+            client: reqwest::Client::builder()
+                .timeout(OTLP_PUSH_TIMEOUT)
+                .build()
+                .unwrap(),
+            start_time_nanos: unix_nanos(SystemTime::now()),
+        }
+    }
+
+    pub fn endpoint(&self) -> &str {
+        self.endpoint.as_str()
+    }
+
+    async fn push(&self) -> Result<(), reqwest::Error> {
+        let request = otlp_metrics_request(
+            &self.system,
+            &metrics::collect_metrics(),
+            self.start_time_nanos,
+            unix_nanos(SystemTime::now()),
+        );
+        self.client
+            .post(self.endpoint.as_str())
+            .json(&request)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BackgroundService for OtlpExporter {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        let mut interval = tokio::time::interval(self.interval);
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => {
+                    return;
+                }
+                _ = interval.tick() => {
+                    if let Err(e) = self.push().await {
+                        log::warn!("Failed to push metrics to {}: {e}", redact_url(&self.endpoint));
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64)
+}
+
+/// Encode the metric families as an OTLP/JSON ExportMetricsServiceRequest,
+/// gauges as gauges and counters as cumulative monotonic sums, other types are skipped
+pub fn otlp_metrics_request(
+    system: &str,
+    families: &[MetricFamily],
+    start_time_nanos: u64,
+    time_nanos: u64,
+) -> Value {
+    let metrics: Vec<Value> = families
+        .iter()
+        .filter(|family| !family.get_metric().is_empty())
+        .filter_map(|family| {
+            let data_points = |value: fn(&prometheus::proto::Metric) -> f64| -> Vec<Value> {
+                family
+                    .get_metric()
+                    .iter()
+                    .map(|metric| {
+                        let attributes: Vec<Value> = metric
+                            .get_label()
+                            .iter()
+                            .map(|label| string_attribute(label.get_name(), label.get_value()))
+                            .collect();
+                        json!({
+                            "attributes": attributes,
+                            "startTimeUnixNano": start_time_nanos.to_string(),
+                            "timeUnixNano": time_nanos.to_string(),
+                            "asDouble": value(metric),
+                        })
+                    })
+                    .collect()
+            };
+
+            let data = match family.get_field_type() {
+                MetricType::GAUGE => ("gauge", json!({
+                    "dataPoints": data_points(|metric| metric.get_gauge().get_value()),
+                })),
+                MetricType::COUNTER => ("sum", json!({
+                    "dataPoints": data_points(|metric| metric.get_counter().get_value()),
+                    "aggregationTemporality": AGGREGATION_TEMPORALITY_CUMULATIVE,
+                    "isMonotonic": true,
+                })),
+                _ => return None,
+            };
+
+            let mut metric = json!({
+                "name": family.get_name(),
+                "description": family.get_help(),
+            });
+            metric[data.0] = data.1;
+            Some(metric)
+        })
+        .collect();
+
+    json!({
+        "resourceMetrics": [{
+            "resource": {
+                "attributes": [string_attribute("service.name", system)],
+            },
+            "scopeMetrics": [{
+                "scope": { "name": env!("CARGO_PKG_NAME") },
+                "metrics": metrics,
+            }],
+        }],
+    })
+}
+
+fn string_attribute(key: &str, value: &str) -> Value {
+    json!({ "key": key, "value": { "stringValue": value } })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::Metrics;
+
+    #[test]
+    fn test_otlp_metrics_request() {
+        let metrics = Metrics::new("otlp_test");
+        metrics.set_node_height_gauge("ethereum", "node1", 42);
+        metrics.inc_proxy_result_counter("ethereum", "node1", "200", "eth_call");
+        metrics.inc_proxy_result_counter("ethereum", "node1", "200", "eth_call");
+
+        let request = otlp_metrics_request("wallet", &metrics.collect(), 1_000, 2_000);
+        let resource_metrics = &request["resourceMetrics"][0];
+        assert_eq!(resource_metrics["resource"]["attributes"][0]["key"], "service.name");
+        assert_eq!(resource_metrics["resource"]["attributes"][0]["value"]["stringValue"], "wallet");
+
+        // metrics without any series are left out
+        let otlp_metrics = resource_metrics["scopeMetrics"][0]["metrics"].as_array().unwrap();
+        assert_eq!(otlp_metrics.len(), 2);
+
+        let height = otlp_metrics.iter().find(|m| m["name"] == "otlp_test_node_height_gauge").unwrap();
+        let data_point = &height["gauge"]["dataPoints"][0];
+        assert_eq!(data_point["asDouble"], 42.0);
+        assert_eq!(data_point["timeUnixNano"], "2000");
+        let attributes = data_point["attributes"].as_array().unwrap();
+        assert!(attributes.contains(&string_attribute("chain", "ethereum")));
+        assert!(attributes.contains(&string_attribute("host", "node1")));
+
+        let results = otlp_metrics.iter().find(|m| m["name"] == "otlp_test_proxy_result_counter").unwrap();
+        assert_eq!(results["sum"]["isMonotonic"], true);
+        assert_eq!(results["sum"]["aggregationTemporality"], 2);
+        let data_point = &results["sum"]["dataPoints"][0];
+        assert_eq!(data_point["asDouble"], 2.0);
+        assert_eq!(data_point["startTimeUnixNano"], "1000");
+        assert!(data_point["attributes"].as_array().unwrap().contains(&string_attribute("method", "eth_call")));
+    }
+}
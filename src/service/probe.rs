@@ -0,0 +1,194 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::config::{redact_url, ChainState, NodeState};
+use crate::service::proxy::{build_chain_health_check, build_common_health_check, ChainProxyConfig};
+
+/// Result of probing a node once
+#[derive(Debug)]
+pub struct NodeProbe {
+    // chain or common name
+    pub group: String,
+    // node address, secrets redacted
+    pub node: String,
+    // block height, None for commons or checks without a validator
+    pub height: Option<u64>,
+    pub latency: Duration,
+    // reason of a failed probe, None if it passed
+    pub error: Option<String>,
+}
+
+impl NodeProbe {
+    pub fn passed(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Probe every node of a chain once with its chain health check
+pub async fn probe_chain_nodes(chain_name: &str, host_configs: &[ChainProxyConfig]) -> Vec<NodeProbe> {
+    // a throwaway state, the probes don't feed the proxy
+    let chain_state = Arc::new(Mutex::new(ChainState::new(chain_name)));
+
+    let mut results = Vec::new();
+    for config in host_configs.iter() {
+        let health_check = build_chain_health_check(config, chain_state.clone());
+
+        let start = Instant::now();
+        let result = health_check.probe().await;
+        let latency = start.elapsed();
+
+        let (height, error) = match result {
+            Ok(height) => (height, None),
+            Err(e) => (None, Some(e.etype().as_str().to_string())),
+        };
+        results.push(NodeProbe {
+            group: chain_name.to_string(),
+            node: redact_url(&config.proxy_uri),
+            height,
+            latency,
+            error,
+        });
+    }
+    results
+}
+
+/// Probe every node of a common once with its common health check
+pub async fn probe_common_nodes(common_name: &str, host_configs: &[ChainProxyConfig]) -> Vec<NodeProbe> {
+    let node_state = Arc::new(Mutex::new(NodeState::new(common_name)));
+
+    let mut results = Vec::new();
+    for config in host_configs.iter() {
+        let health_check = build_common_health_check(config, node_state.clone());
+
+        let start = Instant::now();
+        let result = health_check.probe().await;
+        let latency = start.elapsed();
+
+        results.push(NodeProbe {
+            group: common_name.to_string(),
+            node: redact_url(&config.proxy_uri),
+            height: None,
+            latency,
+            error: result.err().map(|e| e.etype().as_str().to_string()),
+        });
+    }
+    results
+}
+
+/// Format the probe results as a table, one node per line
+pub fn format_probe_table(results: &[NodeProbe]) -> String {
+    let header = ["GROUP", "NODE", "RESULT", "HEIGHT", "LATENCY"];
+    let rows: Vec<[String; 5]> = results
+        .iter()
+        .map(|probe| {
+            [
+                probe.group.clone(),
+                probe.node.clone(),
+                match probe.error.as_ref() {
+                    Some(error) => format!("fail: {error}"),
+                    None => "ok".to_string(),
+                },
+                probe.height.map_or("-".to_string(), |h| h.to_string()),
+                format!("{}ms", probe.latency.as_millis()),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(|h| h.len());
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let format_line = |cells: Vec<&str>| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:<1$}", cell, *width))
+            .collect();
+        line.join("  ").trim_end().to_string()
+    };
+
+    let mut table = vec![format_line(header.to_vec())];
+    for row in rows.iter() {
+        table.push(format_line(row.iter().map(|c| c.as_str()).collect()));
+    }
+    table.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::service::chain_health_check::init_chain_checker;
+    use crate::test_util::{self, mock_response, spawn_mock_server};
+
+    fn host_config(addr: std::net::SocketAddr, chain_type: &str) -> ChainProxyConfig {
+        ChainProxyConfig {
+            method: "POST".to_string(),
+            chain_type: chain_type.to_string(),
+            ..test_util::host_config(&format!("http://{addr}"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_probe_chain_nodes() {
+        init_chain_checker();
+
+        let result = r#"{"jsonrpc":"2.0","id":1,"result":"0x10"}"#;
+        let error = r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32000}}"#;
+        let healthy = spawn_mock_server(mock_response("200 OK", &[], result)).await;
+        let failing = spawn_mock_server(mock_response("200 OK", &[], error)).await;
+        let host_configs = vec![host_config(healthy, "ethereum"), host_config(failing, "ethereum")];
+
+        let results = probe_chain_nodes("ethereum", &host_configs).await;
+        assert_eq!(results.len(), 2);
+
+        assert!(results[0].passed());
+        assert_eq!(results[0].group, "ethereum");
+        assert_eq!(results[0].node, format!("http://{healthy}"));
+        assert_eq!(results[0].height, Some(16));
+
+        assert!(!results[1].passed());
+        assert_eq!(results[1].height, None);
+        assert_eq!(results[1].error.as_deref(), Some("failed to validate response body"));
+    }
+
+    #[tokio::test]
+    async fn test_probe_common_nodes() {
+        let healthy = spawn_mock_server(mock_response("200 OK", &[], "ok")).await;
+        let failing = spawn_mock_server(mock_response("503 Service Unavailable", &[], "")).await;
+        let host_configs = vec![host_config(healthy, ""), host_config(failing, "")];
+
+        let results = probe_common_nodes("common", &host_configs).await;
+        assert!(results[0].passed());
+        assert_eq!(results[1].error.as_deref(), Some("request failed"));
+    }
+
+    #[test]
+    fn test_format_probe_table() {
+        let results = vec![
+            NodeProbe {
+                group: "ethereum".to_string(),
+                node: "https://a.example.com".to_string(),
+                height: Some(123),
+                latency: Duration::from_millis(45),
+                error: None,
+            },
+            NodeProbe {
+                group: "ethereum".to_string(),
+                node: "https://b.example.com".to_string(),
+                height: None,
+                latency: Duration::from_millis(1200),
+                error: Some("failed to send request".to_string()),
+            },
+        ];
+
+        let table = format_probe_table(&results);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "GROUP     NODE                   RESULT                        HEIGHT  LATENCY");
+        assert_eq!(lines[1], "ethereum  https://a.example.com  ok                            123     45ms");
+        assert_eq!(lines[2], "ethereum  https://b.example.com  fail: failed to send request  -       1200ms");
+    }
+}
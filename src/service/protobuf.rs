@@ -0,0 +1,134 @@
+/// Minimal protobuf reader
+///
+/// Walks the fields of an encoded message without its schema, enough to read a few scalars of grpc
+/// health check responses, e.g. the block height of a cosmos `GetLatestBlock` response. Groups are rejected.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ProtoValue<'a> {
+    Varint(u64),
+    Fixed64(u64),
+    // strings, bytes and embedded messages
+    LengthDelimited(&'a [u8]),
+    Fixed32(u32),
+}
+
+/// The fields of a message in the encoded order, None if it is malformed or unsupported
+pub(crate) fn decode_fields(input: &[u8]) -> Option<Vec<(u32, ProtoValue<'_>)>> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while pos < input.len() {
+        let key = read_varint(input, &mut pos)?;
+        let number = u32::try_from(key >> 3).ok().filter(|number| *number > 0)?;
+        let value = match key & 0x07 {
+            0 => ProtoValue::Varint(read_varint(input, &mut pos)?),
+            1 => ProtoValue::Fixed64(u64::from_le_bytes(read_bytes(input, &mut pos, 8)?.try_into().ok()?)),
+            2 => {
+                let len = read_varint(input, &mut pos)?;
+                ProtoValue::LengthDelimited(read_bytes(input, &mut pos, len)?)
+            }
+            5 => ProtoValue::Fixed32(u32::from_le_bytes(read_bytes(input, &mut pos, 4)?.try_into().ok()?)),
+            // start and end group, or reserved
+            _ => return None,
+        };
+        fields.push((number, value));
+    }
+    Some(fields)
+}
+
+/// The last value of a field, as a scalar field is overwritten by a later occurrence
+pub(crate) fn field(input: &[u8], number: u32) -> Option<ProtoValue<'_>> {
+    decode_fields(input)?
+        .into_iter()
+        .rev()
+        .find(|(n, _)| *n == number)
+        .map(|(_, value)| value)
+}
+
+/// The embedded message found by following the field numbers of the path
+pub(crate) fn message_at<'a>(input: &'a [u8], path: &[u32]) -> Option<&'a [u8]> {
+    path.iter().try_fold(input, |message, number| match field(message, *number)? {
+        ProtoValue::LengthDelimited(embedded) => Some(embedded),
+        _ => None,
+    })
+}
+
+/// Frame a message as the body of a unary grpc request, uncompressed
+pub(crate) fn grpc_frame(message: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(message.len() + 5);
+    frame.push(0);
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(message);
+    frame
+}
+
+/// The message of the first frame of a grpc response body, None if it is truncated or compressed
+pub(crate) fn grpc_message(body: &[u8]) -> Option<&[u8]> {
+    let (compressed, rest) = body.split_first()?;
+    if *compressed != 0 {
+        return None;
+    }
+    let len = u32::from_be_bytes(rest.get(..4)?.try_into().ok()?);
+    rest.get(4..4 + len as usize)
+}
+
+fn read_varint(input: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *input.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    // longer than 10 bytes
+    None
+}
+
+fn read_bytes<'a>(input: &'a [u8], pos: &mut usize, len: u64) -> Option<&'a [u8]> {
+    let end = pos.checked_add(usize::try_from(len).ok()?)?;
+    let bytes = input.get(*pos..end)?;
+    *pos = end;
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_fields() {
+        // {1: 150, 2: "hi", 3: {1: 1}, 4: fixed32 1, 1: 300}
+        let input = [
+            0x08, 0x96, 0x01, 0x12, 0x02, b'h', b'i', 0x1a, 0x02, 0x08, 0x01, 0x25, 0x01, 0x00, 0x00, 0x00, 0x08,
+            0xac, 0x02,
+        ];
+        let fields = decode_fields(&input).unwrap();
+        assert_eq!(fields.len(), 5);
+        assert_eq!(fields[1], (2, ProtoValue::LengthDelimited(b"hi")));
+        assert_eq!(fields[3], (4, ProtoValue::Fixed32(1)));
+
+        // the last occurrence of a scalar wins
+        assert_eq!(field(&input, 1), Some(ProtoValue::Varint(300)));
+        assert_eq!(field(&input, 5), None);
+        assert_eq!(message_at(&input, &[3]), Some(&[0x08, 0x01][..]));
+        assert_eq!(message_at(&input, &[1]), None);
+
+        // truncated length, group and field number 0
+        assert!(decode_fields(&[0x12, 0x05, b'h']).is_none());
+        assert!(decode_fields(&[0x0b]).is_none());
+        assert!(decode_fields(&[0x00, 0x01]).is_none());
+    }
+
+    #[test]
+    fn test_grpc_frame() {
+        let frame = grpc_frame(&[0x08, 0x01]);
+        assert_eq!(frame, vec![0, 0, 0, 0, 2, 0x08, 0x01]);
+        assert_eq!(grpc_message(&frame), Some(&[0x08, 0x01][..]));
+        assert_eq!(grpc_frame(&[]), vec![0, 0, 0, 0, 0]);
+
+        // truncated and compressed frames
+        assert!(grpc_message(&frame[..6]).is_none());
+        assert!(grpc_message(&[1, 0, 0, 0, 0]).is_none());
+        assert!(grpc_message(&[]).is_none());
+    }
+}
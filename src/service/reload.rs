@@ -0,0 +1,184 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use pingora::server::ShutdownWatch;
+use pingora::services::background::BackgroundService;
+use tokio::signal::unix::{signal, SignalKind};
+
+use url::Url;
+
+use crate::app::maintenance::set_maintenance;
+use crate::config::{effective_node_address, redact_url, Chain, Config};
+use crate::metrics::set_removed_hosts;
+use crate::service::interval::{set_health_check_interval, IntervalKind};
+use crate::service::nodes::set_chain_nodes;
+
+/// Config reloader
+///
+/// Reloads the config file on SIGHUP and applies the settings which don't need a restart, the maintenance
+/// and the health check interval of the chains, and expires the metrics of the nodes removed from the chains.
+/// An invalid config file is logged and the loaded settings are kept.
+pub struct ConfigReloader {
+    path: PathBuf,
+}
+
+impl ConfigReloader {
+    pub fn new(path: PathBuf) -> Self {
+        ConfigReloader { path }
+    }
+
+    fn reload(&self) {
+        if let Err(e) = Config::reload_config(&self.path) {
+            log::error!("Failed to reload config {}: {e}", self.path.display());
+            return;
+        }
+        let config = crate::CONFIG.read().unwrap();
+        apply_maintenance(&config);
+        apply_health_check_intervals(&config);
+        apply_chain_nodes(&config);
+        apply_removed_nodes(&config);
+        log::info!("Config {} reloaded", self.path.display());
+    }
+}
+
+/// Apply the maintenance of every chain of the config to its proxy
+pub fn apply_maintenance(config: &Config) {
+    for chain in config.chains.iter() {
+        set_maintenance(chain.name(), chain.maintenance());
+    }
+}
+
+/// Apply the health check interval of every chain and common to its running health checks,
+/// the clusters and the probe history of the nodes are kept
+pub fn apply_health_check_intervals(config: &Config) {
+    for chain in config.chains.iter() {
+        set_health_check_interval(IntervalKind::Chain, chain.name(), Duration::from_secs(chain.interval()));
+    }
+    for common in config.commons.iter() {
+        set_health_check_interval(IntervalKind::Common, common.name(), Duration::from_secs(common.interval()));
+    }
+}
+
+/// Apply the nodes of every chain still in the config to its running services, the removed nodes' state is
+/// pruned, the reload doesn't add nodes
+pub fn apply_chain_nodes(config: &Config) {
+    for chain in config.chains.iter() {
+        let removed = chain.removed_node_addresses();
+        let nodes = chain
+            .nodes()
+            .iter()
+            .map(|node| node.address())
+            .filter(|address| !removed.iter().any(|removed| removed == address))
+            .map(|address| effective_node_address(address, chain.chain_type()))
+            .collect();
+        set_chain_nodes(chain.name(), nodes);
+    }
+}
+
+/// Delete the metric series of the nodes removed from every chain, a node added back is exported again
+pub fn apply_removed_nodes(config: &Config) {
+    for chain in config.chains.iter() {
+        for address in chain.removed_node_addresses() {
+            let node_url = effective_node_address(address, chain.chain_type());
+            log::info!("Node {} removed from chain {}, its metrics are expired", redact_url(&node_url), chain.name());
+        }
+        set_removed_hosts(chain.name(), removed_hosts(chain));
+    }
+}
+
+/// The metric hosts of the nodes removed from a chain, their urls, and their hostnames as the request metrics
+/// when no remaining node of the chain shares them, e.g. another api key of the same provider
+fn removed_hosts(chain: &Chain) -> HashSet<String> {
+    let removed = chain.removed_node_addresses();
+    let remaining_hostnames: HashSet<String> = chain
+        .nodes()
+        .iter()
+        .map(|node| node.address())
+        .filter(|address| !removed.iter().any(|removed| removed == address))
+        .filter_map(|address| node_hostname(&effective_node_address(address, chain.chain_type())))
+        .collect();
+
+    let mut hosts = HashSet::new();
+    for address in removed {
+        let node_url = effective_node_address(address, chain.chain_type());
+        if let Some(hostname) = node_hostname(&node_url).filter(|h| !remaining_hostnames.contains(h)) {
+            hosts.insert(hostname);
+        }
+        hosts.insert(node_url);
+    }
+    hosts
+}
+
+fn node_hostname(node_url: &str) -> Option<String> {
+    Url::parse(node_url).ok().and_then(|url| url.host_str().map(|h| h.to_string()))
+}
+
+#[async_trait]
+impl BackgroundService for ConfigReloader {
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                log::error!("Failed to listen for SIGHUP, the config won't be reloaded: {e}");
+                return;
+            }
+        };
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => {
+                    return;
+                }
+                _ = hangup.recv() => {
+                    self.reload();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_removed_hosts() {
+        let yaml_content = |nodes: &[&str]| {
+            let nodes: String =
+                nodes.iter().map(|node| format!("      - Address: {node}\n        Priority: 1\n")).collect();
+            format!(r#"
+Chains:
+  - Name: ethereum
+    Protocol: "jsonrpc"
+    Listen: 1090
+    Interval: 20
+    BlockGap: 20
+    ChainType: "ethereum"
+    Nodes:
+{nodes}    HealthCheck:
+      Path: ""
+      Method: POST
+Monitor:
+    Listen: 1018
+    System: "test"
+"#)
+        };
+        let key1 = "https://eth-mainnet.g.alchemy.com/v2/key1";
+        let key2 = "https://eth-mainnet.g.alchemy.com/v2/key2";
+        let infura = "https://mainnet.infura.io/v3/key3";
+        let mut config: Config = serde_yaml::from_str(&yaml_content(&[key1, key2, infura])).unwrap();
+
+        // the hostname is kept while another key of the provider remains
+        config.apply_reload(serde_yaml::from_str(&yaml_content(&[key2, infura])).unwrap());
+        assert_eq!(removed_hosts(&config.chains[0]), HashSet::from([key1.to_string()]));
+
+        // the hostname is removed with the last node using it
+        config.apply_reload(serde_yaml::from_str(&yaml_content(&[key1, key2])).unwrap());
+        let expected = HashSet::from([infura.to_string(), "mainnet.infura.io".to_string()]);
+        assert_eq!(removed_hosts(&config.chains[0]), expected);
+
+        config.apply_reload(serde_yaml::from_str(&yaml_content(&[key1, key2, infura])).unwrap());
+        assert!(removed_hosts(&config.chains[0]).is_empty());
+    }
+}
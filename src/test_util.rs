@@ -0,0 +1,81 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::service::proxy::ChainProxyConfig;
+
+/// A node config of the uri at priority 1, the tests override the fields they need
+pub fn host_config(uri: &str) -> ChainProxyConfig {
+    ChainProxyConfig {
+        proxy_uri: uri.to_string(),
+        priority: 1,
+        ..Default::default()
+    }
+}
+
+/// Spawn a mock http server answering every request with the given raw response
+pub async fn spawn_mock_server(response: String) -> SocketAddr {
+    spawn_mock_handler(move |_| response.clone()).await
+}
+
+/// Spawn a mock http server answering every request with the raw response built from the whole raw request
+pub async fn spawn_mock_handler<F>(respond: F) -> SocketAddr
+where
+    F: Fn(&str) -> String + Send + Sync + 'static,
+{
+    let respond = Arc::new(respond);
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let respond = respond.clone();
+            tokio::spawn(async move {
+                // read the whole request, the body may come after the headers
+                let mut request = Vec::new();
+                let mut buf = vec![0u8; 4096];
+                while let Ok(n) = stream.read(&mut buf).await {
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                    if mock_request_complete(&request) {
+                        break;
+                    }
+                }
+                let response = respond(&String::from_utf8_lossy(&request));
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.shutdown().await;
+            });
+        }
+    });
+    addr
+}
+
+/// Whether the raw http request has its headers and its content-length of body
+fn mock_request_complete(request: &[u8]) -> bool {
+    let request = String::from_utf8_lossy(request);
+    let (headers, body) = match request.split_once("\r\n\r\n") {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let content_length = headers
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    body.len() >= content_length
+}
+
+/// Build a raw http response with the given extra headers and body, closing the connection
+pub fn mock_response(status: &str, headers: &[(&str, &str)], body: &str) -> String {
+    let mut response = format!("HTTP/1.1 {status}\r\ncontent-length: {}\r\nconnection: close\r\n", body.len());
+    for (name, value) in headers {
+        response.push_str(&format!("{name}: {value}\r\n"));
+    }
+    response.push_str("\r\n");
+    response.push_str(body);
+    response
+}